@@ -0,0 +1,64 @@
+// Rendering throughput for large images and big grids. Run with `cargo bench`.
+#![allow(dead_code)]
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use image::{DynamicImage, Rgba, RgbaImage};
+
+#[path = "../src/board.rs"]
+mod board;
+#[path = "../src/encoding.rs"]
+mod encoding;
+#[path = "../src/image_processing.rs"]
+mod image_processing;
+#[path = "../src/theme.rs"]
+mod theme;
+
+use board::{shuffled_positions, BoardOptions};
+use encoding::{EncoderSettings, OutputFormat};
+use image_processing::{LabelStyle, Renderer};
+use theme::Theme;
+
+// Busy gradient so encoders cannot compress it away to nothing
+fn test_image(width: u32, height: u32) -> RgbaImage {
+    RgbaImage::from_fn(width, height, |x, y| {
+        Rgba([(x % 256) as u8, (y % 256) as u8, ((x ^ y) % 256) as u8, 255])
+    })
+}
+
+fn render_board(c: &mut Criterion) {
+    let renderer = Renderer::with_default_font();
+    let theme = Theme::builtin("classic", &LabelStyle::default()).unwrap();
+    let mut group = c.benchmark_group("render_board");
+    group.sample_size(20);
+
+    for &(width, height) in &[(1600, 1200), (4000, 3000)] {
+        let image = test_image(width, height);
+        for grid_size in [3u32, 5, 8] {
+            let positions = shuffled_positions((grid_size * grid_size) as usize);
+            let options = BoardOptions::default();
+            group.throughput(Throughput::Elements(width as u64 * height as u64));
+            group.bench_with_input(
+                BenchmarkId::new(format!("{}x{}", width, height), format!("{0}x{0}", grid_size)),
+                &positions,
+                |b, positions| b.iter(|| renderer.render_board(&image, grid_size, positions, &options, &[0, 1], &theme)),
+            );
+        }
+    }
+    group.finish();
+}
+
+fn encode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encode");
+    group.sample_size(10);
+
+    let image = DynamicImage::ImageRgba8(test_image(4000, 3000));
+    group.throughput(Throughput::Elements(4000 * 3000));
+    for format in [OutputFormat::Png, OutputFormat::Jpeg, OutputFormat::WebP] {
+        let settings = EncoderSettings { format, max_bytes: usize::MAX, ..EncoderSettings::default() };
+        group.bench_function(format.extension(), |b| b.iter(|| encoding::encode(&image, &settings)));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, render_board, encode);
+criterion_main!(benches);
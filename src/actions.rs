@@ -0,0 +1,601 @@
+use serenity::model::application::interaction::message_component::MessageComponentInteraction;
+use serenity::model::application::interaction::InteractionResponseType;
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::board::{self, BoardDisplay, BoardOptions};
+use crate::config::{GuildConfig, RevealPolicy};
+use crate::coop::{self, TurnOrder};
+use crate::crowd::{self, VoteRound};
+use crate::economy;
+use crate::encoding::EncoderSettings;
+use crate::image_processing::Renderer;
+use crate::ratings;
+use crate::render::encode_blocking;
+use crate::theme::GuildTheme;
+use crate::{
+    add_score, add_shared_score, game_context, guild_config, retire_board, reveal_caption, send_board, update_board, AccessibilityKey,
+    BoxError, ConfigKey, EconomyKey, LabelStyleKey, ManifestKey, PicturePuzzleGame, RatingsKey, RevealDelayKey, ScoresKey, StatsKey,
+};
+
+// Puzzle actions shared by prefix commands, slash commands and buttons. Anything everyone
+// should see (boards, announcements) is posted to the channel by the action itself; the
+// returned text is feedback for whoever invoked it.
+pub type ActionResult = Result<Option<String>, BoxError>;
+
+pub const NO_PUZZLE: &str = "There is no puzzle in progress.";
+
+// Answer a button or menu press with a message only whoever pressed it can see
+pub async fn respond_ephemeral(ctx: &Context, component: &MessageComponentInteraction, text: &str) {
+    if let Err(e) = component
+        .create_interaction_response(&ctx.http, |response| {
+            response
+                .kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|message| message.content(text).ephemeral(true))
+        })
+        .await
+    {
+        eprintln!("Failed to respond to interaction: {:?}", e);
+    }
+}
+
+// Most swaps accepted in one multi-swap
+const MAX_SWAPS: usize = 10;
+
+// Who asked for an action, and where
+pub struct Invoker {
+    pub channel_id: ChannelId,
+    pub guild_id: Option<GuildId>,
+    pub user_id: UserId,
+}
+
+impl Invoker {
+    pub fn from_message(msg: &Message) -> Self {
+        Invoker {
+            channel_id: msg.channel_id,
+            guild_id: msg.guild_id,
+            user_id: msg.author.id,
+        }
+    }
+}
+
+// How the players in a channel share a puzzle
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PlayMode {
+    Classic,
+    Coop,
+    ChannelPlays,
+}
+
+// Start a new puzzle, optionally restricted to a category or tag
+pub async fn start(ctx: &Context, invoker: &Invoker, filter: Option<&str>) -> ActionResult {
+    start_game(ctx, invoker, filter, PlayMode::Classic).await
+}
+
+// Start a co-op puzzle, where players join a turn order and only the current player may swap
+pub async fn start_coop(ctx: &Context, invoker: &Invoker, filter: Option<&str>) -> ActionResult {
+    start_game(ctx, invoker, filter, PlayMode::Coop).await
+}
+
+// Start a channel plays puzzle, where everyone votes for each swap and the most popular one is made
+pub async fn start_channel_plays(ctx: &Context, invoker: &Invoker, filter: Option<&str>) -> ActionResult {
+    start_game(ctx, invoker, filter, PlayMode::ChannelPlays).await
+}
+
+async fn start_game(ctx: &Context, invoker: &Invoker, filter: Option<&str>, mode: PlayMode) -> ActionResult {
+    // Only offer puzzles marked NSFW-safe outside of age-restricted channels, and
+    // fall back to a text board where the bot may not attach files
+    let (nsfw_channel, can_attach) = match invoker.channel_id.to_channel(ctx).await? {
+        Channel::Guild(channel) => {
+            let can_attach = channel
+                .permissions_for_user(&ctx.cache, ctx.cache.current_user_id())
+                .map_or(true, |permissions| permissions.attach_files());
+            (channel.nsfw, can_attach)
+        }
+        _ => (false, true),
+    };
+
+    let (manifest, config, mut board_options, theme) = {
+        let data = ctx.data.read().await;
+        let config = data.get::<ConfigKey>().unwrap().get(invoker.guild_id);
+        let board_options = player_board_options(&data, &config, invoker.user_id);
+        // Players who bought a theme see their own puzzles in it, keeping the server's watermark
+        let owned_theme = invoker
+            .guild_id
+            .and_then(|guild_id| data.get::<EconomyKey>().unwrap().theme(guild_id, invoker.user_id));
+        let theme = match owned_theme {
+            Some(name) => GuildTheme {
+                theme: name.to_string(),
+                watermark: config.theme.watermark.clone(),
+                ..GuildTheme::default()
+            },
+            None => config.theme.clone(),
+        }
+        .resolve(data.get::<LabelStyleKey>().unwrap());
+        (data.get::<ManifestKey>().unwrap().clone(), config, board_options, theme)
+    };
+    if !config.allows_channel(invoker.channel_id) {
+        return Ok(Some("Puzzles can't be played in this channel.".to_string()));
+    }
+    if !can_attach && board_options.display == BoardDisplay::Image {
+        board_options.display = BoardDisplay::Text;
+    }
+
+    // Solo players with a rating get puzzles close to their skill
+    let picked = {
+        let data = ctx.data.read().await;
+        let ratings = data.get::<RatingsKey>().unwrap();
+        let player = ratings.player(invoker.user_id);
+        if mode != PlayMode::Classic || player.games == 0 {
+            manifest.pick(nsfw_channel, filter)
+        } else {
+            manifest.pick_near(nsfw_channel, filter, |puzzle| {
+                let key = ratings::puzzle_key(puzzle, config.grid_size.unwrap_or(puzzle.grid_size));
+                (ratings.puzzle(&key).value - player.value).abs()
+            })
+        }
+    };
+    let puzzle = match picked {
+        Some(puzzle) => puzzle,
+        None => return Ok(Some("There are no puzzles available for this channel.".to_string())),
+    };
+    let grid_size = config.grid_size.unwrap_or(puzzle.grid_size);
+
+    let (game, renderer, encoder) = game_context(ctx, invoker.channel_id).await;
+    let mut game = game.lock().await;
+    // A new puzzle replaces any game in progress in this channel
+    retire_board(ctx, &mut game).await;
+    if let Err(e) = game.load_puzzle(puzzle, grid_size, invoker.user_id, board_options, theme).await {
+        eprintln!("Failed to load puzzle \"{}\": {:?}", puzzle.title, e);
+        return Ok(Some("Failed to load the puzzle image. Please try again.".to_string()));
+    }
+
+    let text = match mode {
+        PlayMode::Classic => "Puzzle Mastermind! Solve the puzzle by swapping tiles or submitting guesses.",
+        PlayMode::Coop => {
+            game.turns = Some(TurnOrder::new(invoker.user_id, Duration::from_secs(config.turn_timeout)));
+            "Co-op puzzle! Press Join to take turns swapping tiles. Points are shared by how many tiles each player puts in place."
+        }
+        PlayMode::ChannelPlays => {
+            let text = "Channel plays! Pick two tiles to vote for a swap. When voting closes, the most popular swap is made. \
+                        Points are shared by how often each player voted for the swaps that were made.";
+            game.votes = Some(VoteRound::new(invoker.guild_id, Duration::from_secs(config.vote_window), text));
+            text
+        }
+    };
+
+    let board_renderer = game.board_renderer(&renderer, encoder);
+    let board = match game.recreate_scrambled_image(board_renderer).await {
+        Some(board) => board?,
+        None => return Ok(None),
+    };
+
+    // Send the scrambled board and buttons
+    send_board(ctx, invoker.channel_id, &mut game, board, text).await?;
+
+    Ok(None)
+}
+
+// A player's board settings. Their own `!accessibility` choices win over the server's default mode.
+pub fn player_board_options(data: &TypeMap, config: &GuildConfig, user_id: UserId) -> BoardOptions {
+    data.get::<AccessibilityKey>()
+        .and_then(|prefs| prefs.get(&user_id).copied())
+        .unwrap_or_else(|| BoardOptions {
+            display: config.mode.unwrap_or(BoardDisplay::Image),
+            ..BoardOptions::default()
+        })
+}
+
+// Swap two tiles, given as numbers or coordinates, and show the updated board
+pub async fn swap(ctx: &Context, invoker: &Invoker, tile1: &str, tile2: &str) -> ActionResult {
+    let (game, renderer, encoder) = game_context(ctx, invoker.channel_id).await;
+    let mut game = game.lock().await;
+    if game.puzzle.is_none() {
+        return Ok(Some(NO_PUZZLE.to_string()));
+    }
+
+    // Ensure the tiles are on the board
+    let slots = match (game.parse_tile(tile1), game.parse_tile(tile2)) {
+        (Some(index1), Some(index2)) => (index1, index2),
+        _ => return Ok(Some(format!("Invalid tiles! Please use tiles from {}.", game.tile_range()))),
+    };
+
+    apply_swaps(ctx, invoker, &mut game, &renderer, encoder, &[slots]).await
+}
+
+// Swap two tiles by slot index, as picked with the tile buttons
+pub async fn swap_slots(ctx: &Context, invoker: &Invoker, slots: (usize, usize)) -> ActionResult {
+    let (game, renderer, encoder) = game_context(ctx, invoker.channel_id).await;
+    let mut game = game.lock().await;
+    if game.puzzle.is_none() {
+        return Ok(Some(NO_PUZZLE.to_string()));
+    }
+    apply_swaps(ctx, invoker, &mut game, &renderer, encoder, &[slots]).await
+}
+
+// Vote for a swap in a channel plays puzzle
+pub async fn vote(ctx: &Context, invoker: &Invoker, tile1: &str, tile2: &str) -> ActionResult {
+    let (game, renderer, encoder) = game_context(ctx, invoker.channel_id).await;
+    let mut game = game.lock().await;
+    if game.puzzle.is_none() {
+        return Ok(Some(NO_PUZZLE.to_string()));
+    }
+    if game.votes.is_none() {
+        return Ok(Some("This puzzle isn't a channel plays game, so swap the tiles yourself.".to_string()));
+    }
+
+    let slots = match (game.parse_tile(tile1), game.parse_tile(tile2)) {
+        (Some(index1), Some(index2)) => (index1, index2),
+        _ => return Ok(Some(format!("Invalid tiles! Please use tiles from {}.", game.tile_range()))),
+    };
+    apply_swaps(ctx, invoker, &mut game, &renderer, encoder, &[slots]).await
+}
+
+// Make several swaps in one move, e.g. "1-5 2-7", showing the board once at the end.
+// Nothing is swapped unless every entry is valid.
+pub async fn swap_many(ctx: &Context, invoker: &Invoker, input: &str) -> ActionResult {
+    let (game, renderer, encoder) = game_context(ctx, invoker.channel_id).await;
+    let mut game = game.lock().await;
+    if game.puzzle.is_none() {
+        return Ok(Some(NO_PUZZLE.to_string()));
+    }
+
+    let swaps = match board::parse_swaps(input, game.grid_size, game.board_options.notation) {
+        Ok(swaps) if swaps.len() > MAX_SWAPS => {
+            return Ok(Some(format!("At most {} swaps can be made at once.", MAX_SWAPS)))
+        }
+        Ok(swaps) => swaps,
+        Err(errors) => return Ok(Some(errors)),
+    };
+    apply_swaps(ctx, invoker, &mut game, &renderer, encoder, &swaps).await
+}
+
+// Swap each pair in turn, stopping early if the puzzle is solved part way through. In channel
+// plays puzzles the swap is a vote instead.
+async fn apply_swaps(
+    ctx: &Context,
+    invoker: &Invoker,
+    game: &mut PicturePuzzleGame,
+    renderer: &Arc<Renderer>,
+    encoder: EncoderSettings,
+    swaps: &[(usize, usize)],
+) -> ActionResult {
+    if let Some(turns) = &game.turns {
+        if let Some(refusal) = turns.refusal(invoker.user_id) {
+            return Ok(Some(refusal));
+        }
+        if swaps.len() > 1 {
+            return Ok(Some("In co-op puzzles each turn is a single swap.".to_string()));
+        }
+    }
+    if let Some(votes) = game.votes.as_mut() {
+        let &[(first, second)] = swaps else {
+            return Ok(Some("In channel plays puzzles each vote is for a single swap.".to_string()));
+        };
+        if first == second {
+            return Ok(Some("Vote for a swap of two different tiles.".to_string()));
+        }
+        crowd::cast(ctx, invoker.channel_id, votes, invoker.user_id, (first, second));
+        game.selected_tiles.remove(&invoker.user_id);
+        game.participants.insert(invoker.user_id);
+        let (grid_size, notation) = (game.grid_size, game.board_options.notation);
+        return Ok(Some(format!(
+            "Your vote to swap {} and {} is in. You can change it until voting closes.",
+            board::tile_label(first, grid_size, notation),
+            board::tile_label(second, grid_size, notation)
+        )));
+    }
+
+    let (grid_size, notation) = (game.grid_size, game.board_options.notation);
+    let placed_before = board::correct_slots(&game.tile_positions).len();
+    let mut made = Vec::new();
+    game.last_swap.clear();
+    for &(index1, index2) in swaps {
+        game.swap(index1, index2);
+        made.push(format!(
+            "{} and {}",
+            board::tile_label(index1, grid_size, notation),
+            board::tile_label(index2, grid_size, notation)
+        ));
+        if game.tile_positions == game.correct_positions {
+            break;
+        }
+    }
+    game.participants.insert(invoker.user_id);
+    let placed = board::correct_slots(&game.tile_positions).len().saturating_sub(placed_before);
+    if let Some(turns) = game.turns.as_mut() {
+        turns.record_move(invoker.user_id, placed);
+    }
+    let status = format!("{} swapped {}.", invoker.user_id.mention(), made.join(", "));
+
+    // Re-create the board based on the new tile positions
+    let board_renderer = game.board_renderer(renderer, encoder);
+    if let Some(board) = game.recreate_scrambled_image(board_renderer).await {
+        update_board(ctx, invoker.channel_id, game, board?, &status).await?;
+    }
+
+    // Check if the puzzle is solved
+    if game.tile_positions == game.correct_positions && !game.solved {
+        game.solved = true;
+        invoker.channel_id.say(&ctx.http, "Congratulations! You solved the puzzle!").await?;
+        let points = guild_config(ctx, invoker.guild_id).await.scoring.solve;
+        award(ctx, invoker, game, points).await?;
+    } else if let Some(turns) = &game.turns {
+        coop::schedule_timeout(ctx, invoker.channel_id, turns.turn(), turns.timeout);
+    }
+
+    Ok(None)
+}
+
+// Give a finished puzzle's points to whoever finished it, or in co-op games share them out by
+// the tiles each player put in place. A co-op pool holds the points for every player taking turns.
+// Channel plays pools work the same way, shared by winning votes instead of tiles.
+// Solo solves also count as a won game against the puzzle's rating.
+pub async fn award(ctx: &Context, invoker: &Invoker, game: &PicturePuzzleGame, points: usize) -> Result<(), BoxError> {
+    let (shares, shared_by) = match (&game.turns, &game.votes) {
+        (Some(turns), _) => (turns.split(points * turns.player_count().max(1)), "tiles placed"),
+        (None, Some(votes)) => (votes.split(points * votes.player_count().max(1)), "winning votes"),
+        (None, None) => {
+            add_score(ctx, invoker.guild_id, invoker.user_id, points).await;
+            if let Some(puzzle) = &game.puzzle {
+                let key = ratings::puzzle_key(puzzle, game.grid_size);
+                let change = ctx.data.write().await.get_mut::<RatingsKey>().unwrap().record_attempt(invoker.user_id, &key, true);
+                invoker
+                    .channel_id
+                    .send_message(&ctx.http, |m| {
+                        m.content(format!("{}'s rating: {}", invoker.user_id.mention(), ratings::describe_change(change)))
+                            .allowed_mentions(|mentions| mentions.empty_parse())
+                    })
+                    .await?;
+            }
+            return Ok(());
+        }
+    };
+    add_shared_score(ctx, invoker.guild_id, &shares).await;
+    let shares: Vec<_> = shares
+        .iter()
+        .map(|(user_id, points)| format!("{} +{}", user_id.mention(), points))
+        .collect();
+    invoker
+        .channel_id
+        .send_message(&ctx.http, |m| {
+            m.content(format!("Points shared by {}: {}", shared_by, shares.join(", ")))
+                .allowed_mentions(|mentions| mentions.empty_parse())
+        })
+        .await?;
+    Ok(())
+}
+
+pub async fn guess(ctx: &Context, invoker: &Invoker, guess: &str) -> ActionResult {
+    let (game, _, _) = game_context(ctx, invoker.channel_id).await;
+    let mut game = game.lock().await;
+    if game.puzzle.is_none() {
+        return Ok(Some(NO_PUZZLE.to_string()));
+    }
+
+    game.participants.insert(invoker.user_id);
+    if game.solved {
+        return Ok(Some("This puzzle has already been solved.".to_string()));
+    }
+    if game.check_guess(guess) {
+        game.solved = true;
+        if let Some(turns) = game.turns.as_mut() {
+            turns.record_guess(invoker.user_id);
+        }
+        if let Some(votes) = game.votes.as_mut() {
+            votes.record_guess(invoker.user_id);
+        }
+        invoker
+            .channel_id
+            .say(&ctx.http, format!("Correct! {} guessed the picture.", invoker.user_id.mention()))
+            .await?;
+        let points = guild_config(ctx, invoker.guild_id).await.scoring.guess;
+        award(ctx, invoker, &game, points).await?;
+        Ok(None)
+    } else {
+        Ok(Some("Incorrect guess. Try again!".to_string()))
+    }
+}
+
+// Suggest a swap that puts one more tile in place
+pub async fn hint(ctx: &Context, invoker: &Invoker) -> ActionResult {
+    let (game, _, _) = game_context(ctx, invoker.channel_id).await;
+    let game = game.lock().await;
+    if game.puzzle.is_none() {
+        return Ok(Some(NO_PUZZLE.to_string()));
+    }
+
+    let label = |slot| board::tile_label(slot, game.grid_size, game.board_options.notation);
+    let (slot, holder) = match board::hint(&game.tile_positions) {
+        Some(hint) => hint,
+        None => return Ok(Some("Every tile is already in place!".to_string())),
+    };
+    let price = guild_config(ctx, invoker.guild_id).await.prices.hint;
+    let receipt = match economy::pay(ctx, invoker.guild_id, invoker.user_id, price, "A hint").await {
+        Ok(receipt) => receipt,
+        Err(refusal) => return Ok(Some(refusal)),
+    };
+    Ok(Some(format!(
+        "Hint: swap {} and {} to put a tile in its correct place.{}",
+        label(slot),
+        label(holder),
+        receipt
+    )))
+}
+
+// Send the original picture to the player privately, for the server's peek price
+pub async fn peek(ctx: &Context, invoker: &Invoker) -> ActionResult {
+    let (game, _, encoder) = game_context(ctx, invoker.channel_id).await;
+    let game = game.lock().await;
+    let image = match (&game.puzzle, &game.original_image) {
+        (Some(_), Some(image)) => image.clone(),
+        _ => return Ok(Some(NO_PUZZLE.to_string())),
+    };
+    if game.solved {
+        return Ok(Some("This puzzle is already solved. Press Solve Puzzle to see the picture.".to_string()));
+    }
+
+    let price = guild_config(ctx, invoker.guild_id).await.prices.peek;
+    let receipt = match economy::pay(ctx, invoker.guild_id, invoker.user_id, price, "A peek").await {
+        Ok(receipt) => receipt,
+        Err(refusal) => return Ok(Some(refusal)),
+    };
+    let sent: Result<(), BoxError> = async {
+        let attachment = encode_blocking(image, encoder, "peek").await?;
+        let dm = invoker.user_id.create_dm_channel(ctx).await?;
+        dm.send_message(&ctx.http, |m| m.content("Here's a peek at the picture. Keep it to yourself!").add_file(attachment))
+            .await?;
+        Ok(())
+    }
+    .await;
+    if let Err(e) = sent {
+        eprintln!("Failed to send peek: {:?}", e);
+        economy::refund(ctx, invoker.guild_id, invoker.user_id, price, "A peek that couldn't be sent").await;
+        return Ok(Some(
+            "I couldn't send you a direct message, so you weren't charged. Check that DMs from server members are allowed."
+                .to_string(),
+        ));
+    }
+    Ok(Some(format!("Check your direct messages for a peek at the picture.{}", receipt)))
+}
+
+// End the puzzle and reveal the answer and original image. Once a puzzle is solved anyone may
+// reveal it. Before then the server's reveal policy decides who can, after the minimum play
+// time; players without permission vote instead, and a majority ends the game as abandoned.
+pub async fn give_up(ctx: &Context, invoker: &Invoker) -> ActionResult {
+    let (game, _, encoder) = game_context(ctx, invoker.channel_id).await;
+    let mut game = game.lock().await;
+    if game.puzzle.is_none() {
+        return Ok(Some(NO_PUZZLE.to_string()));
+    }
+
+    let headline = if game.solved {
+        None
+    } else {
+        let config = guild_config(ctx, invoker.guild_id).await;
+        let reveal_delay = match config.reveal_delay {
+            Some(seconds) => Duration::from_secs(seconds),
+            None => *ctx.data.read().await.get::<RevealDelayKey>().unwrap(),
+        };
+        let played = game.started_at.elapsed();
+        if played < reveal_delay {
+            return Ok(Some(format!(
+                "The answer can be revealed in {} seconds. Keep trying!",
+                (reveal_delay - played).as_secs().max(1)
+            )));
+        }
+
+        let allowed = match config.reveal {
+            RevealPolicy::Anyone => true,
+            RevealPolicy::Starter => game.starter == Some(invoker.user_id) || is_moderator(ctx, invoker).await,
+            RevealPolicy::Moderators | RevealPolicy::Vote => is_moderator(ctx, invoker).await,
+        };
+        if allowed {
+            Some(format!("{} ended the puzzle.", invoker.user_id.mention()))
+        } else if config.reveal == RevealPolicy::Moderators {
+            return Ok(Some("Only moderators can reveal this puzzle before it is solved.".to_string()));
+        } else if !game.participants.contains(&invoker.user_id) {
+            return Ok(Some("Only players in this puzzle can vote to reveal it. Make a move to join the vote.".to_string()));
+        } else {
+            game.reveal_votes.insert(invoker.user_id);
+            let votes = game.reveal_votes.len();
+            let needed = game.participants.len() / 2 + 1;
+            if votes < needed {
+                invoker
+                    .channel_id
+                    .say(
+                        &ctx.http,
+                        format!(
+                            "{} voted to reveal the answer ({}/{} votes needed).",
+                            invoker.user_id.mention(),
+                            votes,
+                            needed
+                        ),
+                    )
+                    .await?;
+                return Ok(Some("Your vote to reveal the answer has been counted.".to_string()));
+            }
+            Some("The players voted to end the puzzle.".to_string())
+        }
+    };
+
+    let puzzle = game.puzzle.take().ok_or(NO_PUZZLE)?;
+    let original_image = game.original_image.take();
+    game.last_swap.clear();
+    game.selected_tiles.clear();
+    retire_board(ctx, &mut game).await;
+
+    let text = match headline {
+        Some(headline) => {
+            let mut data = ctx.data.write().await;
+            data.get_mut::<StatsKey>().unwrap().abandoned += 1;
+            // An abandoned solo puzzle is a lost game for whoever started and played it
+            let solo = game.turns.is_none() && game.votes.is_none();
+            if let Some(starter) = game.starter.filter(|starter| solo && game.participants.contains(starter)) {
+                let key = ratings::puzzle_key(&puzzle, game.grid_size);
+                data.get_mut::<RatingsKey>().unwrap().record_attempt(starter, &key, false);
+            }
+            drop(data);
+            format!(
+                "{} The answer was **{}**. Here is the original image: **{}**\n{}",
+                headline, puzzle.answer, puzzle.title, puzzle.attribution
+            )
+        }
+        None => reveal_caption(&puzzle),
+    };
+    let attachment = match original_image {
+        Some(original_image) => Some(encode_blocking(original_image, encoder, "original").await?),
+        None => None,
+    };
+    invoker
+        .channel_id
+        .send_message(&ctx.http, |m| {
+            m.content(text).allowed_mentions(|mentions| mentions.empty_parse());
+            if let Some(attachment) = attachment {
+                m.add_file(attachment);
+            }
+            m
+        })
+        .await?;
+
+    Ok(None)
+}
+
+// Members who can manage messages or the server may end any puzzle
+pub async fn is_moderator(ctx: &Context, invoker: &Invoker) -> bool {
+    let guild_id = match invoker.guild_id {
+        Some(guild_id) => guild_id,
+        None => return false,
+    };
+    match guild_id.member(ctx, invoker.user_id).await {
+        Ok(member) => member
+            .permissions(&ctx.cache)
+            .is_ok_and(|permissions| permissions.manage_messages() || permissions.manage_guild()),
+        Err(_) => false,
+    }
+}
+
+pub async fn leaderboard(ctx: &Context) -> ActionResult {
+    let data = ctx.data.read().await;
+    let stats = data.get::<StatsKey>().unwrap();
+    let mut scores: Vec<_> = data.get::<ScoresKey>().unwrap().iter().collect();
+    if scores.is_empty() {
+        return Ok(Some("No one has scored yet. Start a puzzle with /puzzle start!".to_string()));
+    }
+    scores.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+
+    let mut text = String::from("**Leaderboard**");
+    for (rank, (user_id, score)) in scores.iter().take(10).enumerate() {
+        text.push_str(&format!(
+            "\n{}. {}: {} point{}",
+            rank + 1,
+            user_id.mention(),
+            score,
+            if **score == 1 { "" } else { "s" }
+        ));
+    }
+    text.push_str(&format!("\nPuzzles solved: {}, abandoned: {}", stats.solved, stats.abandoned));
+    Ok(Some(text))
+}
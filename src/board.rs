@@ -0,0 +1,276 @@
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+// How tiles are named in labels, prompts and replies
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Notation {
+    // 1, 2, 3, ... reading left to right, top to bottom
+    Numbers,
+    // Chess-style A1, B1, ... with columns as letters and rows as numbers from the top
+    Coordinates,
+}
+
+impl std::str::FromStr for Notation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "numbers" | "number" | "numeric" => Ok(Notation::Numbers),
+            "coordinates" | "coords" | "chess" => Ok(Notation::Coordinates),
+            _ => Err(format!("unknown notation \"{}\" (use numbers or coordinates)", s)),
+        }
+    }
+}
+
+// Highlight colors used for correct and misplaced tiles
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HighlightPalette {
+    // Green and red
+    Standard,
+    // Blue and orange from the Okabe-Ito palette, distinguishable with common color vision deficiencies
+    ColorblindSafe,
+}
+
+impl std::str::FromStr for HighlightPalette {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "standard" | "default" => Ok(HighlightPalette::Standard),
+            "colorblind" | "colourblind" | "colorblind-safe" => Ok(HighlightPalette::ColorblindSafe),
+            _ => Err(format!("unknown palette \"{}\" (use standard or colorblind)", s)),
+        }
+    }
+}
+
+// How a board is shown in Discord
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BoardDisplay {
+    Image,
+    // Grid of tile numbers in a code block
+    Text,
+    // Grid of keycap emoji, falling back to numbers on boards with more than ten tiles
+    Emoji,
+}
+
+impl std::str::FromStr for BoardDisplay {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "image" | "picture" => Ok(BoardDisplay::Image),
+            "text" | "numbers" => Ok(BoardDisplay::Text),
+            "emoji" => Ok(BoardDisplay::Emoji),
+            _ => Err(format!("unknown board display \"{}\" (use image, text or emoji)", s)),
+        }
+    }
+}
+
+// Accessibility options that affect how a board is rendered and described
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BoardOptions {
+    pub notation: Notation,
+    pub edge_labels: bool,
+    pub high_contrast: bool,
+    pub palette: HighlightPalette,
+    // Frame and check mark on tiles already in their correct slot
+    pub correctness_overlay: bool,
+    pub display: BoardDisplay,
+}
+
+impl Default for BoardOptions {
+    fn default() -> Self {
+        BoardOptions {
+            notation: Notation::Numbers,
+            edge_labels: false,
+            high_contrast: false,
+            palette: HighlightPalette::Standard,
+            correctness_overlay: false,
+            display: BoardDisplay::Image,
+        }
+    }
+}
+
+impl BoardOptions {
+    // The settings, with tile names shown for a `grid_size` board
+    pub fn describe(&self, grid_size: u32) -> String {
+        let notation = match self.notation {
+            Notation::Numbers => "numbers",
+            Notation::Coordinates => "coordinates",
+        };
+        format!(
+            "Display: {}\nNotation: {} ({} on a {}x{} board)\nEdge labels: {}\nContrast: {}\nHighlight colors: {}\nCorrectness overlay: {}",
+            match self.display {
+                BoardDisplay::Image => "image",
+                BoardDisplay::Text => "text grid",
+                BoardDisplay::Emoji => "emoji grid",
+            },
+            notation,
+            tile_range(grid_size, self.notation),
+            grid_size,
+            grid_size,
+            if self.edge_labels { "on" } else { "off" },
+            if self.high_contrast { "high" } else { "normal" },
+            match self.palette {
+                HighlightPalette::Standard => "standard (green/red)",
+                HighlightPalette::ColorblindSafe => "colorblind-safe (blue/orange)",
+            },
+            if self.correctness_overlay { "on" } else { "off" },
+        )
+    }
+}
+
+pub fn column_name(column: u32) -> char {
+    (b'A' + column as u8) as char
+}
+
+// Name of the slot at `index` (0-based) in the given notation
+pub fn tile_label(index: usize, grid_size: u32, notation: Notation) -> String {
+    match notation {
+        Notation::Numbers => format!("{}", index + 1),
+        Notation::Coordinates => {
+            let column = index as u32 % grid_size;
+            let row = index as u32 / grid_size;
+            format!("{}{}", column_name(column), row + 1)
+        }
+    }
+}
+
+// Parse a tile reference in either notation ("5" or "B2") into a 0-based index
+pub fn parse_tile(input: &str, grid_size: u32) -> Option<usize> {
+    let input = input.trim();
+    let tile_count = (grid_size * grid_size) as usize;
+
+    if let Ok(number) = input.parse::<usize>() {
+        return (1..=tile_count).contains(&number).then(|| number - 1);
+    }
+
+    let mut chars = input.chars();
+    let column = chars.next()?.to_ascii_uppercase();
+    let row = chars.as_str().parse::<u32>().ok()?;
+    if !column.is_ascii_uppercase() {
+        return None;
+    }
+    let column = column as u32 - 'A' as u32;
+    (column < grid_size && (1..=grid_size).contains(&row))
+        .then(|| ((row - 1) * grid_size + column) as usize)
+}
+
+// Parse a list of swaps such as "1-5 2-7" or "A1-B2, C3-A1" into slot pairs.
+// Every problem is reported, one per line, so they can all be fixed at once.
+pub fn parse_swaps(input: &str, grid_size: u32, notation: Notation) -> Result<Vec<(usize, usize)>, String> {
+    let mut swaps = Vec::new();
+    let mut errors = Vec::new();
+    let entries = input
+        .split(|c: char| c.is_whitespace() || c == ',' || c == ';')
+        .filter(|entry| !entry.is_empty());
+    for entry in entries {
+        let tiles = entry.split_once('-').map(|(first, second)| (parse_tile(first, grid_size), parse_tile(second, grid_size)));
+        match tiles {
+            Some((Some(first), Some(second))) if first != second => swaps.push((first, second)),
+            Some((Some(_), Some(_))) => errors.push(format!("\"{}\" swaps a tile with itself.", entry)),
+            Some(_) => errors.push(format!(
+                "\"{}\" is not on the board (use tiles from {}).",
+                entry,
+                tile_range(grid_size, notation)
+            )),
+            None => errors.push(format!("\"{}\" should be two tiles joined by a dash, like 1-5.", entry)),
+        }
+    }
+
+    if swaps.is_empty() && errors.is_empty() {
+        errors.push("Enter at least one swap, like 1-5.".to_string());
+    }
+    if errors.is_empty() {
+        Ok(swaps)
+    } else {
+        Err(errors.join("\n"))
+    }
+}
+
+// Example of a valid tile range for error messages, e.g. "1 to 9" or "A1 to C3"
+pub fn tile_range(grid_size: u32, notation: Notation) -> String {
+    let last = (grid_size * grid_size) as usize - 1;
+    format!(
+        "{} to {}",
+        tile_label(0, grid_size, notation),
+        tile_label(last, grid_size, notation)
+    )
+}
+
+// Shuffle tiles into a random permutation that is never already solved
+pub fn shuffled_positions(tile_count: usize) -> Vec<usize> {
+    shuffle_unsolved(tile_count, &mut rand::thread_rng())
+}
+
+// The same scramble every time for a given seed, so competing players can get identical boards
+pub fn seeded_positions(tile_count: usize, seed: u64) -> Vec<usize> {
+    shuffle_unsolved(tile_count, &mut StdRng::seed_from_u64(seed))
+}
+
+fn shuffle_unsolved(tile_count: usize, rng: &mut impl Rng) -> Vec<usize> {
+    let mut positions: Vec<usize> = (0..tile_count).collect();
+    if tile_count < 2 {
+        return positions;
+    }
+    loop {
+        positions.shuffle(rng);
+        if positions.iter().enumerate().any(|(slot, &tile)| slot != tile) {
+            return positions;
+        }
+    }
+}
+
+// Slots whose tile is already in its correct place
+pub fn correct_slots(tile_positions: &[usize]) -> Vec<usize> {
+    tile_positions
+        .iter()
+        .enumerate()
+        .filter(|&(slot, &tile)| slot == tile)
+        .map(|(slot, _)| slot)
+        .collect()
+}
+
+// A swap that puts one tile in its correct place: the first misplaced slot, and the slot
+// currently holding the tile that belongs there
+pub fn hint(tile_positions: &[usize]) -> Option<(usize, usize)> {
+    let slot = tile_positions.iter().enumerate().position(|(slot, &tile)| slot != tile)?;
+    let holder = tile_positions.iter().position(|&tile| tile == slot)?;
+    Some((slot, holder))
+}
+
+// Percentage of tiles in their correct place, rounded down
+pub fn percent_solved(tile_positions: &[usize]) -> usize {
+    if tile_positions.is_empty() {
+        return 0;
+    }
+    correct_slots(tile_positions).len() * 100 / tile_positions.len()
+}
+
+// Alt text describing the board for screen-reader users
+pub fn alt_text(grid_size: u32, tile_positions: &[usize], options: &BoardOptions) -> String {
+    let mut text = format!(
+        "Scrambled {size} by {size} picture puzzle. Tiles are labelled {range}, left to right and top to bottom.",
+        size = grid_size,
+        range = tile_range(grid_size, options.notation),
+    );
+    if options.edge_labels {
+        text.push_str(" Column letters and row numbers are printed around the edge of the board.");
+    }
+    if options.correctness_overlay {
+        let correct = correct_slots(tile_positions);
+        if correct.is_empty() {
+            text.push_str(" No tiles are in their correct place yet.");
+        } else {
+            let names: Vec<_> = correct
+                .iter()
+                .map(|&slot| tile_label(slot, grid_size, options.notation))
+                .collect();
+            text.push_str(&format!(" Tiles in their correct place are marked with a check: {}.", names.join(", ")));
+        }
+    }
+    text
+}
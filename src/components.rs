@@ -0,0 +1,182 @@
+use serenity::builder::{CreateActionRow, CreateButton, CreateSelectMenu, CreateSelectMenuOption};
+use serenity::model::application::component::ButtonStyle;
+
+use crate::board::{tile_label, BoardOptions};
+use crate::coop;
+
+// Discord allows five action rows per message, with five buttons or one select menu in each
+const MAX_ROWS: usize = 5;
+const MAX_SELECT_OPTIONS: usize = 25;
+
+// Largest grid shown as buttons. One row is kept for the Solve, Guess and Swap buttons,
+// so a 5x5 grid, which would need all five rows, uses select menus instead.
+pub const MAX_BUTTON_GRID: u32 = (MAX_ROWS - 1) as u32;
+
+const TILE_BUTTON_PREFIX: &str = "tile:";
+const TILE_MENU_PREFIX: &str = "tiles:";
+
+// The tile a clicked button or chosen menu option refers to
+pub fn clicked_tile(custom_id: &str, values: &[String]) -> Option<usize> {
+    if let Some(slot) = custom_id.strip_prefix(TILE_BUTTON_PREFIX) {
+        return slot.parse().ok();
+    }
+    if custom_id.starts_with(TILE_MENU_PREFIX) {
+        return values.first()?.parse().ok();
+    }
+    None
+}
+
+// Controls for a board: one button per tile, or select menus on large grids, then the
+// Solve, Guess and Swap buttons. `selected` is the tile picked as the first half of a swap.
+// Finished games keep their controls on show, but `disabled` so they can no longer be used.
+// Co-op games with `turns` also get Join and Leave buttons for the turn order.
+pub fn board_components(
+    grid_size: u32,
+    tile_positions: &[usize],
+    options: &BoardOptions,
+    selected: Option<usize>,
+    disabled: bool,
+    turns: bool,
+) -> Vec<CreateActionRow> {
+    let mut rows = tile_controls(grid_size, tile_positions, options, selected, disabled, "");
+    rows.push(create_button_row(disabled, turns));
+    rows
+}
+
+// Just the tile buttons or menus. `scope` is put in front of every id, so boards outside the
+// channel game (such as race boards) can tell their clicks apart; strip it before `clicked_tile`.
+pub fn tile_controls(
+    grid_size: u32,
+    tile_positions: &[usize],
+    options: &BoardOptions,
+    selected: Option<usize>,
+    disabled: bool,
+    scope: &str,
+) -> Vec<CreateActionRow> {
+    if grid_size <= MAX_BUTTON_GRID {
+        tile_buttons(grid_size, tile_positions, options, selected, disabled, scope)
+    } else {
+        tile_menus(grid_size, options, selected, disabled, scope)
+    }
+}
+
+fn tile_buttons(
+    grid_size: u32,
+    tile_positions: &[usize],
+    options: &BoardOptions,
+    selected: Option<usize>,
+    disabled: bool,
+    scope: &str,
+) -> Vec<CreateActionRow> {
+    tile_positions
+        .chunks(grid_size as usize)
+        .enumerate()
+        .map(|(row_index, tiles)| {
+            let mut row = CreateActionRow::default();
+            for (column, &tile) in tiles.iter().enumerate() {
+                let slot = row_index * grid_size as usize + column;
+                // Selected tile stands out; with the overlay on, tiles already in place are green
+                let style = if selected == Some(slot) {
+                    ButtonStyle::Primary
+                } else if options.correctness_overlay && tile == slot {
+                    ButtonStyle::Success
+                } else {
+                    ButtonStyle::Secondary
+                };
+                let mut button = CreateButton::default();
+                button
+                    .label(tile_label(slot, grid_size, options.notation))
+                    .custom_id(format!("{}{}{}", scope, TILE_BUTTON_PREFIX, slot))
+                    .style(style)
+                    .disabled(disabled);
+                row.add_button(button);
+            }
+            row
+        })
+        .collect()
+}
+
+// Tiles split across select menus of up to 25 options each
+fn tile_menus(grid_size: u32, options: &BoardOptions, selected: Option<usize>, disabled: bool, scope: &str) -> Vec<CreateActionRow> {
+    let tile_count = (grid_size * grid_size) as usize;
+    let slots: Vec<usize> = (0..tile_count).collect();
+    slots
+        .chunks(MAX_SELECT_OPTIONS)
+        .enumerate()
+        .map(|(index, chunk)| {
+            let first = tile_label(chunk[0], grid_size, options.notation);
+            let last = tile_label(chunk[chunk.len() - 1], grid_size, options.notation);
+            let placeholder = match selected {
+                Some(slot) if chunk.contains(&slot) => format!(
+                    "Selected {}, now pick a tile to swap with",
+                    tile_label(slot, grid_size, options.notation)
+                ),
+                _ => format!("Pick a tile ({} to {})", first, last),
+            };
+
+            let mut menu = CreateSelectMenu::default();
+            menu.custom_id(format!("{}{}{}", scope, TILE_MENU_PREFIX, index))
+                .placeholder(placeholder)
+                .disabled(disabled)
+                .options(|menu_options| {
+                    for &slot in chunk {
+                        menu_options.add_option(CreateSelectMenuOption::new(
+                            tile_label(slot, grid_size, options.notation),
+                            slot,
+                        ));
+                    }
+                    menu_options
+                });
+            let mut row = CreateActionRow::default();
+            row.add_select_menu(menu);
+            row
+        })
+        .collect()
+}
+
+fn create_button_row(disabled: bool, turns: bool) -> CreateActionRow {
+    let mut row = CreateActionRow::default();
+    row.add_button({
+        let mut button = CreateButton::default();
+        button.label("Solve Puzzle")
+              .custom_id("solve_puzzle")
+              .style(ButtonStyle::Primary)
+              .disabled(disabled);
+        button
+    });
+    row.add_button({
+        let mut button = CreateButton::default();
+        button.label("Guess")
+              .custom_id("guess")
+              .style(ButtonStyle::Success)
+              .disabled(disabled);
+        button
+    });
+    row.add_button({
+        let mut button = CreateButton::default();
+        button.label("Swap Tiles")
+              .custom_id("swap_tiles")
+              .style(ButtonStyle::Secondary)
+              .disabled(disabled);
+        button
+    });
+    if turns {
+        row.add_button({
+            let mut button = CreateButton::default();
+            button.label("Join")
+                  .custom_id(coop::JOIN_BUTTON)
+                  .style(ButtonStyle::Success)
+                  .disabled(disabled);
+            button
+        });
+        row.add_button({
+            let mut button = CreateButton::default();
+            button.label("Leave")
+                  .custom_id(coop::LEAVE_BUTTON)
+                  .style(ButtonStyle::Secondary)
+                  .disabled(disabled);
+            button
+        });
+    }
+    row
+}
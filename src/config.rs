@@ -0,0 +1,413 @@
+use serde::{Deserialize, Serialize};
+use serenity::model::id::{ChannelId, GuildId};
+use serenity::prelude::*;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::Notify;
+
+use crate::board::BoardDisplay;
+use crate::economy::price;
+use crate::manifest::{MAX_GRID_SIZE, MIN_GRID_SIZE};
+use crate::theme::GuildTheme;
+
+pub const DEFAULT_PREFIX: &str = "!";
+const MAX_PREFIX_LENGTH: usize = 5;
+const DEFAULT_TURN_TIMEOUT: u64 = 60;
+const MIN_TURN_TIMEOUT: u64 = 10;
+const MAX_TURN_TIMEOUT: u64 = 600;
+const DEFAULT_VOTE_WINDOW: u64 = 30;
+const MIN_VOTE_WINDOW: u64 = 10;
+const MAX_VOTE_WINDOW: u64 = 300;
+const MAX_PRICE: u64 = 100_000;
+
+// Who may reveal a puzzle before it has been solved
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RevealPolicy {
+    // The player who started it or a moderator; other players vote
+    #[default]
+    Starter,
+    // Moderators only
+    Moderators,
+    // A majority of players, with moderators still able to end it outright
+    Vote,
+    // Anyone
+    Anyone,
+}
+
+impl std::str::FromStr for RevealPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "starter" => Ok(RevealPolicy::Starter),
+            "moderators" | "mods" => Ok(RevealPolicy::Moderators),
+            "vote" => Ok(RevealPolicy::Vote),
+            "anyone" | "everyone" => Ok(RevealPolicy::Anyone),
+            _ => Err(format!("unknown reveal policy \"{}\" (use starter, moderators, vote or anyone)", s)),
+        }
+    }
+}
+
+// Points awarded for each way of finishing a puzzle
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScoringWeights {
+    // Putting the last tile in place
+    pub solve: usize,
+    // Guessing what the picture shows
+    pub guess: usize,
+}
+
+impl Default for ScoringWeights {
+    fn default() -> Self {
+        ScoringWeights { solve: 1, guess: 1 }
+    }
+}
+
+// Coins charged for things players buy. A price of 0 makes it free.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Prices {
+    pub hint: u64,
+    // A private look at the original picture
+    pub peek: u64,
+    // Unlocking a board theme for your own puzzles
+    pub theme: u64,
+}
+
+impl Default for Prices {
+    fn default() -> Self {
+        Prices { hint: 5, peek: 20, theme: 250 }
+    }
+}
+
+// Settings a server's admins can change with `!config`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GuildConfig {
+    pub prefix: String,
+    // Replaces the grid size suggested by the manifest
+    pub grid_size: Option<u32>,
+    // Board display for players who have not chosen one with `!accessibility`
+    pub mode: Option<BoardDisplay>,
+    // Channels puzzles may be played in; empty means every channel
+    pub allowed_channels: Vec<ChannelId>,
+    pub theme: GuildTheme,
+    pub scoring: ScoringWeights,
+    pub reveal: RevealPolicy,
+    // Seconds a puzzle must be played before it can be revealed; None uses PUZZLE_MIN_REVEAL_SECONDS
+    pub reveal_delay: Option<u64>,
+    // Seconds each co-op turn lasts before the player is skipped
+    pub turn_timeout: u64,
+    // Seconds each voting window lasts in channel plays puzzles
+    pub vote_window: u64,
+    pub prices: Prices,
+}
+
+impl Default for GuildConfig {
+    fn default() -> Self {
+        GuildConfig {
+            prefix: DEFAULT_PREFIX.to_string(),
+            grid_size: None,
+            mode: None,
+            allowed_channels: Vec::new(),
+            theme: GuildTheme::default(),
+            scoring: ScoringWeights::default(),
+            reveal: RevealPolicy::default(),
+            reveal_delay: None,
+            turn_timeout: DEFAULT_TURN_TIMEOUT,
+            vote_window: DEFAULT_VOTE_WINDOW,
+            prices: Prices::default(),
+        }
+    }
+}
+
+impl GuildConfig {
+    pub fn allows_channel(&self, channel_id: ChannelId) -> bool {
+        self.allowed_channels.is_empty() || self.allowed_channels.contains(&channel_id)
+    }
+
+    pub fn set_prefix(&mut self, prefix: &str) -> Result<(), String> {
+        let prefix = prefix.trim();
+        if prefix.is_empty() || prefix.chars().count() > MAX_PREFIX_LENGTH || prefix.contains(char::is_whitespace) {
+            return Err(format!(
+                "invalid prefix \"{}\" (use up to {} characters without spaces)",
+                prefix, MAX_PREFIX_LENGTH
+            ));
+        }
+        self.prefix = prefix.to_string();
+        Ok(())
+    }
+
+    // A grid size, or `auto` to use each puzzle's own
+    pub fn set_grid_size(&mut self, value: &str) -> Result<(), String> {
+        self.grid_size = match value.trim() {
+            "auto" | "default" => None,
+            value => Some(
+                value
+                    .parse()
+                    .ok()
+                    .filter(|size| (MIN_GRID_SIZE..=MAX_GRID_SIZE).contains(size))
+                    .ok_or_else(|| format!("invalid grid size \"{}\" (use {}-{} or auto)", value, MIN_GRID_SIZE, MAX_GRID_SIZE))?,
+            ),
+        };
+        Ok(())
+    }
+
+    pub fn set_mode(&mut self, value: &str) -> Result<(), String> {
+        self.mode = match value.trim() {
+            "auto" | "default" => None,
+            value => Some(value.parse()?),
+        };
+        Ok(())
+    }
+
+    // e.g. `solve 3` or `guess 2`
+    pub fn set_scoring(&mut self, kind: &str, value: &str) -> Result<(), String> {
+        let points = value
+            .trim()
+            .parse()
+            .ok()
+            .filter(|points| *points <= 100)
+            .ok_or_else(|| format!("invalid points \"{}\" (use 0-100)", value.trim()))?;
+        match kind {
+            "solve" => self.scoring.solve = points,
+            "guess" => self.scoring.guess = points,
+            _ => return Err(format!("unknown score \"{}\" (use solve or guess)", kind)),
+        }
+        Ok(())
+    }
+
+    pub fn set_price(&mut self, kind: &str, value: &str) -> Result<(), String> {
+        let coins = value
+            .trim()
+            .parse()
+            .ok()
+            .filter(|coins| *coins <= MAX_PRICE)
+            .ok_or_else(|| format!("invalid price \"{}\" (use 0-{} coins)", value.trim(), MAX_PRICE))?;
+        match kind {
+            "hint" => self.prices.hint = coins,
+            "peek" => self.prices.peek = coins,
+            "theme" => self.prices.theme = coins,
+            _ => return Err(format!("unknown price \"{}\" (use hint, peek or theme)", kind)),
+        }
+        Ok(())
+    }
+
+    // A policy name, or `delay <seconds|default>`
+    pub fn set_reveal(&mut self, value: &str) -> Result<(), String> {
+        let mut words = value.split_whitespace();
+        match (words.next(), words.next()) {
+            (Some("delay"), Some("default")) => self.reveal_delay = None,
+            (Some("delay"), Some(seconds)) => {
+                self.reveal_delay = Some(
+                    seconds
+                        .parse()
+                        .ok()
+                        .filter(|seconds| *seconds <= 3600)
+                        .ok_or_else(|| format!("invalid delay \"{}\" (use 0-3600 seconds or default)", seconds))?,
+                );
+            }
+            (Some(policy), None) => self.reveal = policy.parse()?,
+            _ => return Err("usage: reveal <starter|moderators|vote|anyone> or reveal delay <seconds|default>".to_string()),
+        }
+        Ok(())
+    }
+
+    pub fn set_turn_timeout(&mut self, value: &str) -> Result<(), String> {
+        self.turn_timeout = match value.trim() {
+            "default" => DEFAULT_TURN_TIMEOUT,
+            value => value
+                .parse()
+                .ok()
+                .filter(|seconds| (MIN_TURN_TIMEOUT..=MAX_TURN_TIMEOUT).contains(seconds))
+                .ok_or_else(|| {
+                    format!(
+                        "invalid turn length \"{}\" (use {}-{} seconds or default)",
+                        value, MIN_TURN_TIMEOUT, MAX_TURN_TIMEOUT
+                    )
+                })?,
+        };
+        Ok(())
+    }
+
+    pub fn set_vote_window(&mut self, value: &str) -> Result<(), String> {
+        self.vote_window = match value.trim() {
+            "default" => DEFAULT_VOTE_WINDOW,
+            value => value
+                .parse()
+                .ok()
+                .filter(|seconds| (MIN_VOTE_WINDOW..=MAX_VOTE_WINDOW).contains(seconds))
+                .ok_or_else(|| {
+                    format!(
+                        "invalid voting window \"{}\" (use {}-{} seconds or default)",
+                        value, MIN_VOTE_WINDOW, MAX_VOTE_WINDOW
+                    )
+                })?,
+        };
+        Ok(())
+    }
+
+    pub fn describe(&self) -> String {
+        let channels = if self.allowed_channels.is_empty() {
+            "all".to_string()
+        } else {
+            self.allowed_channels
+                .iter()
+                .map(|channel_id| format!("<#{}>", channel_id))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        format!(
+            "Prefix: {}\nGrid size: {}\nMode: {}\nAllowed channels: {}\n{}\nScoring: {} for solving, {} for guessing\nReveal: {}{}\nCo-op turns: {} seconds\nChannel plays votes: {} seconds\nPrices: hint {}, peek {}, theme {}",
+            self.prefix,
+            self.grid_size.map_or("from each puzzle".to_string(), |size| format!("{0}x{0}", size)),
+            match self.mode {
+                None => "image, unless players choose otherwise",
+                Some(BoardDisplay::Image) => "image",
+                Some(BoardDisplay::Text) => "text grid",
+                Some(BoardDisplay::Emoji) => "emoji grid",
+            },
+            channels,
+            self.theme.describe(),
+            self.scoring.solve,
+            self.scoring.guess,
+            match self.reveal {
+                RevealPolicy::Starter => "starter or moderators, or a vote of players",
+                RevealPolicy::Moderators => "moderators only",
+                RevealPolicy::Vote => "a vote of players, or moderators",
+                RevealPolicy::Anyone => "anyone",
+            },
+            self.reveal_delay
+                .map_or(String::new(), |seconds| format!(" after {} seconds", seconds)),
+            self.turn_timeout,
+            self.vote_window,
+            price(self.prices.hint),
+            price(self.prices.peek),
+            price(self.prices.theme),
+        )
+    }
+}
+
+// Every server's settings, saved to a JSON file by `keep_saved` whenever they change
+pub struct ConfigStore {
+    path: PathBuf,
+    guilds: HashMap<GuildId, GuildConfig>,
+    changed: Arc<Notify>,
+}
+
+impl ConfigStore {
+    // Load the store, starting empty if the file does not exist yet
+    pub fn load(path: PathBuf) -> Result<Self, String> {
+        let guilds = match std::fs::read_to_string(&path) {
+            Ok(json) => serde_json::from_str(&json).map_err(|e| format!("{}: {}", path.display(), e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(format!("{}: {}", path.display(), e)),
+        };
+        Ok(ConfigStore {
+            path,
+            guilds,
+            changed: Arc::new(Notify::new()),
+        })
+    }
+
+    // A server's settings, or the defaults outside servers and for servers that changed nothing
+    pub fn get(&self, guild_id: Option<GuildId>) -> GuildConfig {
+        guild_id
+            .and_then(|guild_id| self.guilds.get(&guild_id).cloned())
+            .unwrap_or_default()
+    }
+
+    pub fn prefix(&self, guild_id: Option<GuildId>) -> &str {
+        guild_id
+            .and_then(|guild_id| self.guilds.get(&guild_id))
+            .map_or(DEFAULT_PREFIX, |config| config.prefix.as_str())
+    }
+
+    // Change a server's settings and save them
+    pub fn update<T>(
+        &mut self,
+        guild_id: GuildId,
+        change: impl FnOnce(&mut GuildConfig) -> Result<T, String>,
+    ) -> Result<T, String> {
+        let mut config = self.get(Some(guild_id));
+        let result = change(&mut config)?;
+        self.guilds.insert(guild_id, config);
+        self.changed.notify_one();
+        Ok(result)
+    }
+}
+
+impl SavedStore for ConfigStore {
+    const NAME: &'static str = "server settings";
+
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn changes(&self) -> &Arc<Notify> {
+        &self.changed
+    }
+
+    fn to_json(&self) -> Result<String, String> {
+        serde_json::to_string_pretty(&self.guilds).map_err(|e| e.to_string())
+    }
+}
+
+// A store kept in a JSON file. Stores don't write the file themselves: they signal `changes`,
+// and `keep_saved` writes it, so nobody waits on the disk while holding the data lock.
+pub trait SavedStore {
+    // What the file holds, for error messages
+    const NAME: &'static str;
+    fn path(&self) -> &Path;
+    fn changes(&self) -> &Arc<Notify>;
+    fn to_json(&self) -> Result<String, String>;
+}
+
+// Write the store under `K` to its file whenever it changes, for as long as the bot runs. The
+// contents are copied under the data lock, which is released before the file is written on the
+// blocking pool. Changes made while a write is in progress are saved together by the next one.
+pub async fn keep_saved<K>(data: Arc<RwLock<TypeMap>>)
+where
+    K: TypeMapKey,
+    K::Value: SavedStore,
+{
+    let changes = data.read().await.get::<K>().unwrap().changes().clone();
+    loop {
+        changes.notified().await;
+        let (path, json) = {
+            let data = data.read().await;
+            let store = data.get::<K>().unwrap();
+            (store.path().to_path_buf(), store.to_json())
+        };
+        let written = match json {
+            Ok(json) => {
+                let path = path.clone();
+                tokio::task::spawn_blocking(move || write_atomic(&path, &json))
+                    .await
+                    .unwrap_or_else(|e| Err(e.to_string()))
+            }
+            Err(e) => Err(e),
+        };
+        if let Err(e) = written {
+            eprintln!("Failed to save {} to {}: {}", K::Value::NAME, path.display(), e);
+        }
+    }
+}
+
+// Write `value` as JSON to `path`
+pub fn save_json(path: &Path, value: &impl Serialize) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(value).map_err(|e| e.to_string())?;
+    write_atomic(path, &json)
+}
+
+// Write through a temporary file that replaces `path` in one step,
+// so a crash part way through a save never leaves it half written
+pub fn write_atomic(path: &Path, contents: &str) -> Result<(), String> {
+    let temp_path = path.with_extension("json.tmp");
+    std::fs::write(&temp_path, contents)
+        .and_then(|_| std::fs::rename(&temp_path, path))
+        .map_err(|e| e.to_string())
+}
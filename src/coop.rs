@@ -0,0 +1,338 @@
+use serenity::model::application::interaction::message_component::MessageComponentInteraction;
+use serenity::model::application::interaction::InteractionResponseType;
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::actions::NO_PUZZLE;
+use crate::{game_context, update_board};
+
+// Join and Leave buttons on a co-op puzzle message
+pub const JOIN_BUTTON: &str = "turns:join";
+pub const LEAVE_BUTTON: &str = "turns:leave";
+
+// Players who let this many turns in a row run out are taken out of the order
+const MAX_MISSED_TURNS: u32 = 3;
+
+// Turn order for a co-op puzzle. Only the current player may swap; each swap passes the turn on.
+pub struct TurnOrder {
+    players: Vec<UserId>, // In the order they joined
+    current: usize,
+    // Identifies the turn in progress, so a timeout can tell whether its turn already ended.
+    // Starts at a random number so timers from an earlier puzzle never match.
+    turn: u64,
+    pub timeout: Duration,
+    missed: HashMap<UserId, u32>,
+    // Tiles each player put in their correct place, used to split the points
+    contributions: HashMap<UserId, usize>,
+}
+
+impl TurnOrder {
+    pub fn new(starter: UserId, timeout: Duration) -> Self {
+        TurnOrder {
+            players: vec![starter],
+            current: 0,
+            turn: rand::random(),
+            timeout,
+            missed: HashMap::new(),
+            contributions: HashMap::new(),
+        }
+    }
+
+    pub fn current(&self) -> Option<UserId> {
+        self.players.get(self.current).copied()
+    }
+
+    pub fn turn(&self) -> u64 {
+        self.turn
+    }
+
+    pub fn player_count(&self) -> usize {
+        self.players.len()
+    }
+
+    pub fn contains(&self, user_id: UserId) -> bool {
+        self.players.contains(&user_id)
+    }
+
+    // Add a player at the end of the order. A second player starts the turn clock again,
+    // since a lone player is never timed out.
+    pub fn join(&mut self, user_id: UserId) -> bool {
+        if self.contains(user_id) {
+            return false;
+        }
+        self.players.push(user_id);
+        if self.players.len() == 2 {
+            self.turn = self.turn.wrapping_add(1);
+        }
+        true
+    }
+
+    // Remove a player, passing the turn on if it was theirs
+    pub fn leave(&mut self, user_id: UserId) -> bool {
+        let index = match self.players.iter().position(|&player| player == user_id) {
+            Some(index) => index,
+            None => return false,
+        };
+        self.players.remove(index);
+        self.missed.remove(&user_id);
+        if index < self.current {
+            self.current -= 1;
+        } else if index == self.current {
+            self.turn = self.turn.wrapping_add(1);
+        }
+        if self.current >= self.players.len() {
+            self.current = 0;
+        }
+        true
+    }
+
+    fn advance(&mut self) {
+        if !self.players.is_empty() {
+            self.current = (self.current + 1) % self.players.len();
+        }
+        self.turn = self.turn.wrapping_add(1);
+    }
+
+    // Why a player can't move right now, or None if it is their turn
+    pub fn refusal(&self, user_id: UserId) -> Option<String> {
+        match self.current() {
+            Some(current) if current == user_id => None,
+            _ if !self.contains(user_id) => Some("This is a co-op puzzle. Press Join on the puzzle to take turns.".to_string()),
+            Some(current) => Some(format!("It's {}'s turn. Wait for yours!", current.mention())),
+            None => Some("Nobody is taking turns. Press Join on the puzzle to take turns.".to_string()),
+        }
+    }
+
+    // Credit a move to the current player and pass the turn on
+    pub fn record_move(&mut self, user_id: UserId, placed: usize) {
+        self.missed.remove(&user_id);
+        *self.contributions.entry(user_id).or_insert(0) += placed;
+        self.advance();
+    }
+
+    // Credit a correct guess as one placed tile
+    pub fn record_guess(&mut self, user_id: UserId) {
+        *self.contributions.entry(user_id).or_insert(0) += 1;
+    }
+
+    // Skip the current player after a timeout. Returns who was skipped, and whether they
+    // missed too many turns in a row and were taken out of the order.
+    fn skip(&mut self) -> Option<(UserId, bool)> {
+        let skipped = self.current()?;
+        let missed = self.missed.entry(skipped).or_insert(0);
+        *missed += 1;
+        if *missed >= MAX_MISSED_TURNS {
+            self.leave(skipped);
+            return Some((skipped, true));
+        }
+        self.advance();
+        Some((skipped, false))
+    }
+
+    // Shown on the puzzle message under the move count
+    pub fn describe(&self) -> String {
+        match self.current() {
+            None => "Turn: nobody yet. Press Join to take turns.".to_string(),
+            Some(current) => {
+                let mut text = format!("Turn: {}", current.mention());
+                if self.players.len() > 1 {
+                    text.push_str(&format!(" ({} seconds)", self.timeout.as_secs()));
+                    let next: Vec<_> = (1..self.players.len())
+                        .map(|offset| self.players[(self.current + offset) % self.players.len()].mention().to_string())
+                        .collect();
+                    text.push_str(&format!(", then {}", next.join(", ")));
+                }
+                text
+            }
+        }
+    }
+
+    // Share `pool` points in proportion to each player's contribution. With no contributions
+    // it is split evenly between players.
+    pub fn split(&self, pool: usize) -> Vec<(UserId, usize)> {
+        let mut weights: Vec<(UserId, usize)> = self
+            .contributions
+            .iter()
+            .filter(|(_, &placed)| placed > 0)
+            .map(|(&user_id, &placed)| (user_id, placed))
+            .collect();
+        if weights.is_empty() {
+            weights = self.players.iter().map(|&user_id| (user_id, 1)).collect();
+        }
+        share(weights, pool)
+    }
+}
+
+// Share `pool` points in proportion to each player's weight, handing the points lost to
+// rounding to the heaviest weights. If every weight is zero the points are split evenly.
+pub fn share(mut weights: Vec<(UserId, usize)>, pool: usize) -> Vec<(UserId, usize)> {
+    if weights.iter().all(|&(_, weight)| weight == 0) {
+        weights.iter_mut().for_each(|(_, weight)| *weight = 1);
+    }
+    let total: usize = weights.iter().map(|(_, weight)| weight).sum();
+    if total == 0 {
+        return Vec::new();
+    }
+    weights.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+    let mut shares: Vec<(UserId, usize)> = weights
+        .iter()
+        .map(|&(user_id, weight)| (user_id, pool * weight / total))
+        .collect();
+    let mut left = pool - shares.iter().map(|(_, points)| points).sum::<usize>();
+    for share in shares.iter_mut() {
+        if left == 0 {
+            break;
+        }
+        share.1 += 1;
+        left -= 1;
+    }
+    shares
+}
+
+// Skip the player holding `turn` if they have not moved when it runs out. Only runs while at
+// least two players take turns, so someone playing alone is never hurried.
+pub fn schedule_timeout(ctx: &Context, channel_id: ChannelId, turn: u64, timeout: Duration) {
+    let ctx = ctx.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(timeout).await;
+        let (game, renderer, encoder) = game_context(&ctx, channel_id).await;
+        let mut game = game.lock().await;
+        if game.puzzle.is_none() || game.solved {
+            return;
+        }
+        let (skipped, removed, next_turn) = match game.turns.as_mut() {
+            Some(turns) if turns.turn == turn && turns.players.len() > 1 => match turns.skip() {
+                Some((skipped, removed)) => (skipped, removed, turns.turn),
+                None => return,
+            },
+            _ => return,
+        };
+        game.selected_tiles.clear();
+        game.last_swap.clear();
+
+        let status = if removed {
+            format!(
+                "{} missed {} turns in a row and was taken out of the turn order.",
+                skipped.mention(),
+                MAX_MISSED_TURNS
+            )
+        } else {
+            format!("{} ran out of time, so their turn was skipped.", skipped.mention())
+        };
+        let board_renderer = game.board_renderer(&renderer, encoder);
+        if let Some(board) = game.recreate_scrambled_image(board_renderer).await {
+            match board {
+                Ok(board) => {
+                    if let Err(e) = update_board(&ctx, channel_id, &mut game, board, &status).await {
+                        eprintln!("Failed to show skipped turn: {:?}", e);
+                    }
+                }
+                Err(e) => eprintln!("Failed to render board after skipped turn: {:?}", e),
+            }
+        }
+        schedule_timeout(&ctx, channel_id, next_turn, timeout);
+    });
+}
+
+// Join or leave the turn order from the puzzle message
+pub async fn handle_join(ctx: &Context, component: &MessageComponentInteraction, joining: bool) {
+    let (game, renderer, encoder) = game_context(ctx, component.channel_id).await;
+    let mut game = game.lock().await;
+    let user_id = component.user.id;
+    let has_puzzle = game.puzzle.is_some();
+    let turn_before = game.turns.as_ref().map(|turns| turns.turn);
+    let feedback = match game.turns.as_mut() {
+        _ if !has_puzzle => Some(NO_PUZZLE),
+        None => Some("This puzzle isn't a co-op game."),
+        Some(turns) if joining => (!turns.join(user_id)).then_some("You're already taking turns."),
+        Some(turns) => (!turns.leave(user_id)).then_some("You aren't taking turns in this puzzle."),
+    };
+    if let Some(feedback) = feedback {
+        drop(game);
+        if let Err(e) = component
+            .create_interaction_response(&ctx.http, |response| {
+                response
+                    .kind(InteractionResponseType::ChannelMessageWithSource)
+                    .interaction_response_data(|message| message.content(feedback).ephemeral(true))
+            })
+            .await
+        {
+            eprintln!("Failed to respond to turn button: {:?}", e);
+        }
+        return;
+    }
+
+    // The board is drawn again below, which can take longer than the three seconds Discord allows
+    if let Err(e) = component
+        .create_interaction_response(&ctx.http, |response| response.kind(InteractionResponseType::DeferredUpdateMessage))
+        .await
+    {
+        eprintln!("Failed to acknowledge turn button: {:?}", e);
+    }
+    game.selected_tiles.remove(&user_id);
+    let (turn, timeout) = match game.turns.as_ref() {
+        Some(turns) => (turns.turn, turns.timeout),
+        None => return,
+    };
+    let status = if joining {
+        format!("{} joined the turn order.", user_id.mention())
+    } else {
+        format!("{} left the turn order.", user_id.mention())
+    };
+    game.last_swap.clear();
+    let board_renderer = game.board_renderer(&renderer, encoder);
+    if let Some(board) = game.recreate_scrambled_image(board_renderer).await {
+        match board {
+            Ok(board) => {
+                if let Err(e) = update_board(ctx, component.channel_id, &mut game, board, &status).await {
+                    eprintln!("Failed to show turn order: {:?}", e);
+                }
+            }
+            Err(e) => eprintln!("Failed to render board for turn order: {:?}", e),
+        }
+    }
+    // A new turn starts when the turn holder leaves, or a lone player gets company
+    if turn_before != Some(turn) {
+        schedule_timeout(ctx, component.channel_id, turn, timeout);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn total(shares: &[(UserId, usize)]) -> usize {
+        shares.iter().map(|(_, points)| points).sum()
+    }
+
+    #[test]
+    fn shares_add_up_to_the_pool() {
+        for pool in [0, 1, 7, 10, 99] {
+            let shares = share(vec![(UserId(1), 3), (UserId(2), 1), (UserId(3), 2)], pool);
+            assert_eq!(total(&shares), pool);
+        }
+    }
+
+    #[test]
+    fn rounding_leftovers_go_to_the_heaviest_weights() {
+        let shares = share(vec![(UserId(1), 1), (UserId(2), 3), (UserId(3), 2)], 10);
+        // 1.67, 5 and 3.33 round down to 1, 5 and 3, so the one point left goes to the weight of 3
+        assert_eq!(shares, [(UserId(2), 6), (UserId(3), 3), (UserId(1), 1)]);
+    }
+
+    #[test]
+    fn equal_weights_break_ties_by_user() {
+        let shares = share(vec![(UserId(3), 1), (UserId(1), 1), (UserId(2), 1)], 5);
+        assert_eq!(shares, [(UserId(1), 2), (UserId(2), 2), (UserId(3), 1)]);
+    }
+
+    #[test]
+    fn all_zero_weights_split_evenly() {
+        let shares = share(vec![(UserId(1), 0), (UserId(2), 0), (UserId(3), 0)], 10);
+        assert_eq!(shares, [(UserId(1), 4), (UserId(2), 3), (UserId(3), 3)]);
+        assert!(share(Vec::new(), 10).is_empty());
+    }
+}
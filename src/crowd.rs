@@ -0,0 +1,238 @@
+use rand::seq::SliceRandom;
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::actions::{self, Invoker};
+use crate::board::{tile_label, Notation};
+use crate::coop;
+use crate::{game_context, guild_config, update_board};
+
+// How often the tally on the puzzle message catches up with votes coming in. Editing the
+// message for every vote would run into Discord's rate limits in a busy channel.
+const TALLY_REFRESH: Duration = Duration::from_secs(3);
+// Most popular moves listed on the puzzle message
+const TALLY_LENGTH: usize = 5;
+
+// Votes for a channel plays puzzle. Everyone votes for a swap; when the voting window closes
+// the most popular one is made. A window opens with the first vote cast after the last one closed.
+pub struct VoteRound {
+    guild_id: Option<GuildId>,
+    pub window: Duration,
+    // Identifies the window in progress, so a timer can tell whether its window already closed.
+    // Starts at a random number so timers from an earlier puzzle never match.
+    round: u64,
+    closes_at: Option<SystemTime>,
+    votes: HashMap<UserId, (usize, usize)>, // Each player's vote in this window, as a pair of slots
+    // How often each player voted for the swap that was made, used to split the points
+    winning_votes: HashMap<UserId, usize>,
+    refresh_pending: bool,
+    // Latest news shown at the top of the puzzle message, kept for tally refreshes
+    status: String,
+}
+
+impl VoteRound {
+    pub fn new(guild_id: Option<GuildId>, window: Duration, status: &str) -> Self {
+        VoteRound {
+            guild_id,
+            window,
+            round: rand::random(),
+            closes_at: None,
+            votes: HashMap::new(),
+            winning_votes: HashMap::new(),
+            refresh_pending: false,
+            status: status.to_string(),
+        }
+    }
+
+    // Count or change a player's vote. Returns the window's id if this vote opened it.
+    fn vote(&mut self, user_id: UserId, (first, second): (usize, usize)) -> Option<u64> {
+        self.votes.insert(user_id, (first.min(second), first.max(second)));
+        if self.closes_at.is_some() {
+            return None;
+        }
+        self.closes_at = Some(SystemTime::now() + self.window);
+        Some(self.round)
+    }
+
+    // Each swap voted for and its votes, most popular first
+    fn tally(&self) -> Vec<((usize, usize), usize)> {
+        let mut counts: HashMap<(usize, usize), usize> = HashMap::new();
+        for &slots in self.votes.values() {
+            *counts.entry(slots).or_insert(0) += 1;
+        }
+        let mut tally: Vec<_> = counts.into_iter().collect();
+        tally.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        tally
+    }
+
+    // Close the window, crediting everyone who voted for the winning swap. Ties are broken at
+    // random. Returns the swap, its votes and the votes cast, or None if nobody voted.
+    fn close(&mut self) -> Option<((usize, usize), usize, usize)> {
+        let tally = self.tally();
+        let (_, top) = *tally.first()?;
+        let tied: Vec<_> = tally.iter().filter(|(_, count)| *count == top).collect();
+        let &&(winner, _) = tied.choose(&mut rand::thread_rng())?;
+        for (&user_id, &slots) in &self.votes {
+            if slots == winner {
+                *self.winning_votes.entry(user_id).or_insert(0) += 1;
+            }
+        }
+        let cast = self.votes.len();
+        self.votes.clear();
+        self.closes_at = None;
+        self.round = self.round.wrapping_add(1);
+        self.refresh_pending = false;
+        Some((winner, top, cast))
+    }
+
+    // Credit a correct guess as one winning vote
+    pub fn record_guess(&mut self, user_id: UserId) {
+        *self.winning_votes.entry(user_id).or_insert(0) += 1;
+    }
+
+    // Players with at least one winning vote or correct guess
+    pub fn player_count(&self) -> usize {
+        self.winning_votes.len()
+    }
+
+    // Share `pool` points by how often each player voted for the swaps that were made
+    pub fn split(&self, pool: usize) -> Vec<(UserId, usize)> {
+        coop::share(self.winning_votes.iter().map(|(&user_id, &count)| (user_id, count)).collect(), pool)
+    }
+
+    // Shown on the puzzle message under the move count
+    pub fn describe(&self, grid_size: u32, notation: Notation) -> String {
+        let closes_at = match self.closes_at {
+            Some(closes_at) => closes_at,
+            None => return "Votes: none yet. Pick two tiles to vote for a swap.".to_string(),
+        };
+        let tally = self.tally();
+        let mut moves: Vec<_> = tally
+            .iter()
+            .take(TALLY_LENGTH)
+            .map(|&((first, second), count)| {
+                format!(
+                    "{} and {} ({})",
+                    tile_label(first, grid_size, notation),
+                    tile_label(second, grid_size, notation),
+                    count
+                )
+            })
+            .collect();
+        if tally.len() > TALLY_LENGTH {
+            moves.push(format!("{} more", tally.len() - TALLY_LENGTH));
+        }
+        let timestamp = closes_at.duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs());
+        format!("Votes, closing <t:{}:R>: {}", timestamp, moves.join(", "))
+    }
+}
+
+// Count a vote from the tile buttons or a command, bringing the tally up to date shortly after
+pub fn cast(ctx: &Context, channel_id: ChannelId, votes: &mut VoteRound, user_id: UserId, slots: (usize, usize)) {
+    if let Some(round) = votes.vote(user_id, slots) {
+        schedule_close(ctx, channel_id, round, votes.window);
+    }
+    if !votes.refresh_pending {
+        votes.refresh_pending = true;
+        schedule_refresh(ctx, channel_id);
+    }
+}
+
+// Redraw the puzzle message with the latest tally, once for however many votes came in meanwhile
+fn schedule_refresh(ctx: &Context, channel_id: ChannelId) {
+    let ctx = ctx.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(TALLY_REFRESH).await;
+        let (game, renderer, encoder) = game_context(&ctx, channel_id).await;
+        let mut game = game.lock().await;
+        if game.puzzle.is_none() || game.solved {
+            return;
+        }
+        let status = match game.votes.as_mut() {
+            Some(votes) if votes.refresh_pending => {
+                votes.refresh_pending = false;
+                votes.status.clone()
+            }
+            _ => return,
+        };
+        let board_renderer = game.board_renderer(&renderer, encoder);
+        if let Some(board) = game.recreate_scrambled_image(board_renderer).await {
+            match board {
+                Ok(board) => {
+                    if let Err(e) = update_board(&ctx, channel_id, &mut game, board, &status).await {
+                        eprintln!("Failed to show vote tally: {:?}", e);
+                    }
+                }
+                Err(e) => eprintln!("Failed to render board for vote tally: {:?}", e),
+            }
+        }
+    });
+}
+
+// Make the most popular swap when the voting window `round` closes
+fn schedule_close(ctx: &Context, channel_id: ChannelId, round: u64, window: Duration) {
+    let ctx = ctx.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(window).await;
+        let (game, renderer, encoder) = game_context(&ctx, channel_id).await;
+        let mut game = game.lock().await;
+        if game.puzzle.is_none() || game.solved {
+            return;
+        }
+        let starter = match game.starter {
+            Some(starter) => starter,
+            None => return,
+        };
+        let (guild_id, ((first, second), count, cast)) = match game.votes.as_mut() {
+            Some(votes) if votes.round == round => match votes.close() {
+                Some(result) => (votes.guild_id, result),
+                None => return,
+            },
+            _ => return,
+        };
+
+        let (grid_size, notation) = (game.grid_size, game.board_options.notation);
+        game.last_swap.clear();
+        game.swap(first, second);
+        let status = format!(
+            "The channel voted to swap {} and {} ({} of {} vote{}).",
+            tile_label(first, grid_size, notation),
+            tile_label(second, grid_size, notation),
+            count,
+            cast,
+            if cast == 1 { "" } else { "s" }
+        );
+        if let Some(votes) = game.votes.as_mut() {
+            votes.status = status.clone();
+        }
+        let board_renderer = game.board_renderer(&renderer, encoder);
+        if let Some(board) = game.recreate_scrambled_image(board_renderer).await {
+            match board {
+                Ok(board) => {
+                    if let Err(e) = update_board(&ctx, channel_id, &mut game, board, &status).await {
+                        eprintln!("Failed to show voted swap: {:?}", e);
+                    }
+                }
+                Err(e) => eprintln!("Failed to render board after voted swap: {:?}", e),
+            }
+        }
+
+        if game.tile_positions == game.correct_positions {
+            game.solved = true;
+            if let Err(e) = channel_id.say(&ctx.http, "Congratulations! The channel solved the puzzle!").await {
+                eprintln!("Failed to announce channel plays solve: {:?}", e);
+            }
+            let invoker = Invoker {
+                channel_id,
+                guild_id,
+                user_id: starter,
+            };
+            let points = guild_config(&ctx, guild_id).await.scoring.solve;
+            if let Err(e) = actions::award(&ctx, &invoker, &game, points).await {
+                eprintln!("Failed to award channel plays points: {:?}", e);
+            }
+        }
+    });
+}
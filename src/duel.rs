@@ -0,0 +1,417 @@
+use serde::{Deserialize, Serialize};
+use serenity::builder::CreateActionRow;
+use serenity::model::application::component::ButtonStyle;
+use serenity::model::application::interaction::message_component::MessageComponentInteraction;
+use serenity::model::application::interaction::InteractionResponseType;
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::Notify;
+
+use crate::actions::{ActionResult, Invoker, respond_ephemeral};
+use crate::config::SavedStore;
+use crate::manifest::{MAX_GRID_SIZE, MIN_GRID_SIZE};
+use crate::race::{self, MatchOf, MatchSetup};
+use crate::economy::coins;
+use crate::{ConfigKey, DuelExpiryKey, DuelKey, DuelStatsKey, EconomyKey, RatingsKey};
+
+// Buttons on a challenge are "duel:accept:<id>" and "duel:decline:<id>"
+const DUEL_PREFIX: &str = "duel:";
+const ACCEPT_PREFIX: &str = "duel:accept:";
+const DECLINE_PREFIX: &str = "duel:decline:";
+const MAX_WAGER: u64 = 100_000;
+
+// A challenge waiting for an answer
+pub struct Challenge {
+    channel_id: ChannelId,
+    guild_id: Option<GuildId>,
+    message_id: MessageId,
+    challenger: UserId,
+    opponent: UserId,
+    grid_size: Option<u32>,
+    nsfw_channel: bool,
+    wager: u64, // Coins each player stakes, or 0
+}
+
+// Duels between two players, counted from the first player's side. Players are kept in id order.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct HeadToHead {
+    players: [UserId; 2],
+    wins: [usize; 2],
+    draws: usize,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct DuelData {
+    records: Vec<HeadToHead>,
+}
+
+// Every pair of players' head-to-head records, saved to a JSON file by `keep_saved` after each duel.
+// Ratings are kept with everyone else's, in the rating store.
+pub struct DuelStats {
+    path: PathBuf,
+    data: DuelData,
+    changed: Arc<Notify>,
+}
+
+impl DuelStats {
+    // Load the stats, starting empty if the file does not exist yet
+    pub fn load(path: PathBuf) -> Result<Self, String> {
+        let data = match std::fs::read_to_string(&path) {
+            Ok(json) => serde_json::from_str(&json).map_err(|e| format!("{}: {}", path.display(), e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => DuelData::default(),
+            Err(e) => return Err(format!("{}: {}", path.display(), e)),
+        };
+        Ok(DuelStats {
+            path,
+            data,
+            changed: Arc::new(Notify::new()),
+        })
+    }
+
+    // Wins for `user_id`, wins for `other` and draws between them
+    fn head_to_head(&self, user_id: UserId, other: UserId) -> (usize, usize, usize) {
+        let record = self
+            .data
+            .records
+            .iter()
+            .find(|record| record.players == [user_id.min(other), user_id.max(other)]);
+        match record {
+            Some(record) if record.players[0] == user_id => (record.wins[0], record.wins[1], record.draws),
+            Some(record) => (record.wins[1], record.wins[0], record.draws),
+            None => (0, 0, 0),
+        }
+    }
+
+    // Count a duel. `winner` is None for a draw.
+    fn record(&mut self, players: [UserId; 2], winner: Option<UserId>) {
+        let ordered = [players[0].min(players[1]), players[0].max(players[1])];
+        let index = match self.data.records.iter().position(|record| record.players == ordered) {
+            Some(index) => index,
+            None => {
+                self.data.records.push(HeadToHead {
+                    players: ordered,
+                    wins: [0, 0],
+                    draws: 0,
+                });
+                self.data.records.len() - 1
+            }
+        };
+        let record = &mut self.data.records[index];
+        match winner.and_then(|winner| ordered.iter().position(|&player| player == winner)) {
+            Some(side) => record.wins[side] += 1,
+            None => record.draws += 1,
+        }
+        self.changed.notify_one();
+    }
+}
+
+impl SavedStore for DuelStats {
+    const NAME: &'static str = "duel results";
+
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn changes(&self) -> &Arc<Notify> {
+        &self.changed
+    }
+
+    fn to_json(&self) -> Result<String, String> {
+        serde_json::to_string_pretty(&self.data).map_err(|e| e.to_string())
+    }
+}
+
+fn answer_buttons(duel_id: u64) -> CreateActionRow {
+    let mut row = CreateActionRow::default();
+    row.create_button(|button| {
+        button
+            .label("Accept")
+            .custom_id(format!("{}{}", ACCEPT_PREFIX, duel_id))
+            .style(ButtonStyle::Success)
+    });
+    row.create_button(|button| {
+        button
+            .label("Decline")
+            .custom_id(format!("{}{}", DECLINE_PREFIX, duel_id))
+            .style(ButtonStyle::Danger)
+    });
+    row
+}
+
+// Challenge another player to a head-to-head race, optionally on a board of a given size, as "4" or "4x4",
+// and for a wager of coins from each player that the winner takes. The challenge is taken off the message
+// if it hasn't been answered in time.
+pub async fn challenge(
+    ctx: &Context,
+    invoker: &Invoker,
+    opponent: Option<&User>,
+    size: Option<&str>,
+    wager: Option<&str>,
+) -> ActionResult {
+    let opponent = match opponent {
+        Some(opponent) if opponent.bot => return Ok(Some("Bots don't duel.".to_string())),
+        Some(opponent) if opponent.id == invoker.user_id => return Ok(Some("You can't duel yourself.".to_string())),
+        Some(opponent) => opponent.id,
+        None => return Ok(Some("Mention who you want to duel, e.g. `!duel @someone 4`.".to_string())),
+    };
+    let grid_size = match size.map(|size| size.split(['x', 'X']).next().unwrap_or(size).parse::<u32>()) {
+        None => None,
+        Some(Ok(size)) if (MIN_GRID_SIZE..=MAX_GRID_SIZE).contains(&size) => Some(size),
+        Some(_) => {
+            return Ok(Some(format!(
+                "Board size must be a number from {} to {}.",
+                MIN_GRID_SIZE, MAX_GRID_SIZE
+            )))
+        }
+    };
+    let wager = match (wager.map(str::parse::<u64>), invoker.guild_id) {
+        (None, _) => 0,
+        (Some(_), None) => return Ok(Some("Coins are kept per server, so wagers only work in servers.".to_string())),
+        (Some(Ok(wager)), Some(_)) if (1..=MAX_WAGER).contains(&wager) => wager,
+        (Some(_), Some(_)) => return Ok(Some(format!("A wager must be a number of coins from 1 to {}.", MAX_WAGER))),
+    };
+    if let Some(guild_id) = invoker.guild_id.filter(|_| wager > 0) {
+        let balance = ctx.data.read().await.get::<EconomyKey>().unwrap().balance(guild_id, invoker.user_id);
+        if balance < wager {
+            return Ok(Some(format!("You can't wager {} when you have {}.", coins(wager), coins(balance))));
+        }
+    }
+    let nsfw_channel = match invoker.channel_id.to_channel(ctx).await? {
+        Channel::Guild(channel) => channel.nsfw,
+        _ => false,
+    };
+    let (config, expiry) = {
+        let data = ctx.data.read().await;
+        (data.get::<ConfigKey>().unwrap().get(invoker.guild_id), *data.get::<DuelExpiryKey>().unwrap())
+    };
+    if !config.allows_channel(invoker.channel_id) {
+        return Ok(Some("Puzzles can't be played in this channel.".to_string()));
+    }
+    let already_waiting = ctx.data.read().await.get::<DuelKey>().unwrap().values().any(|pending| {
+        pending.challenger == invoker.user_id && pending.opponent == opponent
+    });
+    if already_waiting {
+        return Ok(Some(format!("You've already challenged {}. Wait for their answer.", opponent.mention())));
+    }
+
+    let duel_id: u64 = rand::random();
+    let text = format!(
+        "**Duel!** {} challenges {} to a head-to-head race{}{}. Both get the same scramble in their direct messages, \
+         and the first to finish wins. {}, answer within {} seconds.",
+        invoker.user_id.mention(),
+        opponent.mention(),
+        grid_size.map_or(String::new(), |size| format!(" on a {}x{} board", size, size)),
+        if wager > 0 { format!(" for {} each, winner takes all", coins(wager)) } else { String::new() },
+        opponent.mention(),
+        expiry.as_secs()
+    );
+    let message = invoker
+        .channel_id
+        .send_message(&ctx.http, |m| {
+            m.content(text)
+                .allowed_mentions(|mentions| mentions.users(vec![opponent]))
+                .components(|c| c.add_action_row(answer_buttons(duel_id)))
+        })
+        .await?;
+    ctx.data.write().await.get_mut::<DuelKey>().unwrap().insert(
+        duel_id,
+        Challenge {
+            channel_id: invoker.channel_id,
+            guild_id: invoker.guild_id,
+            message_id: message.id,
+            challenger: invoker.user_id,
+            opponent,
+            grid_size,
+            nsfw_channel,
+            wager,
+        },
+    );
+
+    tokio::time::sleep(expiry).await;
+    let expired = ctx.data.write().await.get_mut::<DuelKey>().unwrap().remove(&duel_id);
+    if let Some(expired) = expired {
+        let text = format!(
+            "**Duel!** {} didn't answer {}'s challenge in time.",
+            expired.opponent.mention(),
+            expired.challenger.mention()
+        );
+        expired
+            .channel_id
+            .edit_message(&ctx.http, expired.message_id, |m| {
+                m.content(text).allowed_mentions(|mentions| mentions.empty_parse()).components(|c| c)
+            })
+            .await?;
+    }
+    Ok(None)
+}
+
+pub fn is_duel_button(custom_id: &str) -> bool {
+    custom_id.starts_with(DUEL_PREFIX)
+}
+
+// Accept or Decline on a challenge. Only the challenged player can answer, though the challenger
+// may take the challenge back with Decline.
+pub async fn handle_component(ctx: &Context, component: &MessageComponentInteraction) {
+    let custom_id = component.data.custom_id.as_str();
+    let (duel_id, accepting) = match (
+        custom_id.strip_prefix(ACCEPT_PREFIX).and_then(|id| id.parse::<u64>().ok()),
+        custom_id.strip_prefix(DECLINE_PREFIX).and_then(|id| id.parse::<u64>().ok()),
+    ) {
+        (Some(id), _) => (id, true),
+        (_, Some(id)) => (id, false),
+        _ => return,
+    };
+    let user_id = component.user.id;
+
+    let pending = {
+        let mut data = ctx.data.write().await;
+        let answering = data
+            .get::<DuelKey>()
+            .unwrap()
+            .get(&duel_id)
+            .map(|pending| (pending.challenger, pending.opponent, pending.guild_id, pending.wager));
+        let may_answer = answering
+            .is_some_and(|(challenger, opponent, _, _)| user_id == opponent || (user_id == challenger && !accepting));
+        match answering {
+            None => None,
+            Some((_, opponent, _, _)) if !may_answer => {
+                let text = format!("Only {} can answer this challenge.", opponent.mention());
+                drop(data);
+                return respond_ephemeral(ctx, component, &text).await;
+            }
+            Some((challenger, opponent, Some(guild_id), wager)) if accepting && wager > 0 => {
+                // Both wagers are taken in one step, so neither player can stake coins they've spent since
+                let held = data
+                    .get_mut::<EconomyKey>()
+                    .unwrap()
+                    .hold(duel_id, guild_id, &[challenger, opponent], wager, "Duel wager");
+                if let Err(short) = held {
+                    let text = if short == opponent {
+                        format!("You need {} to accept this wager.", coins(wager))
+                    } else {
+                        format!("{} no longer has {} to cover the wager.", challenger.mention(), coins(wager))
+                    };
+                    drop(data);
+                    return respond_ephemeral(ctx, component, &text).await;
+                }
+                data.get_mut::<DuelKey>().unwrap().remove(&duel_id)
+            }
+            Some(_) => data.get_mut::<DuelKey>().unwrap().remove(&duel_id),
+        }
+    };
+    let pending = match pending {
+        Some(pending) => pending,
+        None => return respond_ephemeral(ctx, component, "This challenge has already been answered.").await,
+    };
+
+    let text = match (accepting, user_id == pending.challenger) {
+        (true, _) => format!(
+            "**Duel!** {} accepted {}'s challenge. Your boards are in your direct messages.",
+            pending.opponent.mention(),
+            pending.challenger.mention()
+        ),
+        (false, true) => format!("**Duel!** {} took back their challenge.", pending.challenger.mention()),
+        (false, false) => format!(
+            "**Duel!** {} declined {}'s challenge.",
+            pending.opponent.mention(),
+            pending.challenger.mention()
+        ),
+    };
+    if let Err(e) = component
+        .create_interaction_response(&ctx.http, |response| {
+            response.kind(InteractionResponseType::UpdateMessage).interaction_response_data(|message| {
+                message
+                    .content(text)
+                    .allowed_mentions(|mentions| mentions.empty_parse())
+                    .components(|c| c)
+            })
+        })
+        .await
+    {
+        eprintln!("Failed to answer duel challenge: {:?}", e);
+    }
+    if !accepting {
+        return;
+    }
+
+    let setup = MatchSetup {
+        channel_id: pending.channel_id,
+        guild_id: pending.guild_id,
+        nsfw_channel: pending.nsfw_channel,
+        filter: None,
+        racers: [pending.challenger, pending.opponent],
+        title: "Duel".to_string(),
+        grid_size: pending.grid_size,
+        match_of: MatchOf::Duel(duel_id),
+    };
+    if let Err(e) = race::start_match(ctx, setup).await {
+        eprintln!("Failed to start duel: {:?}", e);
+        ctx.data.write().await.get_mut::<EconomyKey>().unwrap().settle(duel_id, None, "Duel wager returned");
+        if let Err(e) = pending.channel_id.say(&ctx.http, "Failed to start the duel. Please try again.").await {
+            eprintln!("Failed to report duel error: {:?}", e);
+        }
+    }
+}
+
+// A duel race ended. `winner` is None when neither player finished, which counts as a draw and hands
+// back any wagers.
+pub async fn report(ctx: &Context, channel_id: ChannelId, duel_id: u64, players: [UserId; 2], winner: Option<UserId>) {
+    let text = {
+        let mut data = ctx.data.write().await;
+        let reason = if winner.is_some() { "Won a duel" } else { "Duel wager returned" };
+        let pot = data.get_mut::<EconomyKey>().unwrap().settle(duel_id, winner, reason);
+        let stats = data.get_mut::<DuelStatsKey>().unwrap();
+        stats.record(players, winner);
+
+        let mut text = match winner {
+            Some(winner) => {
+                let loser = if winner == players[0] { players[1] } else { players[0] };
+                format!("**Duel result:** {} beat {}.", winner.mention(), loser.mention())
+            }
+            None => format!("**Duel result:** a draw between {} and {}.", players[0].mention(), players[1].mention()),
+        };
+        let (wins, losses, draws) = stats.head_to_head(players[0], players[1]);
+        text.push_str(&format!(
+            "\nHead to head: {} {}, {} {}, {} draw{}",
+            players[0].mention(),
+            wins,
+            players[1].mention(),
+            losses,
+            draws,
+            if draws == 1 { "" } else { "s" }
+        ));
+        match (pot, winner) {
+            (Some(pot), Some(winner)) => text.push_str(&format!("\n{} takes the pot of {}.", winner.mention(), coins(pot))),
+            (Some(_), None) => text.push_str("\nBoth wagers were handed back."),
+            (None, _) => {}
+        }
+        text
+    };
+    if let Err(e) = channel_id
+        .send_message(&ctx.http, |m| m.content(text).allowed_mentions(|mentions| mentions.empty_parse()))
+        .await
+    {
+        eprintln!("Failed to post duel result: {:?}", e);
+    }
+}
+
+// A player's rating, and their duel record against another player if one is given
+pub async fn record(ctx: &Context, user_id: UserId, other: Option<UserId>) -> ActionResult {
+    let data = ctx.data.read().await;
+    let stats = data.get::<DuelStatsKey>().unwrap();
+    let rating = data.get::<RatingsKey>().unwrap().player(user_id);
+    let mut text = format!("{}'s rating: {:.0}", user_id.mention(), rating.value);
+    if let Some(other) = other.filter(|&other| other != user_id) {
+        let (wins, losses, draws) = stats.head_to_head(user_id, other);
+        text.push_str(&format!(
+            "\nAgainst {}: {} won, {} lost, {} drawn",
+            other.mention(),
+            wins,
+            losses,
+            draws
+        ));
+    }
+    Ok(Some(text))
+}
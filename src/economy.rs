@@ -0,0 +1,595 @@
+use serde::{Deserialize, Serialize};
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::Notify;
+
+use crate::actions::{ActionResult, Invoker};
+use crate::config::{save_json, SavedStore};
+use crate::theme::THEME_NAMES;
+use crate::{guild_config, EconomyKey};
+
+// Coins paid for every point scored
+pub const COINS_PER_POINT: u64 = 10;
+const DAILY_COINS: u64 = 50;
+// Extra coins for each day in a row a daily reward is claimed, up to a week's worth
+const STREAK_BONUS: u64 = 10;
+const MAX_STREAK_BONUS_DAYS: u32 = 7;
+// Transactions listed by `!coins history`
+const HISTORY_LENGTH: usize = 10;
+// Transactions kept per player. Older ones are dropped so the file stays small.
+const LEDGER_LENGTH: usize = 50;
+// Largest single grant or revoke, so a typo can't wreck a server's economy
+const MAX_ADJUSTMENT: u64 = 1_000_000;
+
+// "1 coin" or "20 coins"
+pub fn coins(amount: u64) -> String {
+    if amount == 1 {
+        "1 coin".to_string()
+    } else {
+        format!("{} coins", amount)
+    }
+}
+
+// A price, which may be nothing
+pub fn price(amount: u64) -> String {
+    if amount == 0 {
+        "free".to_string()
+    } else {
+        coins(amount)
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs())
+}
+
+// One player's coins in one server
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct Wallet {
+    balance: u64,
+    last_daily: Option<u64>, // Day number (days since 1970) of the last daily reward
+    streak: u32,
+    themes: Vec<String>, // Themes bought for their own puzzles
+    theme: Option<String>, // The one in use, if any
+    ledger: VecDeque<Transaction>, // Latest changes to the balance, oldest first
+}
+
+// Every change to a balance, kept so admins and players can see where coins came from
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Transaction {
+    id: u64,
+    time: u64, // Unix seconds
+    amount: i64,
+    balance: u64, // After the change
+    reason: String,
+}
+
+// Coins taken from players and held until a result pays them out, e.g. a duel's wagers
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Stake {
+    guild_id: GuildId,
+    players: Vec<UserId>,
+    amount: u64, // From each player
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct EconomyData {
+    wallets: HashMap<GuildId, HashMap<UserId, Wallet>>,
+    stakes: HashMap<u64, Stake>,
+    next_id: u64,
+}
+
+// Every server's coin balances and the latest transactions behind them, saved to a JSON file by
+// `keep_saved` after changes. Balances and the ledger are written together, so they always agree.
+// Callers hold the data lock for writing while they use the store, so each check of a balance and
+// the change that depends on it happen together and two interactions can never spend the same coins.
+pub struct Economy {
+    path: PathBuf,
+    data: EconomyData,
+    changed: Arc<Notify>,
+}
+
+impl Economy {
+    // Load the economy, starting empty if the file does not exist yet. Wagers still held when the
+    // bot stopped belong to matches that can no longer finish, so they are handed back.
+    pub fn load(path: PathBuf) -> Result<Self, String> {
+        let data = match std::fs::read_to_string(&path) {
+            Ok(json) => serde_json::from_str(&json).map_err(|e| format!("{}: {}", path.display(), e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => EconomyData::default(),
+            Err(e) => return Err(format!("{}: {}", path.display(), e)),
+        };
+        let mut economy = Economy {
+            path,
+            data,
+            changed: Arc::new(Notify::new()),
+        };
+        let stakes: Vec<u64> = economy.data.stakes.keys().copied().collect();
+        if !stakes.is_empty() {
+            for stake_id in stakes {
+                economy.refund(stake_id, "Wager returned after a restart");
+            }
+            save_json(&economy.path, &economy.data)?;
+        }
+        Ok(economy)
+    }
+
+    fn wallet(&self, guild_id: GuildId, user_id: UserId) -> Option<&Wallet> {
+        self.data.wallets.get(&guild_id)?.get(&user_id)
+    }
+
+    fn wallet_mut(&mut self, guild_id: GuildId, user_id: UserId) -> &mut Wallet {
+        self.data.wallets.entry(guild_id).or_default().entry(user_id).or_default()
+    }
+
+    pub fn balance(&self, guild_id: GuildId, user_id: UserId) -> u64 {
+        self.wallet(guild_id, user_id).map_or(0, |wallet| wallet.balance)
+    }
+
+    // The theme a player has chosen for their own puzzles
+    pub fn theme(&self, guild_id: GuildId, user_id: UserId) -> Option<&str> {
+        self.wallet(guild_id, user_id)?.theme.as_deref()
+    }
+
+    // A player's latest transactions, newest first
+    fn history(&self, guild_id: GuildId, user_id: UserId) -> Vec<&Transaction> {
+        self.wallet(guild_id, user_id)
+            .map(|wallet| wallet.ledger.iter().rev().take(HISTORY_LENGTH).collect())
+            .unwrap_or_default()
+    }
+
+    // Change a balance and write it in the ledger. A change that would take the balance below
+    // zero is refused with the balance as it stands.
+    fn apply(&mut self, guild_id: GuildId, user_id: UserId, amount: i64, reason: &str) -> Result<u64, u64> {
+        self.data.next_id += 1;
+        let id = self.data.next_id;
+        let wallet = self.wallet_mut(guild_id, user_id);
+        let balance = match amount {
+            0.. => wallet.balance.saturating_add(amount.unsigned_abs()),
+            _ => wallet.balance.checked_sub(amount.unsigned_abs()).ok_or(wallet.balance)?,
+        };
+        wallet.balance = balance;
+        if wallet.ledger.len() >= LEDGER_LENGTH {
+            wallet.ledger.pop_front();
+        }
+        wallet.ledger.push_back(Transaction {
+            id,
+            time: now(),
+            amount,
+            balance,
+            reason: reason.to_string(),
+        });
+        Ok(balance)
+    }
+
+    // Pay a player. Returns their new balance.
+    pub fn credit(&mut self, guild_id: GuildId, user_id: UserId, amount: u64, reason: &str) -> u64 {
+        if amount == 0 {
+            return self.balance(guild_id, user_id);
+        }
+        let balance = self.apply(guild_id, user_id, amount as i64, reason).unwrap_or_default();
+        self.changed.notify_one();
+        balance
+    }
+
+    // Take coins from a player. Returns their new balance, or their balance as it stands if they
+    // can't afford it, in which case nothing is taken.
+    pub fn debit(&mut self, guild_id: GuildId, user_id: UserId, amount: u64, reason: &str) -> Result<u64, u64> {
+        if amount == 0 {
+            return Ok(self.balance(guild_id, user_id));
+        }
+        let balance = self.apply(guild_id, user_id, -(amount as i64), reason)?;
+        self.changed.notify_one();
+        Ok(balance)
+    }
+
+    // Pay today's daily reward, with a bonus for each day in a row. Returns the reward, the
+    // streak and the new balance, or the seconds until the next reward if today's was claimed.
+    fn claim_daily(&mut self, guild_id: GuildId, user_id: UserId) -> Result<(u64, u32, u64), u64> {
+        let time = now();
+        let today = time / 86_400;
+        let wallet = self.wallet_mut(guild_id, user_id);
+        let streak = match wallet.last_daily {
+            Some(day) if day >= today => return Err((today + 1) * 86_400 - time),
+            Some(day) if day + 1 == today => wallet.streak + 1,
+            _ => 1,
+        };
+        wallet.last_daily = Some(today);
+        wallet.streak = streak;
+        let reward = DAILY_COINS + STREAK_BONUS * u64::from((streak - 1).min(MAX_STREAK_BONUS_DAYS));
+        let reason = if streak > 1 {
+            format!("Daily reward, {}-day streak", streak)
+        } else {
+            "Daily reward".to_string()
+        };
+        let balance = self.apply(guild_id, user_id, reward as i64, &reason).unwrap_or_default();
+        self.changed.notify_one();
+        Ok((reward, streak, balance))
+    }
+
+    // Take `amount` from each player and hold it under `stake_id`. Nothing is taken unless every
+    // player can afford it; otherwise the first who can't is returned.
+    // A player listed twice is only charged once.
+    pub fn hold(&mut self, stake_id: u64, guild_id: GuildId, players: &[UserId], amount: u64, reason: &str) -> Result<(), UserId> {
+        let mut unique: Vec<UserId> = Vec::new();
+        for &user_id in players {
+            if !unique.contains(&user_id) {
+                unique.push(user_id);
+            }
+        }
+        if let Some(&short) = unique.iter().find(|&&user_id| self.balance(guild_id, user_id) < amount) {
+            return Err(short);
+        }
+        for (index, &user_id) in unique.iter().enumerate() {
+            if self.apply(guild_id, user_id, -(amount as i64), reason).is_err() {
+                // Hand back what was already taken
+                for &paid in &unique[..index] {
+                    self.apply(guild_id, paid, amount as i64, "Wager returned").ok();
+                }
+                self.changed.notify_one();
+                return Err(user_id);
+            }
+        }
+        self.data.stakes.insert(
+            stake_id,
+            Stake {
+                guild_id,
+                players: unique,
+                amount,
+            },
+        );
+        self.changed.notify_one();
+        Ok(())
+    }
+
+    // Pay everything held under `stake_id` to the winner, or hand each player's share back if
+    // there is none. Returns the pot, or None if nothing was held.
+    pub fn settle(&mut self, stake_id: u64, winner: Option<UserId>, reason: &str) -> Option<u64> {
+        let stake = self.data.stakes.get(&stake_id)?;
+        let pot = stake.amount * stake.players.len() as u64;
+        match winner.filter(|winner| stake.players.contains(winner)) {
+            Some(winner) => {
+                let guild_id = stake.guild_id;
+                self.data.stakes.remove(&stake_id);
+                self.apply(guild_id, winner, pot as i64, reason).ok();
+            }
+            None => self.refund(stake_id, reason),
+        }
+        self.changed.notify_one();
+        Some(pot)
+    }
+
+    fn refund(&mut self, stake_id: u64, reason: &str) {
+        if let Some(stake) = self.data.stakes.remove(&stake_id) {
+            for user_id in stake.players {
+                self.apply(stake.guild_id, user_id, stake.amount as i64, reason).ok();
+            }
+        }
+    }
+
+    // Buy a theme for a player's own puzzles and start using it
+    fn buy_theme(&mut self, guild_id: GuildId, user_id: UserId, name: &str, price: u64) -> Result<u64, String> {
+        if self.wallet(guild_id, user_id).is_some_and(|wallet| wallet.themes.iter().any(|theme| theme == name)) {
+            return Err(format!("You already own the {} theme. Use it with `shop use {}`.", name, name));
+        }
+        let reason = format!("Bought the {} theme", name);
+        let balance = self
+            .apply(guild_id, user_id, -(price as i64), &reason)
+            .map_err(|balance| format!("The {} theme costs {}, but you have {}.", name, coins(price), coins(balance)))?;
+        let wallet = self.wallet_mut(guild_id, user_id);
+        wallet.themes.push(name.to_string());
+        wallet.theme = Some(name.to_string());
+        self.changed.notify_one();
+        Ok(balance)
+    }
+
+    // Switch to a theme the player owns, or back to the server's with None
+    fn use_theme(&mut self, guild_id: GuildId, user_id: UserId, name: Option<&str>) -> Result<(), String> {
+        let wallet = self.wallet_mut(guild_id, user_id);
+        if let Some(name) = name {
+            if !wallet.themes.iter().any(|theme| theme == name) {
+                return Err(format!("You don't own the {} theme yet. Buy it with `shop buy {}`.", name, name));
+            }
+        }
+        wallet.theme = name.map(str::to_string);
+        self.changed.notify_one();
+        Ok(())
+    }
+}
+
+impl SavedStore for Economy {
+    const NAME: &'static str = "coins";
+
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn changes(&self) -> &Arc<Notify> {
+        &self.changed
+    }
+
+    fn to_json(&self) -> Result<String, String> {
+        serde_json::to_string_pretty(&self.data).map_err(|e| e.to_string())
+    }
+}
+
+const SERVER_ONLY: &str = "Coins are kept per server, so use this in a server.";
+
+// Only members who can manage the server may grant or revoke coins
+async fn is_admin(ctx: &Context, guild_id: GuildId, user_id: UserId) -> bool {
+    match guild_id.member(ctx, user_id).await {
+        Ok(member) => member.permissions(&ctx.cache).is_ok_and(|permissions| permissions.manage_guild()),
+        Err(_) => false,
+    }
+}
+
+pub async fn balance(ctx: &Context, guild_id: Option<GuildId>, user_id: UserId) -> ActionResult {
+    let guild_id = match guild_id {
+        Some(guild_id) => guild_id,
+        None => return Ok(Some(SERVER_ONLY.to_string())),
+    };
+    let data = ctx.data.read().await;
+    let economy = data.get::<EconomyKey>().unwrap();
+    let mut text = format!("{} has {}.", user_id.mention(), coins(economy.balance(guild_id, user_id)));
+    if let Some(wallet) = economy.wallet(guild_id, user_id).filter(|wallet| wallet.streak > 1) {
+        text.push_str(&format!(" Daily streak: {} days.", wallet.streak));
+    }
+    Ok(Some(text))
+}
+
+pub async fn history(ctx: &Context, guild_id: Option<GuildId>, user_id: UserId) -> ActionResult {
+    let guild_id = match guild_id {
+        Some(guild_id) => guild_id,
+        None => return Ok(Some(SERVER_ONLY.to_string())),
+    };
+    let data = ctx.data.read().await;
+    let transactions = data.get::<EconomyKey>().unwrap().history(guild_id, user_id);
+    if transactions.is_empty() {
+        return Ok(Some(format!("{} has no transactions yet.", user_id.mention())));
+    }
+    let mut text = format!("**{}'s latest transactions**", user_id.mention());
+    for transaction in transactions {
+        text.push_str(&format!(
+            "\n`{:+}` {}, <t:{}:R> (balance {})",
+            transaction.amount, transaction.reason, transaction.time, transaction.balance
+        ));
+    }
+    Ok(Some(text))
+}
+
+pub async fn daily(ctx: &Context, invoker: &Invoker) -> ActionResult {
+    let guild_id = match invoker.guild_id {
+        Some(guild_id) => guild_id,
+        None => return Ok(Some(SERVER_ONLY.to_string())),
+    };
+    let claimed = ctx.data.write().await.get_mut::<EconomyKey>().unwrap().claim_daily(guild_id, invoker.user_id);
+    let text = match claimed {
+        Ok((reward, 1, balance)) => format!("You claimed {}. You now have {}.", coins(reward), coins(balance)),
+        Ok((reward, streak, balance)) => format!(
+            "You claimed {} for a {}-day streak. You now have {}.",
+            coins(reward),
+            streak,
+            coins(balance)
+        ),
+        Err(wait) => format!(
+            "You've already claimed today's reward. The next one is ready <t:{}:R>.",
+            now() + wait
+        ),
+    };
+    Ok(Some(text))
+}
+
+// Charge a player for something they buy. Returns a receipt to add to the reply, or why they can't
+// have it. Free things, and anything outside a server, cost nothing.
+pub async fn pay(ctx: &Context, guild_id: Option<GuildId>, user_id: UserId, amount: u64, item: &str) -> Result<String, String> {
+    let guild_id = match guild_id {
+        Some(guild_id) if amount > 0 => guild_id,
+        _ => return Ok(String::new()),
+    };
+    match ctx.data.write().await.get_mut::<EconomyKey>().unwrap().debit(guild_id, user_id, amount, item) {
+        Ok(balance) => Ok(format!(" You paid {} and have {} left.", coins(amount), coins(balance))),
+        Err(balance) => Err(format!(
+            "{} costs {}, but you have {}. Earn coins by solving puzzles or with `daily`.",
+            item,
+            coins(amount),
+            coins(balance)
+        )),
+    }
+}
+
+// Give back what `pay` took when the purchase couldn't be delivered
+pub async fn refund(ctx: &Context, guild_id: Option<GuildId>, user_id: UserId, amount: u64, item: &str) {
+    if let Some(guild_id) = guild_id.filter(|_| amount > 0) {
+        let reason = format!("Refund: {}", item);
+        ctx.data.write().await.get_mut::<EconomyKey>().unwrap().credit(guild_id, user_id, amount, &reason);
+    }
+}
+
+// `grant` or `revoke` coins, with an optional reason written in the ledger
+pub async fn adjust(ctx: &Context, invoker: &Invoker, granting: bool, target: Option<UserId>, amount: &str, reason: &str) -> ActionResult {
+    let guild_id = match invoker.guild_id {
+        Some(guild_id) => guild_id,
+        None => return Ok(Some(SERVER_ONLY.to_string())),
+    };
+    if !is_admin(ctx, guild_id, invoker.user_id).await {
+        return Ok(Some("Only members with Manage Server can grant or revoke coins.".to_string()));
+    }
+    let target = match target {
+        Some(target) => target,
+        None => return Ok(Some("Mention who to give or take coins from, e.g. `coins grant @someone 100`.".to_string())),
+    };
+    let amount = match amount.parse::<u64>() {
+        Ok(amount) if (1..=MAX_ADJUSTMENT).contains(&amount) => amount,
+        _ => return Ok(Some(format!("The amount must be a number from 1 to {}.", MAX_ADJUSTMENT))),
+    };
+    let reason = match (granting, reason.trim()) {
+        (true, "") => format!("Granted by {}", invoker.user_id.mention()),
+        (false, "") => format!("Revoked by {}", invoker.user_id.mention()),
+        (true, reason) => format!("Granted by {}: {}", invoker.user_id.mention(), reason),
+        (false, reason) => format!("Revoked by {}: {}", invoker.user_id.mention(), reason),
+    };
+
+    let mut data = ctx.data.write().await;
+    let economy = data.get_mut::<EconomyKey>().unwrap();
+    let text = if granting {
+        let balance = economy.credit(guild_id, target, amount, &reason);
+        format!("Gave {} {}. They now have {}.", target.mention(), coins(amount), coins(balance))
+    } else {
+        match economy.debit(guild_id, target, amount, &reason) {
+            Ok(balance) => format!("Took {} from {}. They now have {}.", coins(amount), target.mention(), coins(balance)),
+            Err(balance) => format!(
+                "{} only has {}, and balances can't go below zero.",
+                target.mention(),
+                coins(balance)
+            ),
+        }
+    };
+    Ok(Some(text))
+}
+
+// `shop` lists the themes for sale, `shop buy <theme>` buys one and `shop use <theme|server>` switches
+pub async fn shop(ctx: &Context, invoker: &Invoker, action: &str, name: &str) -> ActionResult {
+    let guild_id = match invoker.guild_id {
+        Some(guild_id) => guild_id,
+        None => return Ok(Some(SERVER_ONLY.to_string())),
+    };
+    let prices = guild_config(ctx, Some(guild_id)).await.prices;
+    let name = name.trim().to_lowercase();
+    if !matches!(action, "" | "list" | "buy" | "use") {
+        return Ok(Some("Use `shop`, `shop buy <theme>` or `shop use <theme|server>`.".to_string()));
+    }
+    let listing = matches!(action, "" | "list");
+    let known = THEME_NAMES.contains(&name.as_str()) || (action == "use" && name == "server");
+    if !listing && name.is_empty() {
+        return Ok(Some(format!("Name a theme: {}.", THEME_NAMES.join(", "))));
+    }
+    if !listing && !known {
+        return Ok(Some(format!("There is no {} theme. Pick one of {}.", name, THEME_NAMES.join(", "))));
+    }
+
+    let mut data = ctx.data.write().await;
+    let economy = data.get_mut::<EconomyKey>().unwrap();
+    let text = match action {
+        "" | "list" => {
+            let wallet = economy.wallet(guild_id, invoker.user_id).cloned().unwrap_or_default();
+            let themes: Vec<_> = THEME_NAMES
+                .iter()
+                .map(|&theme| {
+                    if wallet.theme.as_deref() == Some(theme) {
+                        format!("{} (in use)", theme)
+                    } else if wallet.themes.iter().any(|owned| owned == theme) {
+                        format!("{} (owned)", theme)
+                    } else {
+                        theme.to_string()
+                    }
+                })
+                .collect();
+            format!(
+                "**Shop**\nBoard themes for the puzzles you start, {} each: {}\nHint: {}\nPeek at the picture: {}\nYou have {}.",
+                price(prices.theme),
+                themes.join(", "),
+                price(prices.hint),
+                price(prices.peek),
+                coins(wallet.balance)
+            )
+        }
+        "buy" => match economy.buy_theme(guild_id, invoker.user_id, &name, prices.theme) {
+            Ok(balance) => format!(
+                "You bought the {} theme, and puzzles you start will use it. You have {} left.",
+                name,
+                coins(balance)
+            ),
+            Err(e) => e,
+        },
+        _ => {
+            let theme = (name != "server").then_some(name.as_str());
+            match economy.use_theme(guild_id, invoker.user_id, theme) {
+                Ok(()) if theme.is_none() => "Puzzles you start will use the server's theme.".to_string(),
+                Ok(()) => format!("Puzzles you start will use the {} theme.", name),
+                Err(e) => e,
+            }
+        }
+    };
+    Ok(Some(text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GUILD: GuildId = GuildId(1);
+    const ALICE: UserId = UserId(1);
+    const BOB: UserId = UserId(2);
+    const CAROL: UserId = UserId(3);
+
+    fn economy() -> Economy {
+        Economy {
+            path: PathBuf::new(),
+            data: EconomyData::default(),
+            changed: Arc::new(Notify::new()),
+        }
+    }
+
+    #[test]
+    fn debit_never_goes_below_zero() {
+        let mut economy = economy();
+        economy.credit(GUILD, ALICE, 30, "Test");
+        assert_eq!(economy.debit(GUILD, ALICE, 31, "Test"), Err(30));
+        assert_eq!(economy.balance(GUILD, ALICE), 30);
+        assert_eq!(economy.history(GUILD, ALICE).len(), 1);
+        assert_eq!(economy.debit(GUILD, ALICE, 30, "Test"), Ok(0));
+    }
+
+    #[test]
+    fn duplicate_player_is_charged_once() {
+        let mut economy = economy();
+        economy.credit(GUILD, ALICE, 100, "Test");
+        economy.hold(7, GUILD, &[ALICE, ALICE], 40, "Wager").unwrap();
+        assert_eq!(economy.balance(GUILD, ALICE), 60);
+        assert_eq!(economy.settle(7, Some(ALICE), "Won"), Some(40));
+        assert_eq!(economy.balance(GUILD, ALICE), 100);
+    }
+
+    #[test]
+    fn failed_hold_takes_nothing() {
+        let mut economy = economy();
+        economy.credit(GUILD, ALICE, 100, "Test");
+        economy.credit(GUILD, BOB, 100, "Test");
+        economy.credit(GUILD, CAROL, 10, "Test");
+        assert_eq!(economy.hold(7, GUILD, &[ALICE, BOB, CAROL], 50, "Wager"), Err(CAROL));
+        assert_eq!(economy.balance(GUILD, ALICE), 100);
+        assert_eq!(economy.balance(GUILD, BOB), 100);
+        assert_eq!(economy.balance(GUILD, CAROL), 10);
+        assert_eq!(economy.settle(7, Some(ALICE), "Won"), None);
+    }
+
+    #[test]
+    fn settle_without_winner_refunds_everyone() {
+        let mut economy = economy();
+        economy.credit(GUILD, ALICE, 100, "Test");
+        economy.credit(GUILD, BOB, 100, "Test");
+        economy.hold(7, GUILD, &[ALICE, BOB], 25, "Wager").unwrap();
+        assert_eq!(economy.settle(7, None, "Draw"), Some(50));
+        assert_eq!(economy.balance(GUILD, ALICE), 100);
+        assert_eq!(economy.balance(GUILD, BOB), 100);
+        assert_eq!(economy.settle(7, None, "Draw"), None);
+    }
+
+    #[test]
+    fn ledger_keeps_the_latest_transactions() {
+        let mut economy = economy();
+        for amount in 1..=LEDGER_LENGTH as u64 + 5 {
+            economy.credit(GUILD, ALICE, amount, "Test");
+        }
+        let ledger = &economy.wallet(GUILD, ALICE).unwrap().ledger;
+        assert_eq!(ledger.len(), LEDGER_LENGTH);
+        assert_eq!(ledger.front().unwrap().amount, 6);
+        assert_eq!(ledger.back().unwrap().amount, LEDGER_LENGTH as i64 + 5);
+        assert_eq!(economy.history(GUILD, ALICE).len(), HISTORY_LENGTH);
+    }
+}
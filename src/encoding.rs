@@ -0,0 +1,144 @@
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView, ImageEncoder, ImageError};
+use std::fmt;
+
+// Discord's upload limit for servers without boosts, with some headroom for the request body
+pub const DEFAULT_MAX_BYTES: usize = 8 * 1024 * 1024;
+
+// Never shrink boards below this size on their shorter side, labels become unreadable
+const MIN_DIMENSION: u32 = 240;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Png,
+    Jpeg,
+    // Lossless only; lossy WebP needs libwebp, which the image crate does not bundle
+    WebP,
+}
+
+impl OutputFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Png => "png",
+            OutputFormat::Jpeg => "jpg",
+            OutputFormat::WebP => "webp",
+        }
+    }
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "png" => Ok(OutputFormat::Png),
+            "jpeg" | "jpg" => Ok(OutputFormat::Jpeg),
+            "webp" => Ok(OutputFormat::WebP),
+            _ => Err(format!("unknown image format \"{}\" (use png, jpeg or webp)", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct EncoderSettings {
+    pub format: OutputFormat,
+    // JPEG quality from 1 to 100; PNG and WebP are always lossless
+    pub quality: u8,
+    // Largest upload we will produce; images are downscaled until they fit
+    pub max_bytes: usize,
+}
+
+impl Default for EncoderSettings {
+    fn default() -> Self {
+        EncoderSettings {
+            format: OutputFormat::Png,
+            quality: 85,
+            max_bytes: DEFAULT_MAX_BYTES,
+        }
+    }
+}
+
+pub struct EncodedImage {
+    pub bytes: Vec<u8>,
+    pub format: OutputFormat,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Debug)]
+pub enum EncodeError {
+    Image(ImageError),
+    // Even the smallest allowed size did not fit the byte budget
+    OverBudget { size: usize, max_bytes: usize },
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncodeError::Image(e) => write!(f, "failed to encode image: {}", e),
+            EncodeError::OverBudget { size, max_bytes } => write!(
+                f,
+                "encoded image is {} bytes, over the {} byte upload budget",
+                size, max_bytes
+            ),
+        }
+    }
+}
+
+impl std::error::Error for EncodeError {}
+
+impl From<ImageError> for EncodeError {
+    fn from(e: ImageError) -> Self {
+        EncodeError::Image(e)
+    }
+}
+
+fn encode_once(image: &DynamicImage, settings: &EncoderSettings) -> Result<Vec<u8>, ImageError> {
+    let mut bytes = Vec::new();
+    let (width, height) = image.dimensions();
+    match settings.format {
+        OutputFormat::Png => {
+            let rgba = image.to_rgba8();
+            PngEncoder::new(&mut bytes).write_image(&rgba, width, height, image::ColorType::Rgba8)?;
+        }
+        OutputFormat::Jpeg => {
+            // JPEG has no alpha channel
+            let rgb = image.to_rgb8();
+            JpegEncoder::new_with_quality(&mut bytes, settings.quality.clamp(1, 100))
+                .write_image(&rgb, width, height, image::ColorType::Rgb8)?;
+        }
+        OutputFormat::WebP => {
+            let rgba = image.to_rgba8();
+            WebPEncoder::new_lossless(&mut bytes).write_image(&rgba, width, height, image::ColorType::Rgba8)?;
+        }
+    }
+    Ok(bytes)
+}
+
+// Encode `image`, downscaling step by step until the result fits `settings.max_bytes`
+pub fn encode(image: &DynamicImage, settings: &EncoderSettings) -> Result<EncodedImage, EncodeError> {
+    let mut current = image.clone();
+    loop {
+        let bytes = encode_once(&current, settings)?;
+        let (width, height) = current.dimensions();
+        if bytes.len() <= settings.max_bytes {
+            return Ok(EncodedImage { bytes, format: settings.format, width, height });
+        }
+        if width.min(height) <= MIN_DIMENSION {
+            return Err(EncodeError::OverBudget { size: bytes.len(), max_bytes: settings.max_bytes });
+        }
+
+        // Encoded size scales roughly with pixel count, so shrink each side by the square root
+        // of the overshoot, with a little extra so we rarely need another pass
+        let ratio = (settings.max_bytes as f64 / bytes.len() as f64).sqrt() * 0.9;
+        let ratio = ratio.clamp(0.25, 0.9);
+        let min_ratio = MIN_DIMENSION as f64 / width.min(height) as f64;
+        let ratio = ratio.max(min_ratio);
+        let new_width = ((width as f64 * ratio).round() as u32).max(1);
+        let new_height = ((height as f64 * ratio).round() as u32).max(1);
+        current = current.resize_exact(new_width, new_height, FilterType::Triangle);
+    }
+}
@@ -0,0 +1,322 @@
+use serenity::builder::CreateEmbed;
+
+use crate::board::{self, Notation};
+use crate::config::GuildConfig;
+
+// Embed accent, Discord's blurple
+const HELP_COLOUR: u32 = 0x5865F2;
+
+// One command's entry in `!help`. In `usage` and `examples`, `{p}` stands for the server's
+// prefix, `{a}` and `{b}` for two tiles on the server's default grid.
+struct HelpEntry {
+    category: &'static str,
+    name: &'static str,
+    usage: &'static str,
+    slash: Option<&'static str>,
+    description: &'static str,
+    examples: &'static [&'static str],
+}
+
+const CATEGORIES: [&str; 6] = ["Playing", "Game modes", "Competitive", "Coins", "Your board", "Server admins"];
+
+const COMMANDS: &[HelpEntry] = &[
+    HelpEntry {
+        category: "Playing",
+        name: "start_picture_puzzle",
+        usage: "{p}start_picture_puzzle [category]",
+        slash: Some("/puzzle start [category]"),
+        description: "Start a new puzzle in this channel, optionally from a category or tag.",
+        examples: &["{p}start_picture_puzzle", "{p}start_picture_puzzle nature"],
+    },
+    HelpEntry {
+        category: "Playing",
+        name: "swap_tiles",
+        usage: "{p}swap_tiles <tile1> <tile2>",
+        slash: Some("/puzzle swap <tile1> <tile2>"),
+        description: "Swap two tiles, given as numbers or coordinates. You can also click tiles on the board, or list several swaps in the Swap Tiles form.",
+        examples: &["{p}swap_tiles {a} {b}"],
+    },
+    HelpEntry {
+        category: "Game modes",
+        name: "coop",
+        usage: "{p}coop [category]",
+        slash: None,
+        description: "Start a puzzle where players press Join and take turns swapping, with points shared by tiles placed.",
+        examples: &["{p}coop", "{p}coop people"],
+    },
+    HelpEntry {
+        category: "Game modes",
+        name: "channel_plays",
+        usage: "{p}channel_plays [category]",
+        slash: None,
+        description: "Start a puzzle the whole channel plays: everyone votes for a swap and the most popular one is made.",
+        examples: &["{p}channel_plays", "{p}channel_plays nature"],
+    },
+    HelpEntry {
+        category: "Game modes",
+        name: "vote",
+        usage: "{p}vote <tile1> <tile2>",
+        slash: None,
+        description: "Vote for a swap in a channel plays puzzle. Clicking two tiles votes too.",
+        examples: &["{p}vote {a} {b}"],
+    },
+    HelpEntry {
+        category: "Game modes",
+        name: "race",
+        usage: "{p}race [category]",
+        slash: None,
+        description: "Race other players: everyone who joins gets the same scramble in their DMs, and the first to finish wins.",
+        examples: &["{p}race", "{p}race people"],
+    },
+    HelpEntry {
+        category: "Competitive",
+        name: "duel",
+        usage: "{p}duel @player [size] [wager <coins>]",
+        slash: None,
+        description: "Challenge someone to a head-to-head race, optionally staking coins that the winner takes.",
+        examples: &["{p}duel @someone", "{p}duel @someone 4", "{p}duel @someone 4 wager 50"],
+    },
+    HelpEntry {
+        category: "Competitive",
+        name: "duel_record",
+        usage: "{p}duel_record [@player] [@player]",
+        slash: None,
+        description: "Show a rating, and the duel record between two players when you mention someone.",
+        examples: &["{p}duel_record", "{p}duel_record @someone"],
+    },
+    HelpEntry {
+        category: "Game modes",
+        name: "battle",
+        usage: "{p}battle [team or @role ...]",
+        slash: None,
+        description: "Team battle: every team solves the same scramble on its own board. Teams are joined with buttons, or made from roles.",
+        examples: &["{p}battle", "{p}battle cats dogs", "{p}battle @Red @Blue"],
+    },
+    HelpEntry {
+        category: "Playing",
+        name: "submit_guess",
+        usage: "{p}submit_guess <answer>",
+        slash: Some("/puzzle guess <answer>"),
+        description: "Guess what the picture shows.",
+        examples: &["{p}submit_guess lighthouse"],
+    },
+    HelpEntry {
+        category: "Playing",
+        name: "hint",
+        usage: "{p}hint",
+        slash: Some("/puzzle hint"),
+        description: "Suggest a swap that puts a tile in its correct place, for the server's hint price.",
+        examples: &[],
+    },
+    HelpEntry {
+        category: "Playing",
+        name: "giveup",
+        usage: "{p}giveup",
+        slash: Some("/puzzle giveup"),
+        description: "Reveal the answer and end the puzzle, or vote to if you didn't start it.",
+        examples: &[],
+    },
+    HelpEntry {
+        category: "Playing",
+        name: "leaderboard",
+        usage: "{p}leaderboard",
+        slash: Some("/leaderboard"),
+        description: "Show the top scorers.",
+        examples: &[],
+    },
+    HelpEntry {
+        category: "Playing",
+        name: "rating",
+        usage: "{p}rating [@player]",
+        slash: None,
+        description: "Show your skill rating, or a player's.",
+        examples: &["{p}rating", "{p}rating @someone"],
+    },
+    HelpEntry {
+        category: "Playing",
+        name: "rated_leaderboard",
+        usage: "{p}rated_leaderboard",
+        slash: None,
+        description: "Show the highest-rated players.",
+        examples: &[],
+    },
+    HelpEntry {
+        category: "Game modes",
+        name: "team_leaderboard",
+        usage: "{p}team_leaderboard",
+        slash: None,
+        description: "Show this server's teams by battles won.",
+        examples: &[],
+    },
+    HelpEntry {
+        category: "Competitive",
+        name: "tournament",
+        usage: "{p}tournament create [single|double] [category]",
+        slash: None,
+        description: "Run a bracket of head-to-head races. Also `start`, `bracket`, `cancel` and `stats [@user]`.",
+        examples: &["{p}tournament create", "{p}tournament create double nature", "{p}tournament stats"],
+    },
+    HelpEntry {
+        category: "Playing",
+        name: "tutorial",
+        usage: "{p}tutorial [classic|race|coop|battle|duel|channel]",
+        slash: Some("/tutorial [mode]"),
+        description: "Learn to play on a 2x2 practice puzzle that only you can see.",
+        examples: &[],
+    },
+    HelpEntry {
+        category: "Playing",
+        name: "help",
+        usage: "{p}help [command]",
+        slash: Some("/help [command]"),
+        description: "Show this help, or more about one command.",
+        examples: &["{p}help swap_tiles"],
+    },
+    HelpEntry {
+        category: "Your board",
+        name: "accessibility",
+        usage: "{p}accessibility [setting] [value]",
+        slash: None,
+        description: "Show or change your board settings for puzzles you start: display, notation, edges, contrast, colors and overlay.",
+        examples: &["{p}accessibility notation coordinates", "{p}accessibility contrast high", "{p}accessibility display emoji"],
+    },
+    HelpEntry {
+        category: "Your board",
+        name: "overlay",
+        usage: "{p}overlay on|off",
+        slash: None,
+        description: "Mark tiles already in place on the current board and show how much is solved.",
+        examples: &["{p}overlay on"],
+    },
+    HelpEntry {
+        category: "Your board",
+        name: "board",
+        usage: "{p}board image|text|emoji",
+        slash: None,
+        description: "Switch how the current board is shown.",
+        examples: &["{p}board text"],
+    },
+    HelpEntry {
+        category: "Server admins",
+        name: "theme",
+        usage: "{p}theme [name | custom <property> <value> | watermark <text|off>]",
+        slash: None,
+        description: "Show the server's board theme. Members with Manage Server can change it.",
+        examples: &["{p}theme neon", "{p}theme custom border #ff00ff", "{p}theme watermark My Server"],
+    },
+    HelpEntry {
+        category: "Coins",
+        name: "coins",
+        usage: "{p}coins [@player] | history [@player] | grant|revoke @player <amount> [reason]",
+        slash: None,
+        description: "Show a coin balance or recent transactions. Members with Manage Server can grant or revoke coins.",
+        examples: &["{p}coins", "{p}coins history", "{p}coins grant @someone 100 event prize"],
+    },
+    HelpEntry {
+        category: "Coins",
+        name: "daily",
+        usage: "{p}daily",
+        slash: None,
+        description: "Claim your daily coins, with a bonus for each day in a row.",
+        examples: &[],
+    },
+    HelpEntry {
+        category: "Coins",
+        name: "peek",
+        usage: "{p}peek",
+        slash: None,
+        description: "Get a private look at the picture in your DMs, for the server's peek price.",
+        examples: &[],
+    },
+    HelpEntry {
+        category: "Coins",
+        name: "shop",
+        usage: "{p}shop [buy <theme> | use <theme|server>]",
+        slash: None,
+        description: "See what coins can buy, or buy and use a board theme for the puzzles you start.",
+        examples: &["{p}shop", "{p}shop buy neon", "{p}shop use server"],
+    },
+    HelpEntry {
+        category: "Server admins",
+        name: "config",
+        usage: "{p}config [prefix | grid | mode | channels | theme | scoring | reveal | turns | votes | prices] <value>",
+        slash: None,
+        description: "Show or change the server's settings. Needs Manage Server.",
+        examples: &["{p}config prefix ?", "{p}config grid 4", "{p}config channels add #puzzles", "{p}config reveal vote", "{p}config turns 90", "{p}config votes 20", "{p}config prices hint 0"],
+    },
+];
+
+// Names accepted by `!help <command>` and `/help`
+pub fn command_names() -> impl Iterator<Item = &'static str> {
+    COMMANDS.iter().map(|entry| entry.name)
+}
+
+// Fill in the server's prefix and example tiles
+fn apply_settings(text: &str, config: &GuildConfig) -> String {
+    let grid_size = config.grid_size.unwrap_or(3);
+    let last = (grid_size * grid_size) as usize - 1;
+    text.replace("{p}", &config.prefix)
+        .replace("{a}", &board::tile_label(0, grid_size, Notation::Numbers))
+        .replace("{b}", &board::tile_label(last, grid_size, Notation::Numbers))
+}
+
+// Embed fields hold at most 1024 characters, which a long channel list could exceed
+fn truncate_field(text: String) -> String {
+    const MAX_FIELD: usize = 1024;
+    if text.chars().count() <= MAX_FIELD {
+        return text;
+    }
+    let mut truncated: String = text.chars().take(MAX_FIELD - 1).collect();
+    truncated.push('…');
+    truncated
+}
+
+// The overview grouped by category, or the details of one command
+pub fn help_embed(embed: &mut CreateEmbed, config: &GuildConfig, topic: Option<&str>) -> Result<(), String> {
+    let topic = topic
+        .map(|topic| topic.trim().trim_start_matches(config.prefix.as_str()).trim_start_matches('/').to_lowercase())
+        .filter(|topic| !topic.is_empty());
+
+    embed.colour(HELP_COLOUR);
+    match topic {
+        Some(topic) => {
+            let entry = COMMANDS
+                .iter()
+                .find(|entry| entry.name == topic)
+                .ok_or_else(|| format!("There is no command called \"{}\". Try {}help for a list.", topic, config.prefix))?;
+            embed
+                .title(format!("{}{}", config.prefix, entry.name))
+                .description(entry.description)
+                .field("Usage", format!("`{}`", apply_settings(entry.usage, config)), false);
+            if let Some(slash) = entry.slash {
+                embed.field("Slash command", format!("`{}`", slash), false);
+            }
+            if !entry.examples.is_empty() {
+                let examples: Vec<_> = entry
+                    .examples
+                    .iter()
+                    .map(|example| format!("`{}`", apply_settings(example, config)))
+                    .collect();
+                embed.field("Examples", examples.join("\n"), false);
+            }
+        }
+        None => {
+            embed.title("Picture Puzzle help").description(format!(
+                "Unscramble the picture by swapping tiles, or guess what it shows. New here? Try `{}tutorial`.\n\
+                 Use `{}help <command>` for examples.",
+                config.prefix, config.prefix
+            ));
+            for category in CATEGORIES {
+                let lines: Vec<_> = COMMANDS
+                    .iter()
+                    .filter(|entry| entry.category == category)
+                    .map(|entry| format!("`{}` {}", apply_settings(entry.usage, config), entry.description))
+                    .collect();
+                embed.field(category, lines.join("\n"), false);
+            }
+            embed.field("This server", truncate_field(config.describe()), false);
+        }
+    }
+    embed.footer(|footer| footer.text("Tiles can be numbers (1, 2, 3...) or coordinates (A1, B1...)."));
+    Ok(())
+}
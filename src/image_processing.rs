@@ -1,118 +1,552 @@
 extern crate image;
-use image::{DynamicImage, ImageBuffer, GenericImageView, Rgba};
-use rand::seq::SliceRandom;
-use imageproc::drawing::draw_text_mut;
-use rusttype::{Font, Scale};
-use std::convert::TryInto;
-pub fn scramble_image(image: DynamicImage, tile_positions: &mut Vec<usize>) -> DynamicImage {
-    let (width, height) = image.dimensions();
-    let tile_width = width / 3;
-    let tile_height = height / 3;
-    let mut tiles: Vec<DynamicImage> = Vec::with_capacity(9);
-
-    // Load the font (Ensure DejaVuSans.ttf is included in the project)
-    let font = Vec::from(include_bytes!("../src/DejaVuSans.ttf") as &[u8]);
-    let font = Font::try_from_vec(font).unwrap();
-
-    for y in 0..3 {
-        for x in 0..3 {
-            let tile_x = x * tile_width;
-            let tile_y = y * tile_height;
-            let tile = image.crop_imm(tile_x, tile_y, tile_width, tile_height);
-            tiles.push(tile);
-        }
-    }
-
-    let mut rng = rand::thread_rng();
-    tiles.shuffle(&mut rng);
-    *tile_positions = (0..9).collect::<Vec<usize>>();
-    tile_positions.shuffle(&mut rng);
-
-    let mut buffer = ImageBuffer::new(width, height);
-
-    // Define border color and thickness
-    let border_color = Rgba([0, 0, 0, 255]); // Black border
-    let border_thickness = 5;
-
-    for (i, tile) in tiles.iter().enumerate() {
-        let x = (i % 3) as u32 * tile_width;
-        let y = (i / 3) as u32 * tile_height;
-
-        for tile_y in 0..tile_height {
-            for tile_x in 0..tile_width {
-                let pixel = tile.get_pixel(tile_x, tile_y);
-                buffer.put_pixel(x + tile_x, y + tile_y, pixel);
+use image::{ImageBuffer, Rgba, RgbaImage};
+use crate::board::{column_name, tile_label, BoardOptions, HighlightPalette};
+use crate::theme::Theme;
+use imageproc::drawing::{draw_filled_rect_mut, draw_hollow_rect_mut, draw_line_segment_mut};
+use imageproc::rect::Rect;
+use rusttype::{point, Font, Scale};
+use rayon::prelude::*;
+use std::path::Path;
+use std::sync::OnceLock;
+
+// Bundled default font (Ensure DejaVuSans.ttf is included in the project)
+static DEFAULT_FONT_BYTES: &[u8] = include_bytes!("../src/DejaVuSans.ttf");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LabelPosition {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+    Center,
+}
+
+impl std::str::FromStr for LabelPosition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().replace(['-', '_', ' '], "").as_str() {
+            "topleft" => Ok(LabelPosition::TopLeft),
+            "topright" => Ok(LabelPosition::TopRight),
+            "bottomleft" => Ok(LabelPosition::BottomLeft),
+            "bottomright" => Ok(LabelPosition::BottomRight),
+            "center" | "centre" => Ok(LabelPosition::Center),
+            _ => Err(format!("unknown label position \"{}\"", s)),
+        }
+    }
+}
+
+// Parse a "#rrggbb" or "#rrggbbaa" color
+pub fn parse_hex_color(s: &str) -> Result<Rgba<u8>, String> {
+    let hex = s.trim().trim_start_matches('#');
+    let channel = |i: usize| {
+        hex.get(i..i + 2)
+            .and_then(|c| u8::from_str_radix(c, 16).ok())
+            .ok_or_else(|| format!("invalid color \"{}\", expected #rrggbb", s))
+    };
+    match hex.len() {
+        6 => Ok(Rgba([channel(0)?, channel(2)?, channel(4)?, 255])),
+        8 => Ok(Rgba([channel(0)?, channel(2)?, channel(4)?, channel(6)?])),
+        _ => Err(format!("invalid color \"{}\", expected #rrggbb", s)),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LabelStyle {
+    pub text_color: Rgba<u8>,
+    pub background_color: Rgba<u8>,
+    pub position: LabelPosition,
+    // Opacity of the whole label (background and text), from 0.0 to 1.0
+    pub opacity: f32,
+    // Label text height as a fraction of the shorter tile side
+    pub size_ratio: f32,
+}
+
+impl Default for LabelStyle {
+    fn default() -> Self {
+        LabelStyle {
+            text_color: Rgba([255, 255, 255, 255]), // White text color
+            background_color: Rgba([0, 0, 0, 255]),
+            position: LabelPosition::TopLeft,
+            opacity: 1.0,
+            size_ratio: 0.2,
+        }
+    }
+}
+
+// Pixel extents of a piece of text, relative to the point it is drawn from
+#[derive(Debug, Clone, Copy)]
+pub struct TextBounds {
+    pub left: i32,
+    pub top: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+pub struct Renderer {
+    font: Font<'static>,
+}
+
+impl Renderer {
+    pub fn new(font: Font<'static>) -> Self {
+        Renderer { font }
+    }
+
+    // Renderer using the bundled DejaVu Sans font, parsed once per process
+    pub fn with_default_font() -> Self {
+        static DEFAULT_FONT: OnceLock<Font<'static>> = OnceLock::new();
+        let font = DEFAULT_FONT
+            .get_or_init(|| Font::try_from_bytes(DEFAULT_FONT_BYTES).expect("Bundled font is invalid"))
+            .clone();
+        Renderer::new(font)
+    }
+
+    pub fn with_font_file(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path)
+            .map_err(|e| format!("failed to read font {}: {}", path.display(), e))?;
+        let font = Font::try_from_vec(bytes)
+            .ok_or_else(|| format!("{} is not a valid TrueType/OpenType font", path.display()))?;
+        Ok(Renderer::new(font))
+    }
+
+    // Measure the inked area of `text`, matching exactly where `draw_text` puts pixels
+    pub fn measure_text(&self, text: &str, scale: Scale) -> Option<TextBounds> {
+        let ascent = self.font.v_metrics(scale).ascent;
+        let (mut min_x, mut min_y, mut max_x, mut max_y) = (i32::MAX, i32::MAX, i32::MIN, i32::MIN);
+        for glyph in self.font.layout(text, scale, point(0.0, ascent)) {
+            if let Some(bb) = glyph.pixel_bounding_box() {
+                min_x = min_x.min(bb.min.x);
+                min_y = min_y.min(bb.min.y);
+                max_x = max_x.max(bb.max.x);
+                max_y = max_y.max(bb.max.y);
+            }
+        }
+        (min_x < max_x && min_y < max_y).then(|| TextBounds {
+            left: min_x,
+            top: min_y,
+            width: (max_x - min_x) as u32,
+            height: (max_y - min_y) as u32,
+        })
+    }
+
+    // Draw text with its layout origin at (x, y), blending glyph coverage by `opacity`
+    pub fn draw_text(&self, buffer: &mut RgbaImage, text: &str, (x, y): (i32, i32), scale: Scale, color: Rgba<u8>, opacity: f32) {
+        let ascent = self.font.v_metrics(scale).ascent;
+        let (width, height) = buffer.dimensions();
+        for glyph in self.font.layout(text, scale, point(0.0, ascent)) {
+            if let Some(bb) = glyph.pixel_bounding_box() {
+                glyph.draw(|gx, gy, coverage| {
+                    let px = x + bb.min.x + gx as i32;
+                    let py = y + bb.min.y + gy as i32;
+                    if px >= 0 && py >= 0 && (px as u32) < width && (py as u32) < height {
+                        let pixel = buffer.get_pixel_mut(px as u32, py as u32);
+                        *pixel = blend(*pixel, color, coverage * opacity);
+                    }
+                });
             }
         }
+    }
 
-        // Draw border around each tile manually with boundary checks
-        for thickness in 0..border_thickness {
-            let border_start_x = (x as i32 - thickness as i32).max(0);
-            let border_end_x = ((x + tile_width) as i32 + thickness as i32).min(width as i32 - 1);
-            let border_start_y = (y as i32 - thickness as i32).max(0);
-            let border_end_y = ((y + tile_height) as i32 + thickness as i32).min(height as i32 - 1);
+    // Draw a tile label inside the tile at (x, y), sized relative to the tile
+    fn draw_label(&self, buffer: &mut RgbaImage, style: &LabelStyle, text: &str, (x, y): (u32, u32), (tile_width, tile_height): (u32, u32)) {
+        let opacity = style.opacity.clamp(0.0, 1.0);
+        let size = (tile_width.min(tile_height) as f32 * style.size_ratio).max(8.0);
+        let scale = Scale::uniform(size);
+        let bounds = match self.measure_text(text, scale) {
+            Some(bounds) => bounds,
+            None => return,
+        };
 
-            // Top border
-            for bx in border_start_x..=border_end_x {
-                if border_start_y >= 0 && border_start_y < height as i32 {
-                    buffer.put_pixel(bx as u32, border_start_y as u32, border_color);
-                }
+        let padding = (size * 0.25).round() as u32;
+        let margin = (size * 0.2).round() as u32;
+        let box_width = (bounds.width + padding * 2).min(tile_width);
+        let box_height = (bounds.height + padding * 2).min(tile_height);
+
+        let (box_x, box_y) = match style.position {
+            LabelPosition::TopLeft => (x + margin, y + margin),
+            LabelPosition::TopRight => (x + tile_width.saturating_sub(box_width + margin), y + margin),
+            LabelPosition::BottomLeft => (x + margin, y + tile_height.saturating_sub(box_height + margin)),
+            LabelPosition::BottomRight => (
+                x + tile_width.saturating_sub(box_width + margin),
+                y + tile_height.saturating_sub(box_height + margin),
+            ),
+            LabelPosition::Center => (
+                x + (tile_width - box_width) / 2,
+                y + (tile_height - box_height) / 2,
+            ),
+        };
+
+        // Draw the background rectangle, blended over the tile
+        let (width, height) = buffer.dimensions();
+        for py in box_y..(box_y + box_height).min(height) {
+            for px in box_x..(box_x + box_width).min(width) {
+                let pixel = buffer.get_pixel_mut(px, py);
+                *pixel = blend(*pixel, style.background_color, opacity);
             }
+        }
 
-            // Bottom border
-            for bx in border_start_x..=border_end_x {
-                if border_end_y >= 0 && border_end_y < height as i32 {
-                    buffer.put_pixel(bx as u32, border_end_y as u32, border_color);
-                }
+        // Draw the text on top, offset so its inked area starts inside the padding
+        let text_x = (box_x + padding) as i32 - bounds.left;
+        let text_y = (box_y + padding) as i32 - bounds.top;
+        self.draw_text(buffer, text, (text_x, text_y), scale, style.text_color, opacity);
+    }
+
+    // Label style for a board, swapping in bold, high-contrast colors over the theme when requested
+    fn board_label_style(&self, options: &BoardOptions, theme: &Theme) -> LabelStyle {
+        if options.high_contrast {
+            LabelStyle {
+                text_color: Rgba([255, 255, 0, 255]), // Yellow on black
+                background_color: Rgba([0, 0, 0, 255]),
+                opacity: 1.0,
+                size_ratio: theme.label.size_ratio.max(0.28),
+                ..theme.label.clone()
             }
+        } else {
+            theme.label.clone()
+        }
+    }
 
-            // Left border
-            for by in border_start_y..=border_end_y {
-                if border_start_x >= 0 && border_start_x < width as i32 {
-                    buffer.put_pixel(border_start_x as u32, by as u32, border_color);
-                }
+    // Render the board from the original image: slot `i` shows original tile `tile_positions[i]`.
+    // Slots listed in `highlights` get a colored frame showing whether they are now correct.
+    // Colors and borders come from `theme`; high contrast overrides them for legibility.
+    pub fn render_board(
+        &self,
+        image: &RgbaImage,
+        grid_size: u32,
+        tile_positions: &[usize],
+        options: &BoardOptions,
+        highlights: &[usize],
+        theme: &Theme,
+    ) -> RgbaImage {
+        let (width, height) = image.dimensions();
+        let tile_width = width / grid_size;
+        let tile_height = height / grid_size;
+        let board_width = tile_width * grid_size;
+        let board_height = tile_height * grid_size;
+        let style = self.board_label_style(options, theme);
+
+        // Leave room around the board for column letters and row numbers
+        let margin = if options.edge_labels {
+            ((tile_width.min(tile_height) as f32 * style.size_ratio * 1.4) as u32).max(16)
+        } else {
+            0
+        };
+        let width = board_width + margin * 2;
+        let height = board_height + margin * 2;
+
+        let background = if options.high_contrast { Rgba([0, 0, 0, 255]) } else { theme.background };
+        let mut buffer: RgbaImage = ImageBuffer::from_pixel(width, height, background);
+        composite_tiles(&mut buffer, image, grid_size, tile_positions, margin, (tile_width, tile_height));
+
+        let (border_color, border_thickness) = if options.high_contrast {
+            (Rgba([0, 0, 0, 255]), theme.border_thickness.max(8))
+        } else {
+            (theme.border_color, theme.border_thickness)
+        };
+        let (correct_color, misplaced_color) = highlight_colors(options.palette);
+
+        for (i, &pos) in tile_positions.iter().enumerate() {
+            let x = margin + (i as u32 % grid_size) * tile_width;
+            let y = margin + (i as u32 / grid_size) * tile_height;
+
+            // Draw border around each tile
+            draw_frame(&mut buffer, (x, y), (tile_width, tile_height), border_thickness, border_color);
+
+            let correct = pos == i;
+            if correct && options.correctness_overlay {
+                draw_frame(&mut buffer, (x, y), (tile_width, tile_height), border_thickness * 2, correct_color);
+                self.draw_check_mark(&mut buffer, &style, correct_color, (x, y), (tile_width, tile_height));
+            } else if highlights.contains(&i) {
+                let color = if correct { correct_color } else { misplaced_color };
+                draw_frame(&mut buffer, (x, y), (tile_width, tile_height), border_thickness * 2, color);
             }
 
-            // Right border
-            for by in border_start_y..=border_end_y {
-                if border_end_x >= 0 && border_end_x < width as i32 {
-                    buffer.put_pixel(border_end_x as u32, by as u32, border_color);
-                }
+            // Slot labels stay fixed to board positions, so players can name the slot to swap
+            let label = tile_label(i, grid_size, options.notation);
+            self.draw_label(&mut buffer, &style, &label, (x, y), (tile_width, tile_height));
+        }
+
+        if options.edge_labels {
+            let color = if options.high_contrast { style.text_color } else { theme.edge_label_color };
+            self.draw_edge_labels(&mut buffer, color, grid_size, margin, (tile_width, tile_height));
+        }
+
+        if let Some(watermark) = &theme.watermark {
+            self.draw_watermark(&mut buffer, watermark, margin, style.text_color);
+        }
+
+        buffer
+    }
+
+    // Small translucent text in the bottom right corner of the board, inside any edge label margin
+    fn draw_watermark(&self, buffer: &mut RgbaImage, text: &str, margin: u32, color: Rgba<u8>) {
+        let (width, height) = buffer.dimensions();
+        let (width, height) = (width - margin, height - margin);
+        let scale = Scale::uniform((width.min(height) as f32 * 0.035).max(10.0));
+        if let Some(bounds) = self.measure_text(text, scale) {
+            let padding = (scale.y * 0.5) as i32;
+            let x = width as i32 - bounds.width as i32 - padding - bounds.left;
+            let y = height as i32 - bounds.height as i32 - padding - bounds.top;
+            self.draw_text(buffer, text, (x + 1, y + 1), scale, Rgba([0, 0, 0, 255]), 0.35);
+            self.draw_text(buffer, text, (x, y), scale, color, 0.6);
+        }
+    }
+
+    // Check mark badge in the tile corner opposite its label
+    fn draw_check_mark(&self, buffer: &mut RgbaImage, style: &LabelStyle, color: Rgba<u8>, (x, y): (u32, u32), (tile_width, tile_height): (u32, u32)) {
+        let position = match style.position {
+            LabelPosition::TopLeft | LabelPosition::Center => LabelPosition::BottomRight,
+            LabelPosition::TopRight => LabelPosition::BottomLeft,
+            LabelPosition::BottomLeft => LabelPosition::TopRight,
+            LabelPosition::BottomRight => LabelPosition::TopLeft,
+        };
+        let badge = LabelStyle {
+            text_color: Rgba([255, 255, 255, 255]),
+            background_color: color,
+            position,
+            opacity: 1.0,
+            ..style.clone()
+        };
+        self.draw_label(buffer, &badge, "\u{2713}", (x, y), (tile_width, tile_height));
+    }
+
+    // Column letters above and below the board, row numbers to its left and right
+    fn draw_edge_labels(&self, buffer: &mut RgbaImage, color: Rgba<u8>, grid_size: u32, margin: u32, (tile_width, tile_height): (u32, u32)) {
+        let scale = Scale::uniform(margin as f32 * 0.7);
+        let far_x = margin + tile_width * grid_size;
+        let far_y = margin + tile_height * grid_size;
+
+        let mut draw_centered = |text: &str, center_x: u32, center_y: u32| {
+            if let Some(bounds) = self.measure_text(text, scale) {
+                let x = center_x as i32 - bounds.width as i32 / 2 - bounds.left;
+                let y = center_y as i32 - bounds.height as i32 / 2 - bounds.top;
+                self.draw_text(buffer, text, (x, y), scale, color, 1.0);
             }
+        };
+
+        for column in 0..grid_size {
+            let text = column_name(column).to_string();
+            let center_x = margin + column * tile_width + tile_width / 2;
+            draw_centered(&text, center_x, margin / 2);
+            draw_centered(&text, center_x, far_y + margin / 2);
+        }
+        for row in 0..grid_size {
+            let text = format!("{}", row + 1);
+            let center_y = margin + row * tile_height + tile_height / 2;
+            draw_centered(&text, margin / 2, center_y);
+            draw_centered(&text, far_x + margin / 2, center_y);
         }
+    }
+}
+
+// One match box in a bracket image
+pub struct BracketMatch {
+    pub players: [String; 2],
+    pub winner: Option<usize>,
+    pub live: bool,
+    // The match in the next round of the same section that the winner moves on to
+    pub next: Option<usize>,
+}
+
+// A titled part of a bracket, drawn as one column of matches per round
+pub struct BracketSection {
+    pub title: String,
+    pub rounds: Vec<Vec<BracketMatch>>,
+}
 
-        // Set the font size
-        let text_scale = Scale { x: 50.0, y: 50.0 };  // Larger font size
+const BRACKET_BOX_WIDTH: u32 = 200;
+const BRACKET_ROW_HEIGHT: u32 = 26;
+const BRACKET_COLUMN_GAP: u32 = 40;
+const BRACKET_MATCH_GAP: u32 = 16;
+const BRACKET_MARGIN: u32 = 24;
+const BRACKET_TITLE_HEIGHT: u32 = 36;
 
-        // Set the font color to white
-        let text_color = Rgba([255, 255, 255, 255]); // White text color
+impl Renderer {
+    // Draw a tournament bracket: each section's rounds left to right, joined by lines from
+    // every match to the one its winner plays next. Winners are highlighted, live matches outlined.
+    pub fn render_bracket(&self, sections: &[BracketSection]) -> RgbaImage {
+        let background = Rgba([43, 45, 49, 255]);
+        let box_color = Rgba([30, 31, 34, 255]);
+        let winner_color = Rgba([36, 92, 58, 255]);
+        let live_color = Rgba([240, 178, 50, 255]);
+        let line_color = Rgba([128, 132, 142, 255]);
+        let text_color = Rgba([242, 243, 245, 255]);
+        let loser_color = Rgba([148, 155, 164, 255]);
 
-        // Create a rectangle for the text background
-        let index_text = format!("{}", i + 1);  // Static numbers from 1 to 9
-        let text_width = text_scale.x * index_text.chars().count() as f32; // Estimate text width
-        let text_height = text_scale.y;
+        let box_height = BRACKET_ROW_HEIGHT * 2;
+        let section_height = |section: &BracketSection| {
+            let most = section.rounds.iter().map(Vec::len).max().unwrap_or(0) as u32;
+            most.max(1) * (box_height + BRACKET_MATCH_GAP)
+        };
+        let columns = sections.iter().map(|section| section.rounds.len()).max().unwrap_or(0).max(1) as u32;
+        let width = BRACKET_MARGIN * 2 + columns * BRACKET_BOX_WIDTH + (columns - 1) * BRACKET_COLUMN_GAP;
+        let height = BRACKET_MARGIN
+            + sections
+                .iter()
+                .map(|section| BRACKET_TITLE_HEIGHT + section_height(section) + BRACKET_MARGIN)
+                .sum::<u32>();
+        let mut buffer = ImageBuffer::from_pixel(width, height.max(BRACKET_MARGIN * 2), background);
 
-        let rect_x = x as i32 + 10; // Adjust position as needed
-        let rect_y = y as i32 + 10;
-        let rect_width = text_width as u32 + 20; // Padding around the text
-        let rect_height = text_height as u32 + 20;
+        let title_scale = Scale::uniform(22.0);
+        let name_scale = Scale::uniform(16.0);
+        let mut top = BRACKET_MARGIN;
+        for section in sections {
+            self.draw_text(&mut buffer, &section.title, (BRACKET_MARGIN as i32, top as i32), title_scale, text_color, 1.0);
+            top += BRACKET_TITLE_HEIGHT;
+            let section_height = section_height(section);
+            // Top left corner of a match box
+            let place = |round: usize, index: usize, count: usize| {
+                let slot_height = section_height / count.max(1) as u32;
+                let x = BRACKET_MARGIN + round as u32 * (BRACKET_BOX_WIDTH + BRACKET_COLUMN_GAP);
+                let y = top + index as u32 * slot_height + slot_height.saturating_sub(box_height) / 2;
+                (x, y)
+            };
 
-        // Draw the background rectangle (for the border)
-        for i in 0..rect_width {
-            for j in 0..rect_height {
-                let px = rect_x + i as i32;
-                let py = rect_y + j as i32;
-                if px >= 0 && px < width as i32 && py >= 0 && py < height as i32 {
-                    buffer.put_pixel(px as u32, py as u32, border_color);
+            for (round, matches) in section.rounds.iter().enumerate() {
+                for (index, bracket_match) in matches.iter().enumerate() {
+                    let (x, y) = place(round, index, matches.len());
+                    draw_filled_rect_mut(&mut buffer, Rect::at(x as i32, y as i32).of_size(BRACKET_BOX_WIDTH, box_height), box_color);
+                    for (row, name) in bracket_match.players.iter().enumerate() {
+                        let row_y = y + row as u32 * BRACKET_ROW_HEIGHT;
+                        let color = match bracket_match.winner {
+                            Some(winner) if winner == row => {
+                                let rect = Rect::at(x as i32, row_y as i32).of_size(BRACKET_BOX_WIDTH, BRACKET_ROW_HEIGHT);
+                                draw_filled_rect_mut(&mut buffer, rect, winner_color);
+                                text_color
+                            }
+                            Some(_) => loser_color,
+                            None => text_color,
+                        };
+                        let name = self.fit_text(name, name_scale, BRACKET_BOX_WIDTH - 16);
+                        let text_y = row_y as i32 + (BRACKET_ROW_HEIGHT as i32 - name_scale.y as i32) / 2;
+                        self.draw_text(&mut buffer, &name, (x as i32 + 8, text_y), name_scale, color, 1.0);
+                    }
+                    let divider_y = (y + BRACKET_ROW_HEIGHT) as f32;
+                    draw_line_segment_mut(
+                        &mut buffer,
+                        (x as f32, divider_y),
+                        ((x + BRACKET_BOX_WIDTH - 1) as f32, divider_y),
+                        background,
+                    );
+                    if bracket_match.live {
+                        let rect = Rect::at(x as i32, y as i32).of_size(BRACKET_BOX_WIDTH, box_height);
+                        draw_hollow_rect_mut(&mut buffer, rect, live_color);
+                        let inner = Rect::at(x as i32 + 1, y as i32 + 1).of_size(BRACKET_BOX_WIDTH - 2, box_height - 2);
+                        draw_hollow_rect_mut(&mut buffer, inner, live_color);
+                    }
+
+                    // Elbow from the middle of this box to the middle of the next one
+                    let next = bracket_match.next.zip(section.rounds.get(round + 1));
+                    if let Some((next, next_matches)) = next {
+                        let (next_x, next_y) = place(round + 1, next, next_matches.len());
+                        let start = ((x + BRACKET_BOX_WIDTH) as f32, (y + BRACKET_ROW_HEIGHT) as f32);
+                        let end = (next_x as f32 - 1.0, (next_y + BRACKET_ROW_HEIGHT) as f32);
+                        let middle = start.0 + BRACKET_COLUMN_GAP as f32 / 2.0;
+                        draw_line_segment_mut(&mut buffer, start, (middle, start.1), line_color);
+                        draw_line_segment_mut(&mut buffer, (middle, start.1), (middle, end.1), line_color);
+                        draw_line_segment_mut(&mut buffer, (middle, end.1), end, line_color);
+                    }
                 }
             }
+            top += section_height + BRACKET_MARGIN;
         }
+        buffer
+    }
 
-        // Draw the text on top
-        let text_x = (rect_x + 10).try_into().unwrap();
-        let text_y = (rect_y + 10).try_into().unwrap();
-        draw_text_mut(&mut buffer, text_color, text_x, text_y, text_scale, &font, &index_text);
+    // Shorten text with an ellipsis until it fits in `max_width` pixels
+    fn fit_text(&self, text: &str, scale: Scale, max_width: u32) -> String {
+        let fits = |text: &str| {
+            self.measure_text(text, scale)
+                .is_none_or(|bounds| bounds.left + bounds.width as i32 <= max_width as i32)
+        };
+        if fits(text) {
+            return text.to_string();
+        }
+        let mut chars: Vec<char> = text.chars().collect();
+        while !chars.is_empty() {
+            chars.pop();
+            let shortened = format!("{}…", chars.iter().collect::<String>().trim_end());
+            if fits(&shortened) {
+                return shortened;
+            }
+        }
+        "…".to_string()
+    }
+}
+
+// Copy every tile from `image` into its slot on the board. Each output row is assembled from
+// whole tile-width slices of the source rows, and rows are filled in parallel.
+fn composite_tiles(
+    buffer: &mut RgbaImage,
+    image: &RgbaImage,
+    grid_size: u32,
+    tile_positions: &[usize],
+    margin: u32,
+    (tile_width, tile_height): (u32, u32),
+) {
+    const CHANNELS: usize = 4;
+    let grid = grid_size as usize;
+    let (tile_width, tile_height) = (tile_width as usize, tile_height as usize);
+    let margin = margin as usize;
+    let source_stride = image.width() as usize * CHANNELS;
+    let row_stride = buffer.width() as usize * CHANNELS;
+    let tile_bytes = tile_width * CHANNELS;
+    let source = image.as_raw();
+
+    buffer
+        .par_chunks_mut(row_stride)
+        .skip(margin)
+        .take(tile_height * grid)
+        .enumerate()
+        .for_each(|(board_y, row)| {
+            let slot_row = board_y / tile_height;
+            let y_in_tile = board_y % tile_height;
+            for column in 0..grid {
+                let tile = tile_positions[slot_row * grid + column];
+                let source_x = (tile % grid) * tile_width;
+                let source_y = (tile / grid) * tile_height + y_in_tile;
+                let from = source_y * source_stride + source_x * CHANNELS;
+                let to = (margin + column * tile_width) * CHANNELS;
+                row[to..to + tile_bytes].copy_from_slice(&source[from..from + tile_bytes]);
+            }
+        });
+}
+
+// Colors for (correct, misplaced) tile highlights
+fn highlight_colors(palette: HighlightPalette) -> (Rgba<u8>, Rgba<u8>) {
+    match palette {
+        HighlightPalette::Standard => (Rgba([0, 200, 83, 255]), Rgba([229, 57, 53, 255])),
+        // Okabe-Ito blue and orange
+        HighlightPalette::ColorblindSafe => (Rgba([0, 114, 178, 255]), Rgba([230, 159, 0, 255])),
+    }
+}
+
+// Draw a frame of the given thickness just inside the rectangle at (x, y)
+fn draw_frame(buffer: &mut RgbaImage, (x, y): (u32, u32), (width, height): (u32, u32), thickness: u32, color: Rgba<u8>) {
+    let thickness = thickness.min(width / 2).min(height / 2);
+    for offset in 0..thickness {
+        // Top and bottom edges
+        for px in x..x + width {
+            buffer.put_pixel(px, y + offset, color);
+            buffer.put_pixel(px, y + height - 1 - offset, color);
+        }
+        // Left and right edges
+        for py in y..y + height {
+            buffer.put_pixel(x + offset, py, color);
+            buffer.put_pixel(x + width - 1 - offset, py, color);
+        }
     }
+}
 
-    DynamicImage::ImageRgba8(buffer)
-}
\ No newline at end of file
+// Alpha-blend `color` over `base` with the given weight
+fn blend(base: Rgba<u8>, color: Rgba<u8>, weight: f32) -> Rgba<u8> {
+    let weight = weight.clamp(0.0, 1.0) * (color[3] as f32 / 255.0);
+    let mix = |a: u8, b: u8| (a as f32 * (1.0 - weight) + b as f32 * weight).round() as u8;
+    Rgba([
+        mix(base[0], color[0]),
+        mix(base[1], color[1]),
+        mix(base[2], color[2]),
+        base[3].max((weight * 255.0).round() as u8),
+    ])
+}
@@ -1,273 +1,942 @@
 use serenity::{
     async_trait,
-    builder::{CreateActionRow, CreateButton},
+    builder::{CreateActionRow, CreateEmbed},
     model::{channel::Message, prelude::*},
     prelude::*,
     framework::standard::{
-        CommandResult,
-        macros::{command, group},
-        Args, StandardFramework,
+        CommandOptions, CommandResult, Reason,
+        macros::{check, command, group, hook},
+        Args, DispatchError, StandardFramework,
     },
     Client,
     model::gateway::GatewayIntents,
-    model::interactions::{Interaction, InteractionResponseType},
-    model::interactions::message_component::ButtonStyle,
+    model::application::interaction::{Interaction, InteractionResponseType},
+    model::application::interaction::message_component::MessageComponentInteraction,
+    model::application::component::ButtonStyle,
 };
 use dotenv::dotenv;
-use reqwest;
-use futures::stream::StreamExt;
 use std::env;
-use image::{DynamicImage, GenericImageView};
-use std::time::Duration;
-use std::collections::HashMap;
+use image::RgbaImage;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+mod actions;
+mod board;
+mod components;
+mod config;
+mod coop;
+mod crowd;
+mod duel;
+mod economy;
+mod encoding;
+mod help;
 mod image_processing;
-use image_processing::scramble_image;
+mod manifest;
+mod modals;
+mod prompts;
+mod race;
+mod ratings;
+mod render;
+mod slash;
+mod teams;
+mod theme;
+mod tournament;
+mod tutorial;
+use actions::{ActionResult, Invoker};
+use board::{shuffled_positions, BoardDisplay, BoardOptions};
+use encoding::EncoderSettings;
+use image_processing::{parse_hex_color, LabelStyle, Renderer};
+use manifest::{ImageSource, Manifest, PuzzleEntry};
+use render::{board_renderer, describe_upload, render_blocking, BoardRenderer, BoardSnapshot, RenderedBoard};
+use config::{ConfigStore, GuildConfig};
+use coop::TurnOrder;
+use crowd::VoteRound;
+use race::RaceTiming;
+use theme::{GuildTheme, Theme, THEME_NAMES};
+use tutorial::TutorialMode;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
 
 #[group]
-#[commands(start_picture_puzzle, submit_guess, swap_tiles)]
+#[checks(AllowedChannel)]
+#[commands(start_picture_puzzle, coop, channel_plays, vote, race, duel, duel_record, battle, team_leaderboard, tournament, submit_guess, swap_tiles, hint, giveup, peek, leaderboard, rating, rated_leaderboard, coins, daily, shop, accessibility, overlay, board, theme, help, tutorial)]
 struct General;
 
 struct PicturePuzzleGame {
-    current_image_url: String,
-    scrambled_image: Option<DynamicImage>,
-    tile_positions: Vec<usize>, // Store current positions of the tiles
+    puzzle: Option<PuzzleEntry>,
+    grid_size: u32,
+    original_image: Option<Arc<RgbaImage>>, // Decoded once, shared with render threads
+    tile_positions: Vec<usize>, // Original tile shown in each slot
     correct_positions: Vec<usize>, // Correct order of the tiles
-    user_scores: HashMap<UserId, usize>,
+    board_options: BoardOptions,
+    theme: Theme, // Server theme at the time the puzzle started
+    last_swap: Vec<usize>, // Slots moved by the most recent swap, highlighted on the next render
+    selected_tiles: HashMap<UserId, usize>, // First tile each player has clicked for a swap
+    board_message: Option<(ChannelId, MessageId)>, // Puzzle message, edited in place after every move
+    moves: usize,
+    starter: Option<UserId>,
+    started_at: Instant,
+    participants: HashSet<UserId>, // Everyone who has swapped or guessed
+    reveal_votes: HashSet<UserId>,
+    solved: bool, // Set once the tiles are in order or the picture is guessed
+    turns: Option<TurnOrder>, // Co-op games only
+    votes: Option<VoteRound>, // Channel plays games only
 }
 
 impl PicturePuzzleGame {
     fn new() -> Self {
         PicturePuzzleGame {
-            current_image_url: String::new(),
-            scrambled_image: None,
+            puzzle: None,
+            grid_size: 3,
+            original_image: None,
             tile_positions: vec![0, 1, 2, 3, 4, 5, 6, 7, 8], // Default correct positions
             correct_positions: vec![0, 1, 2, 3, 4, 5, 6, 7, 8], // Correct positions
-            user_scores: HashMap::new(),
-        }
-    }
-
-    async fn load_image(&mut self, url: &str) {
-        self.current_image_url = url.to_string();
-
-        // Download the image
-        let response = reqwest::get(url).await.expect("Failed to fetch image");
-        let bytes = response.bytes().await.expect("Failed to read image bytes");
-
-        match image::load_from_memory(&bytes) {
-            Ok(img) => {
-                let mut tile_positions = vec![];
-                let scrambled_image = scramble_image(img.clone(), &mut tile_positions);
-                
-                // Save scrambled image to a file
-                let scrambled_image_path = "scrambled_image.png";
-                scrambled_image.save(scrambled_image_path).expect("Failed to save scrambled image");
-                self.scrambled_image = Some(scrambled_image);
-                self.tile_positions = tile_positions;
-            },
-            Err(e) => {
-                println!("Failed to load image: {:?}", e);
-            }
+            board_options: BoardOptions::default(),
+            theme: GuildTheme::default().resolve(&LabelStyle::default()),
+            last_swap: Vec::new(),
+            selected_tiles: HashMap::new(),
+            board_message: None,
+            moves: 0,
+            starter: None,
+            started_at: Instant::now(),
+            participants: HashSet::new(),
+            reveal_votes: HashSet::new(),
+            solved: false,
+            turns: None,
+            votes: None,
         }
     }
 
+    async fn load_puzzle(
+        &mut self,
+        puzzle: &PuzzleEntry,
+        grid_size: u32,
+        starter: UserId,
+        board_options: BoardOptions,
+        theme: Theme,
+    ) -> Result<(), BoxError> {
+        let image = load_image(puzzle).await?;
+        let tile_count = (grid_size * grid_size) as usize;
+
+        self.original_image = Some(image);
+        self.tile_positions = shuffled_positions(tile_count);
+        self.correct_positions = (0..tile_count).collect();
+        self.grid_size = grid_size;
+        self.board_options = board_options;
+        self.theme = theme;
+        self.last_swap.clear();
+        self.selected_tiles.clear();
+        self.board_message = None;
+        self.moves = 0;
+        self.starter = Some(starter);
+        self.started_at = Instant::now();
+        self.participants = HashSet::from([starter]);
+        self.reveal_votes.clear();
+        self.solved = false;
+        self.turns = None;
+        self.votes = None;
+        self.puzzle = Some(puzzle.clone());
+
+        Ok(())
+    }
+
     fn check_guess(&self, guess: &str) -> bool {
-        guess.to_lowercase() == "correct_answer" // Placeholder logic
-    }
-
-    fn increase_score(&mut self, user_id: UserId) {
-        *self.user_scores.entry(user_id).or_insert(0) += 1;
-    }
-
-    fn get_score(&self, user_id: UserId) -> usize {
-        *self.user_scores.get(&user_id).unwrap_or(&0)
-    }
-
-    fn recreate_scrambled_image(&self) -> Option<DynamicImage> {
-        if let Some(ref scrambled_image) = self.scrambled_image {
-            let (width, height) = scrambled_image.dimensions();
-            let tile_width = width / 3;
-            let tile_height = height / 3;
-    
-            // Create a new image buffer to hold the reordered image
-            let mut buffer = image::ImageBuffer::new(width, height);
-    
-            // Recreate the scrambled image based on the current tile positions
-            for (i, &pos) in self.tile_positions.iter().enumerate() {
-                let x = (i % 3) as u32 * tile_width;
-                let y = (i / 3) as u32 * tile_height;
-                let tile_x = (pos % 3) as u32 * tile_width;
-                let tile_y = (pos / 3) as u32 * tile_height;
-    
-                let tile = scrambled_image.crop_imm(tile_x, tile_y, tile_width, tile_height);
-    
-                for tile_y in 0..tile_height {
-                    for tile_x in 0..tile_width {
-                        let pixel = tile.get_pixel(tile_x, tile_y);
-                        buffer.put_pixel(x + tile_x, y + tile_y, pixel);
+        self.puzzle
+            .as_ref()
+            .is_some_and(|puzzle| puzzle.matches_answer(guess))
+    }
+
+    // Parse a tile reference in either notation ("5" or "B2")
+    fn parse_tile(&self, input: &str) -> Option<usize> {
+        board::parse_tile(input, self.grid_size)
+    }
+
+    fn tile_range(&self) -> String {
+        board::tile_range(self.grid_size, self.board_options.notation)
+    }
+
+    fn swap(&mut self, index1: usize, index2: usize) {
+        self.tile_positions.swap(index1, index2);
+        self.last_swap.extend([index1, index2]);
+        self.selected_tiles.clear();
+        self.moves += 1;
+    }
+
+    // Tile buttons or menus plus the Solve, Guess and Swap buttons (and Join and Leave in co-op
+    // games), attached to every board post
+    fn components(&self) -> Vec<CreateActionRow> {
+        // With several players part way through a swap there is no one tile to highlight
+        let selected = match self.selected_tiles.values().collect::<Vec<_>>()[..] {
+            [&slot] => Some(slot),
+            _ => None,
+        };
+        self.components_selecting(selected)
+    }
+
+    // The same, highlighting the given tile as picked for a swap
+    fn components_selecting(&self, selected: Option<usize>) -> Vec<CreateActionRow> {
+        components::board_components(
+            self.grid_size,
+            &self.tile_positions,
+            &self.board_options,
+            selected,
+            false,
+            self.turns.is_some(),
+        )
+    }
+
+    // The same controls, greyed out once the game is over
+    fn disabled_components(&self) -> Vec<CreateActionRow> {
+        components::board_components(
+            self.grid_size,
+            &self.tile_positions,
+            &self.board_options,
+            None,
+            true,
+            self.turns.is_some(),
+        )
+    }
+
+    // Message text for a board post: the latest status, the move count, whose turn it is in co-op
+    // games or the vote tally in channel plays games, and progress when the overlay is on. Text
+    // boards are followed by the grid itself; image boards carry their alt text on the upload.
+    fn board_caption(&self, text: &str, board: &RenderedBoard) -> String {
+        let mut caption = text.to_string();
+        if self.moves > 0 {
+            caption.push_str(&format!("\nMoves: {}", self.moves));
+        }
+        if let Some(turns) = self.turns.as_ref().filter(|_| !self.solved) {
+            caption.push_str(&format!("\n{}", turns.describe()));
+        }
+        if let Some(votes) = self.votes.as_ref().filter(|_| !self.solved) {
+            caption.push_str(&format!("\n{}", votes.describe(self.grid_size, self.board_options.notation)));
+        }
+        if self.board_options.correctness_overlay {
+            caption.push_str(&format!(
+                "\nSolved: {}% ({}/{} tiles in place)",
+                board::percent_solved(&self.tile_positions),
+                board::correct_slots(&self.tile_positions).len(),
+                self.tile_positions.len()
+            ));
+        }
+        match board {
+            RenderedBoard::Image(..) => caption,
+            RenderedBoard::Text(grid) => format!("{}\n{}", caption, grid),
+        }
+    }
+
+    // Backend for this game's board display
+    fn board_renderer(&self, renderer: &Arc<Renderer>, encoder: EncoderSettings) -> Arc<dyn BoardRenderer> {
+        board_renderer(self.board_options.display, renderer.clone(), encoder)
+    }
+
+    async fn recreate_scrambled_image(&self, renderer: Arc<dyn BoardRenderer>) -> Option<Result<RenderedBoard, BoxError>> {
+        let snapshot = BoardSnapshot {
+            image: self.original_image.clone()?,
+            grid_size: self.grid_size,
+            tile_positions: self.tile_positions.clone(),
+            options: self.board_options,
+            highlights: self.last_swap.clone(),
+            theme: self.theme.clone(),
+        };
+        Some(render_blocking(renderer, snapshot).await)
+    }
+}
+
+// Download or read a puzzle's image and decode it off the async runtime
+async fn load_image(puzzle: &PuzzleEntry) -> Result<Arc<RgbaImage>, BoxError> {
+    let bytes = fetch_image_bytes(&puzzle.image_source()).await?;
+    let image = tokio::task::spawn_blocking(move || image::load_from_memory(&bytes).map(|img| img.to_rgba8())).await??;
+    Ok(Arc::new(image))
+}
+
+async fn fetch_image_bytes(source: &ImageSource) -> Result<Vec<u8>, BoxError> {
+    match source {
+        ImageSource::Url(url) => {
+            let response = reqwest::get(url).await?.error_for_status()?;
+            Ok(response.bytes().await?.to_vec())
+        }
+        ImageSource::Path(path) => Ok(tokio::fs::read(path).await?),
+    }
+}
+
+// Build the shared renderer once at startup, with the font from PUZZLE_FONT if set
+fn renderer_from_env() -> Result<Renderer, String> {
+    match env::var("PUZZLE_FONT") {
+        Ok(path) => Renderer::with_font_file(path),
+        Err(_) => Ok(Renderer::with_default_font()),
+    }
+}
+
+// Label styling for the classic theme, customised with PUZZLE_LABEL_POSITION,
+// PUZZLE_LABEL_OPACITY, PUZZLE_LABEL_SIZE, PUZZLE_LABEL_COLOR and PUZZLE_LABEL_BACKGROUND.
+// Position and size also apply to every other theme.
+fn label_style_from_env() -> Result<LabelStyle, String> {
+    let mut style = LabelStyle::default();
+    if let Ok(position) = env::var("PUZZLE_LABEL_POSITION") {
+        style.position = position.parse()?;
+    }
+    if let Ok(opacity) = env::var("PUZZLE_LABEL_OPACITY") {
+        style.opacity = opacity
+            .parse()
+            .map_err(|_| format!("invalid PUZZLE_LABEL_OPACITY \"{}\"", opacity))?;
+    }
+    if let Ok(size) = env::var("PUZZLE_LABEL_SIZE") {
+        style.size_ratio = size
+            .parse()
+            .map_err(|_| format!("invalid PUZZLE_LABEL_SIZE \"{}\"", size))?;
+    }
+    if let Ok(color) = env::var("PUZZLE_LABEL_COLOR") {
+        style.text_color = parse_hex_color(&color)?;
+    }
+    if let Ok(color) = env::var("PUZZLE_LABEL_BACKGROUND") {
+        style.background_color = parse_hex_color(&color)?;
+    }
+    Ok(style)
+}
+
+// Read output settings from PUZZLE_IMAGE_FORMAT, PUZZLE_IMAGE_QUALITY and PUZZLE_UPLOAD_LIMIT (bytes)
+fn encoder_settings_from_env() -> Result<EncoderSettings, String> {
+    let mut settings = EncoderSettings::default();
+    if let Ok(format) = env::var("PUZZLE_IMAGE_FORMAT") {
+        settings.format = format.parse()?;
+    }
+    if let Ok(quality) = env::var("PUZZLE_IMAGE_QUALITY") {
+        settings.quality = quality
+            .parse()
+            .ok()
+            .filter(|quality| (1..=100).contains(quality))
+            .ok_or_else(|| format!("invalid PUZZLE_IMAGE_QUALITY \"{}\" (use 1-100)", quality))?;
+    }
+    if let Ok(limit) = env::var("PUZZLE_UPLOAD_LIMIT") {
+        settings.max_bytes = limit
+            .parse()
+            .map_err(|_| format!("invalid PUZZLE_UPLOAD_LIMIT \"{}\"", limit))?;
+    }
+    Ok(settings)
+}
+
+// Post the board as a new message and remember it as the game's puzzle message.
+// Mentions in the status text never ping.
+async fn send_board(
+    ctx: &Context,
+    channel_id: ChannelId,
+    game: &mut PicturePuzzleGame,
+    board: RenderedBoard,
+    text: &str,
+) -> Result<(), BoxError> {
+    let content = game.board_caption(text, &board);
+    let rows = game.components();
+    let message = channel_id
+        .send_message(&ctx.http, |m| {
+            m.content(content);
+            if let RenderedBoard::Image(attachment, alt_text) = board {
+                m.add_file(attachment);
+                describe_upload(&mut m.0, &alt_text);
+            }
+            m.components(|c| c.set_action_rows(rows))
+                .allowed_mentions(|mentions| mentions.empty_parse())
+        })
+        .await?;
+    game.board_message = Some((channel_id, message.id));
+    Ok(())
+}
+
+// Show the board after a move by editing the puzzle message in place, swapping in the new
+// image. The board is posted again instead if the message can no longer be edited, or if it
+// has been buried under more than PUZZLE_REPOST_AFTER newer messages.
+async fn update_board(
+    ctx: &Context,
+    channel_id: ChannelId,
+    game: &mut PicturePuzzleGame,
+    board: RenderedBoard,
+    text: &str,
+) -> Result<(), BoxError> {
+    if let Some((board_channel, message_id)) = game.board_message.filter(|&(board_channel, _)| board_channel == channel_id) {
+        let repost_after = *ctx.data.read().await.get::<RepostAfterKey>().unwrap();
+        let buried = match repost_after {
+            Some(limit) => is_buried(ctx, board_channel, message_id, limit).await,
+            None => false,
+        };
+
+        if buried {
+            if let Err(e) = board_channel.delete_message(&ctx.http, message_id).await {
+                eprintln!("Failed to delete buried puzzle message: {:?}", e);
+            }
+        } else {
+            let content = game.board_caption(text, &board);
+            let rows = game.components();
+            let result = board_channel
+                .edit_message(&ctx.http, message_id, |m| {
+                    m.content(content)
+                        .components(|c| c.set_action_rows(rows))
+                        .remove_all_attachments();
+                    if let RenderedBoard::Image(attachment, alt_text) = &board {
+                        m.attachment(attachment.clone());
+                        describe_upload(&mut m.0, alt_text);
                     }
-                }
+                    m
+                })
+                .await;
+            match result {
+                Ok(_) => return Ok(()),
+                Err(e) => eprintln!("Failed to edit puzzle message, posting a new one: {:?}", e),
             }
-    
-            return Some(DynamicImage::ImageRgba8(buffer));
         }
-    
-        None
     }
+    send_board(ctx, channel_id, game, board, text).await
 }
 
+// Whether at least `limit` messages have been posted since the puzzle message
+async fn is_buried(ctx: &Context, channel_id: ChannelId, message_id: MessageId, limit: u8) -> bool {
+    match channel_id.messages(&ctx.http, |retriever| retriever.after(message_id).limit(limit as u64)).await {
+        Ok(newer) => newer.len() >= limit as usize,
+        Err(e) => {
+            eprintln!("Failed to check for newer messages: {:?}", e);
+            false
+        }
+    }
+}
+
+// Disable the buttons on a finished game's puzzle message and forget it
+async fn retire_board(ctx: &Context, game: &mut PicturePuzzleGame) {
+    if let Some((channel_id, message_id)) = game.board_message.take() {
+        let rows = game.disabled_components();
+        if let Err(e) = channel_id
+            .edit_message(&ctx.http, message_id, |m| m.components(|c| c.set_action_rows(rows)))
+            .await
+        {
+            eprintln!("Failed to disable puzzle buttons: {:?}", e);
+        }
+    }
+}
+
+fn reveal_caption(puzzle: &PuzzleEntry) -> String {
+    format!(
+        "The puzzle has been solved! Here is the original image: **{}**\n{}",
+        puzzle.title, puzzle.attribution
+    )
+}
+
+// One game per channel. Each has its own lock, so slow renders and uploads in one channel
+// never hold up the shared data map or other channels.
 struct GameKey;
 
 impl TypeMapKey for GameKey {
-    type Value = PicturePuzzleGame;
+    type Value = HashMap<ChannelId, Arc<Mutex<PicturePuzzleGame>>>;
 }
 
-struct Handler;
+// Points per user, across all channels
+struct ScoresKey;
+
+impl TypeMapKey for ScoresKey {
+    type Value = HashMap<UserId, usize>;
+}
+
+// Puzzles finished across all channels. A puzzle revealed before it was solved is abandoned.
+#[derive(Debug, Default)]
+struct PuzzleStats {
+    solved: usize,
+    abandoned: usize,
+}
+
+struct StatsKey;
+
+impl TypeMapKey for StatsKey {
+    type Value = PuzzleStats;
+}
+
+// How long a puzzle must be played before its answer can be revealed
+struct RevealDelayKey;
+
+impl TypeMapKey for RevealDelayKey {
+    type Value = Duration;
+}
+
+// Repost the board once this many messages have been posted below it; None never reposts
+struct RepostAfterKey;
+
+impl TypeMapKey for RepostAfterKey {
+    type Value = Option<u8>;
+}
+
+struct RendererKey;
+
+impl TypeMapKey for RendererKey {
+    type Value = Arc<Renderer>;
+}
+
+// Accessibility preferences per user, applied to the puzzles they start
+struct AccessibilityKey;
+
+impl TypeMapKey for AccessibilityKey {
+    type Value = HashMap<UserId, BoardOptions>;
+}
+
+struct EncoderKey;
+
+impl TypeMapKey for EncoderKey {
+    type Value = EncoderSettings;
+}
+
+// Label style from the environment, the base every theme builds on
+struct LabelStyleKey;
+
+impl TypeMapKey for LabelStyleKey {
+    type Value = LabelStyle;
+}
+
+// Every server's settings, changed with `!config`
+struct ConfigKey;
+
+impl TypeMapKey for ConfigKey {
+    type Value = ConfigStore;
+}
+
+// Races waiting for players or under way, by race id. A channel holds one race, plus any
+// number of tournament matches and duels.
+struct RaceKey;
+
+impl TypeMapKey for RaceKey {
+    type Value = HashMap<u64, race::RaceEntry>;
+}
+
+// Team battles waiting for players or under way, one per channel
+struct TeamBattleKey;
+
+impl TypeMapKey for TeamBattleKey {
+    type Value = HashMap<ChannelId, Arc<Mutex<teams::TeamBattle>>>;
+}
+
+// Team results per server. Kept apart from players' scores.
+struct TeamStatsKey;
+
+impl TypeMapKey for TeamStatsKey {
+    type Value = teams::TeamStats;
+}
+
+// Duel challenges waiting for an answer, by challenge id
+struct DuelKey;
+
+impl TypeMapKey for DuelKey {
+    type Value = HashMap<u64, duel::Challenge>;
+}
+
+// How long a challenged player has to answer
+struct DuelExpiryKey;
+
+impl TypeMapKey for DuelExpiryKey {
+    type Value = Duration;
+}
+
+// Duel head-to-head records, kept across restarts
+struct DuelStatsKey;
+
+impl TypeMapKey for DuelStatsKey {
+    type Value = duel::DuelStats;
+}
+
+// Every server's coin balances and their ledger, kept across restarts
+struct EconomyKey;
+
+impl TypeMapKey for EconomyKey {
+    type Value = economy::Economy;
+}
+
+// Player skill and puzzle difficulty ratings, kept across restarts
+struct RatingsKey;
+
+impl TypeMapKey for RatingsKey {
+    type Value = ratings::RatingStore;
+}
+
+// Tournaments taking sign-ups or under way, one per channel
+struct TournamentKey;
+
+impl TypeMapKey for TournamentKey {
+    type Value = HashMap<ChannelId, Arc<Mutex<tournament::Tournament>>>;
+}
+
+// How long tournament registration stays open
+struct TournamentSignupKey;
+
+impl TypeMapKey for TournamentSignupKey {
+    type Value = Duration;
+}
+
+// Every server's tournament placements, kept across restarts
+struct TournamentStatsKey;
+
+impl TypeMapKey for TournamentStatsKey {
+    type Value = tournament::TournamentStats;
+}
+
+// Join window and time limit for races and team battles
+struct RaceTimingKey;
+
+impl TypeMapKey for RaceTimingKey {
+    type Value = RaceTiming;
+}
+
+struct ManifestKey;
+
+impl TypeMapKey for ManifestKey {
+    type Value = Arc<Manifest>;
+}
+
+// Clone out the channel's game handle and render settings, releasing the data lock straight away.
+// A channel gets an empty game the first time it is used.
+async fn game_context(ctx: &Context, channel_id: ChannelId) -> (Arc<Mutex<PicturePuzzleGame>>, Arc<Renderer>, EncoderSettings) {
+    {
+        let data = ctx.data.read().await;
+        if let Some(game) = data.get::<GameKey>().unwrap().get(&channel_id) {
+            return (
+                game.clone(),
+                data.get::<RendererKey>().unwrap().clone(),
+                *data.get::<EncoderKey>().unwrap(),
+            );
+        }
+    }
+
+    let mut data = ctx.data.write().await;
+    let game = data
+        .get_mut::<GameKey>()
+        .unwrap()
+        .entry(channel_id)
+        .or_insert_with(|| Arc::new(Mutex::new(PicturePuzzleGame::new())))
+        .clone();
+    (
+        game,
+        data.get::<RendererKey>().unwrap().clone(),
+        *data.get::<EncoderKey>().unwrap(),
+    )
+}
+
+async fn guild_config(ctx: &Context, guild_id: Option<GuildId>) -> GuildConfig {
+    ctx.data.read().await.get::<ConfigKey>().unwrap().get(guild_id)
+}
+
+// Award points for solving a puzzle, with coins to match in servers, and count it in the stats
+async fn add_score(ctx: &Context, guild_id: Option<GuildId>, user_id: UserId, points: usize) {
+    let mut data = ctx.data.write().await;
+    *data.get_mut::<ScoresKey>().unwrap().entry(user_id).or_insert(0) += points;
+    if let Some(guild_id) = guild_id {
+        let coins = points as u64 * economy::COINS_PER_POINT;
+        data.get_mut::<EconomyKey>().unwrap().credit(guild_id, user_id, coins, "Solved a puzzle");
+    }
+    data.get_mut::<StatsKey>().unwrap().solved += 1;
+}
+
+// Share a co-op puzzle's points (and coins) between its players and count it once in the stats
+async fn add_shared_score(ctx: &Context, guild_id: Option<GuildId>, shares: &[(UserId, usize)]) {
+    let mut data = ctx.data.write().await;
+    let scores = data.get_mut::<ScoresKey>().unwrap();
+    for &(user_id, points) in shares {
+        *scores.entry(user_id).or_insert(0) += points;
+    }
+    if let Some(guild_id) = guild_id {
+        let ledger = data.get_mut::<EconomyKey>().unwrap();
+        for &(user_id, points) in shares {
+            ledger.credit(guild_id, user_id, points as u64 * economy::COINS_PER_POINT, "Share of a puzzle's points");
+        }
+    }
+    data.get_mut::<StatsKey>().unwrap().solved += 1;
+}
+
+// Read PUZZLE_MIN_REVEAL_SECONDS. Defaults to 0, so answers can be revealed straight away.
+fn reveal_delay_from_env() -> Result<Duration, String> {
+    match env::var("PUZZLE_MIN_REVEAL_SECONDS") {
+        Ok(value) => value
+            .parse()
+            .map(Duration::from_secs)
+            .map_err(|_| format!("invalid PUZZLE_MIN_REVEAL_SECONDS \"{}\"", value)),
+        Err(_) => Ok(Duration::ZERO),
+    }
+}
+
+// Most messages PUZZLE_REPOST_AFTER can wait for
+const MAX_REPOST_AFTER: u8 = 100;
+
+// Read PUZZLE_REPOST_AFTER: a message count, or 0 to always edit in place. Defaults to 15.
+fn repost_after_from_env() -> Result<Option<u8>, String> {
+    match env::var("PUZZLE_REPOST_AFTER") {
+        Ok(value) => value
+            .parse::<u8>()
+            .ok()
+            // Discord returns at most 100 messages at a time, so a larger limit could never be reached
+            .filter(|&limit| limit <= MAX_REPOST_AFTER)
+            .map(|limit| (limit > 0).then_some(limit))
+            .ok_or_else(|| format!("invalid PUZZLE_REPOST_AFTER \"{}\" (use 0-{})", value, MAX_REPOST_AFTER)),
+        Err(_) => Ok(Some(15)),
+    }
+}
+
+// Read PUZZLE_RACE_JOIN_SECONDS and PUZZLE_RACE_MINUTES. Defaults to 30 seconds to join and 10 minutes to finish.
+fn race_timing_from_env() -> Result<RaceTiming, String> {
+    let read = |name: &str, default: u64| match env::var(name) {
+        Ok(value) => value
+            .parse::<u64>()
+            .ok()
+            .filter(|&amount| amount > 0)
+            .ok_or_else(|| format!("invalid {} \"{}\"", name, value)),
+        Err(_) => Ok(default),
+    };
+    Ok(RaceTiming {
+        join_window: Duration::from_secs(read("PUZZLE_RACE_JOIN_SECONDS", 30)?),
+        time_limit: Duration::from_secs(read("PUZZLE_RACE_MINUTES", 10)? * 60),
+    })
+}
+
+// Read PUZZLE_DUEL_EXPIRY_SECONDS. Defaults to 1 minute.
+fn duel_expiry_from_env() -> Result<Duration, String> {
+    match env::var("PUZZLE_DUEL_EXPIRY_SECONDS") {
+        Ok(value) => value
+            .parse::<u64>()
+            .ok()
+            .filter(|&seconds| seconds > 0)
+            .map(Duration::from_secs)
+            .ok_or_else(|| format!("invalid PUZZLE_DUEL_EXPIRY_SECONDS \"{}\"", value)),
+        Err(_) => Ok(Duration::from_secs(60)),
+    }
+}
+
+// Read PUZZLE_TOURNAMENT_SIGNUP_SECONDS. Defaults to 2 minutes.
+fn tournament_signup_from_env() -> Result<Duration, String> {
+    match env::var("PUZZLE_TOURNAMENT_SIGNUP_SECONDS") {
+        Ok(value) => value
+            .parse::<u64>()
+            .ok()
+            .filter(|&seconds| seconds > 0)
+            .map(Duration::from_secs)
+            .ok_or_else(|| format!("invalid PUZZLE_TOURNAMENT_SIGNUP_SECONDS \"{}\"", value)),
+        Err(_) => Ok(Duration::from_secs(120)),
+    }
+}
+
+struct Handler {
+    // Register slash commands in this guild only, so changes show up instantly during development
+    dev_guild: Option<GuildId>,
+}
 
 #[async_trait]
 impl EventHandler for Handler {
+    async fn ready(&self, ctx: Context, ready: Ready) {
+        println!("{} is connected", ready.user.name);
+        if let Err(e) = slash::register(&ctx, self.dev_guild).await {
+            eprintln!("Failed to register slash commands: {:?}", e);
+        }
+    }
+
     async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
-        if let Interaction::MessageComponent(component) = interaction {
-            let mut data = ctx.data.write().await;
-            let game = data.get_mut::<GameKey>().unwrap();
-
-            match component.data.custom_id.as_str() {
-                "solve_puzzle" => {
-                    // Ensure we have a valid game state and image URL
-                    let original_image_url = &game.current_image_url;
-                    if original_image_url.is_empty() {
-                        eprintln!("No image URL set in game state.");
-                        return;
-                    }
+        match interaction {
+            Interaction::ApplicationCommand(command) => slash::handle_command(&ctx, &command).await,
+            Interaction::Autocomplete(autocomplete) => slash::handle_autocomplete(&ctx, &autocomplete).await,
+            Interaction::MessageComponent(component) => handle_component(&ctx, &component).await,
+            Interaction::ModalSubmit(submission) => modals::handle_submit(&ctx, &submission).await,
+            _ => {}
+        }
+    }
+}
 
-                    let original_image_path = "original_image.png"; // Save image locally
-
-                    // Download and save the original image
-                    match reqwest::get(original_image_url).await {
-                        Ok(response) => {
-                            match response.bytes().await {
-                                Ok(bytes) => {
-                                    match image::load_from_memory(&bytes) {
-                                        Ok(original_image) => {
-                                            if let Err(e) = original_image.save(original_image_path) {
-                                                eprintln!("Failed to save original image: {:?}", e);
-                                            } else {
-                                                if let Err(e) = component.create_interaction_response(&ctx.http, |response| {
-                                                    response
-                                                        .kind(InteractionResponseType::ChannelMessageWithSource)
-                                                        .interaction_response_data(|message| {
-                                                            message.content("The puzzle has been solved! Here is the original image:")
-                                                        })
-                                                })
-                                                .await {
-                                                    eprintln!("Failed to respond to interaction: {:?}", e);
-                                                }
-
-                                                if let Err(e) = component.channel_id.send_message(&ctx.http, |m| {
-                                                    m.add_file(original_image_path)
-                                                })
-                                                .await {
-                                                    eprintln!("Failed to send follow-up message with image: {:?}", e);
-                                                }
-                                            }
-                                        },
-                                        Err(e) => {
-                                            eprintln!("Failed to load original image: {:?}", e);
-                                        }
-                                    }
-                                },
-                                Err(e) => {
-                                    eprintln!("Failed to read original image bytes: {:?}", e);
-                                }
-                            }
-                        },
-                        Err(e) => {
-                            eprintln!("Failed to fetch original image: {:?}", e);
-                        }
-                    }
-                }
-                "swap_tiles" => {
-                    let channel_id = component.channel_id;
-                    if let Err(e) = channel_id.send_message(&ctx.http, |m| {
-                        m.content("Please reply with the indices of the tiles you want to swap, separated by a space (e.g., '1 2').")
-                    })
-                    .await {
-                        eprintln!("Failed to send prompt message: {:?}", e);
-                    }
+async fn handle_component(ctx: &Context, component: &MessageComponentInteraction) {
+    match component.data.custom_id.as_str() {
+        "solve_puzzle" => handle_reveal_click(ctx, component).await,
+        coop::JOIN_BUTTON => coop::handle_join(ctx, component, true).await,
+        coop::LEAVE_BUTTON => coop::handle_join(ctx, component, false).await,
+        "swap_tiles" => prompts::prompt_swap(ctx, component).await,
+        custom_id => {
+            if let Some((form, previous)) = modals::requested_form(custom_id) {
+                modals::show_form(ctx, component, form, previous).await;
+            } else if teams::is_battle_button(custom_id) {
+                teams::handle_component(ctx, component).await;
+            } else if duel::is_duel_button(custom_id) {
+                duel::handle_component(ctx, component).await;
+            } else if tournament::is_tournament_button(custom_id) {
+                tournament::handle_component(ctx, component).await;
+            } else if race::is_race_button(custom_id) {
+                race::handle_component(ctx, component).await;
+            } else if tutorial::is_tutorial_button(custom_id) {
+                tutorial::handle_click(ctx, component).await;
+            } else if let Some(slot) = components::clicked_tile(custom_id, &component.data.values) {
+                handle_tile_click(ctx, component, slot).await;
+            }
+        }
+    }
+}
+
+// Ask whoever pressed "Solve Puzzle" to confirm before the answer is revealed for everyone
+async fn handle_reveal_click(ctx: &Context, component: &MessageComponentInteraction) {
+    let question = {
+        let (game, _, _) = game_context(ctx, component.channel_id).await;
+        let game = game.lock().await;
+        if game.puzzle.is_none() {
+            None
+        } else if game.solved {
+            Some("Reveal the original image and end the puzzle?")
+        } else {
+            Some("Reveal the answer and end the puzzle for everyone? Unless you started it or are a moderator, this counts as your vote to reveal.")
+        }
+    };
+    let question = match question {
+        Some(question) => question,
+        None => {
+            if let Err(e) = component.create_interaction_response(&ctx.http, |response| {
+                response
+                    .kind(InteractionResponseType::ChannelMessageWithSource)
+                    .interaction_response_data(|message| message.content("There is no puzzle in progress.").ephemeral(true))
+            })
+            .await {
+                eprintln!("Failed to respond to reveal: {:?}", e);
+            }
+            return;
+        }
+    };
 
-                    // Fetch and filter messages
-                    match channel_id.messages(&ctx.http, |retriever| {
-                        retriever.limit(100)
+    if let Err(e) = component.create_interaction_response(&ctx.http, |response| {
+        response
+            .kind(InteractionResponseType::ChannelMessageWithSource)
+            .interaction_response_data(|message| {
+                message.content(question).ephemeral(true).components(|c| {
+                    c.create_action_row(|row| {
+                        row.create_button(|button| button.label("Reveal").custom_id("reveal_confirm").style(ButtonStyle::Danger))
+                            .create_button(|button| button.label("Keep playing").custom_id("reveal_cancel").style(ButtonStyle::Secondary))
                     })
-                    .await {
-                        Ok(messages) => {
-                            let user_messages: Vec<_> = messages
-                                .into_iter()
-                                .filter(|msg| msg.author.id == component.user.id)
-                                .collect();
-
-                            if let Some(reply) = user_messages.first() {
-                                let content = reply.content.clone();
-                                let mut indices = content.split_whitespace()
-                                    .filter_map(|s| s.parse::<usize>().ok())
-                                    .collect::<Vec<_>>();
-
-                                if indices.len() == 2 {
-                                    let index1 = indices[0] - 1;
-                                    let index2 = indices[1] - 1;
-
-                                    if index1 < 9 && index2 < 9 {
-                                        game.tile_positions.swap(index1, index2);
-
-                                        if let Some(new_scrambled_image) = game.recreate_scrambled_image() {
-                                            if let Err(e) = new_scrambled_image.save("scrambled_image.png") {
-                                                eprintln!("Failed to save scrambled image: {:?}", e);
-                                            } else {
-                                                if let Err(e) = channel_id.send_message(&ctx.http, |m| {
-                                                    m.content("Here is the updated puzzle after the swap!")
-                                                        .add_file("scrambled_image.png")
-                                                })
-                                                .await {
-                                                    eprintln!("Failed to send updated puzzle image: {:?}", e);
-                                                }
-
-                                                if game.tile_positions == game.correct_positions {
-                                                    if let Err(e) = channel_id.send_message(&ctx.http, |m| {
-                                                        m.content("Congratulations! You solved the puzzle!")
-                                                    })
-                                                    .await {
-                                                        eprintln!("Failed to send congratulations message: {:?}", e);
-                                                    }
-                                                    game.increase_score(component.user.id);
-                                                }
-                                            }
-                                        }
-                                    } else {
-                                        if let Err(e) = channel_id.send_message(&ctx.http, |m| {
-                                            m.content("Invalid tile indices! Please use numbers between 1 and 9.")
-                                        })
-                                        .await {
-                                            eprintln!("Failed to send invalid indices message: {:?}", e);
-                                        }
-                                    }
-                                } else {
-                                    if let Err(e) = channel_id.send_message(&ctx.http, |m| {
-                                        m.content("Please provide exactly two tile indices.")
-                                    })
-                                    .await {
-                                        eprintln!("Failed to send invalid indices message: {:?}", e);
-                                    }
-                                }
-                            }
-                        }
-                        Err(e) => {
-                            eprintln!("Failed to fetch messages: {:?}", e);
-                        }
-                    }
+                })
+            })
+    })
+    .await {
+        eprintln!("Failed to ask for reveal confirmation: {:?}", e);
+        return;
+    }
+    let confirmation = match component.get_interaction_response(&ctx.http).await {
+        Ok(confirmation) => confirmation,
+        Err(e) => {
+            eprintln!("Failed to fetch reveal confirmation: {:?}", e);
+            return;
+        }
+    };
+
+    let answer = confirmation
+        .await_component_interaction(ctx)
+        .author_id(component.user.id)
+        .timeout(Duration::from_secs(30))
+        .await;
+    let text = match answer {
+        Some(answer) if answer.data.custom_id == "reveal_confirm" => {
+            if let Err(e) = answer
+                .create_interaction_response(&ctx.http, |response| response.kind(InteractionResponseType::DeferredUpdateMessage))
+                .await
+            {
+                eprintln!("Failed to acknowledge reveal: {:?}", e);
+            }
+            let invoker = Invoker {
+                channel_id: component.channel_id,
+                guild_id: component.guild_id,
+                user_id: component.user.id,
+            };
+            match actions::give_up(ctx, &invoker).await {
+                Ok(feedback) => feedback.unwrap_or_else(|| "Puzzle revealed.".to_string()),
+                Err(e) => {
+                    eprintln!("Failed to reveal puzzle: {:?}", e);
+                    "Something went wrong. Please try again.".to_string()
                 }
-                _ => {}
             }
         }
+        Some(answer) => {
+            if let Err(e) = answer
+                .create_interaction_response(&ctx.http, |response| response.kind(InteractionResponseType::DeferredUpdateMessage))
+                .await
+            {
+                eprintln!("Failed to acknowledge reveal: {:?}", e);
+            }
+            "The puzzle carries on.".to_string()
+        }
+        None => "No answer received, so the puzzle carries on.".to_string(),
+    };
+    if let Err(e) = component
+        .edit_original_interaction_response(&ctx.http, |message| message.content(text).components(|c| c))
+        .await
+    {
+        eprintln!("Failed to update reveal confirmation: {:?}", e);
+    }
+}
+
+// The first tile click selects a tile, the second swaps it with the first.
+// Clicking the selected tile again clears the selection.
+async fn handle_tile_click(ctx: &Context, component: &MessageComponentInteraction, slot: usize) {
+    let (game, _, _) = game_context(ctx, component.channel_id).await;
+    let mut game = game.lock().await;
+    if game.puzzle.is_none() || slot >= game.tile_positions.len() {
+        if let Err(e) = component.create_interaction_response(&ctx.http, |response| {
+            response
+                .kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|message| message.content("There is no puzzle in progress.").ephemeral(true))
+        })
+        .await {
+            eprintln!("Failed to respond to tile click: {:?}", e);
+        }
+        return;
+    }
+
+    let user_id = component.user.id;
+    if let Some(refusal) = game.turns.as_ref().and_then(|turns| turns.refusal(user_id)) {
+        drop(game);
+        if let Err(e) = component.create_interaction_response(&ctx.http, |response| {
+            response
+                .kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|message| message.content(refusal).ephemeral(true))
+        })
+        .await {
+            eprintln!("Failed to respond to tile click: {:?}", e);
+        }
+        return;
+    }
+
+    let first = game.selected_tiles.get(&user_id).copied();
+
+    if let Some(first) = first.filter(|&first| first != slot) {
+        drop(game);
+        if let Err(e) = component.create_interaction_response(&ctx.http, |response| {
+            response.kind(InteractionResponseType::DeferredUpdateMessage)
+        })
+        .await {
+            eprintln!("Failed to acknowledge tile click: {:?}", e);
+        }
+
+        let invoker = Invoker {
+            channel_id: component.channel_id,
+            guild_id: component.guild_id,
+            user_id,
+        };
+        match actions::swap_slots(ctx, &invoker, (first, slot)).await {
+            Ok(Some(feedback)) => {
+                if let Err(e) = component.create_followup_message(&ctx.http, |m| m.content(feedback).ephemeral(true)).await {
+                    eprintln!("Failed to send swap feedback: {:?}", e);
+                }
+            }
+            Ok(None) => {}
+            Err(e) => eprintln!("Failed to swap tiles: {:?}", e),
+        }
+        return;
+    }
+
+    // Clicking the same tile again deselects it
+    let selected = if first == Some(slot) {
+        game.selected_tiles.remove(&user_id);
+        None
+    } else {
+        game.selected_tiles.insert(user_id, slot);
+        Some(slot)
+    };
+    let rows = game.components_selecting(selected);
+    if let Err(e) = component.create_interaction_response(&ctx.http, |response| {
+        response
+            .kind(InteractionResponseType::UpdateMessage)
+            .interaction_response_data(|message| message.components(|c| c.set_action_rows(rows)))
+    })
+    .await {
+        eprintln!("Failed to update tile buttons: {:?}", e);
     }
 }
 
@@ -278,124 +947,655 @@ async fn main() {
 
     let token = env::var("DISCORD_TOKEN").expect("Token not found");
 
-    let intents = GatewayIntents::GUILD_MESSAGES | GatewayIntents::MESSAGE_CONTENT;
+    let manifest_path = env::var("PUZZLE_MANIFEST").unwrap_or_else(|_| "puzzles.toml".to_string());
+    let manifest = Manifest::load(&manifest_path)
+        .unwrap_or_else(|e| panic!("Invalid puzzle manifest {}: {}", manifest_path, e));
+    let renderer = renderer_from_env()
+        .unwrap_or_else(|e| panic!("Invalid font: {}", e));
+    let label_style = label_style_from_env()
+        .unwrap_or_else(|e| panic!("Invalid label settings: {}", e));
+    let encoder = encoder_settings_from_env()
+        .unwrap_or_else(|e| panic!("Invalid image output settings: {}", e));
+    let repost_after = repost_after_from_env()
+        .unwrap_or_else(|e| panic!("Invalid board settings: {}", e));
+    let reveal_delay = reveal_delay_from_env()
+        .unwrap_or_else(|e| panic!("Invalid reveal settings: {}", e));
+    let race_timing = race_timing_from_env()
+        .unwrap_or_else(|e| panic!("Invalid race settings: {}", e));
+    let duel_expiry = duel_expiry_from_env()
+        .unwrap_or_else(|e| panic!("Invalid duel settings: {}", e));
+    let tournament_signup = tournament_signup_from_env()
+        .unwrap_or_else(|e| panic!("Invalid tournament settings: {}", e));
+
+    // Server settings live in PUZZLE_CONFIG, guild_config.json by default
+    let config_path = env::var("PUZZLE_CONFIG").unwrap_or_else(|_| "guild_config.json".to_string());
+    let config_store = ConfigStore::load(config_path.into())
+        .unwrap_or_else(|e| panic!("Invalid server settings: {}", e));
+
+    // Ratings live in PUZZLE_RATINGS, ratings.json by default
+    let ratings_path = env::var("PUZZLE_RATINGS").unwrap_or_else(|_| "ratings.json".to_string());
+    let rating_store = ratings::RatingStore::load(ratings_path.into())
+        .unwrap_or_else(|e| panic!("Invalid ratings: {}", e));
+
+    // Coins live in PUZZLE_COINS, coins.json by default
+    let coins_path = env::var("PUZZLE_COINS").unwrap_or_else(|_| "coins.json".to_string());
+    let economy = economy::Economy::load(coins_path.into())
+        .unwrap_or_else(|e| panic!("Invalid coin balances: {}", e));
+
+    // Duel records live in PUZZLE_DUEL_STATS, duel_stats.json by default
+    let duel_stats_path = env::var("PUZZLE_DUEL_STATS").unwrap_or_else(|_| "duel_stats.json".to_string());
+    let duel_stats = duel::DuelStats::load(duel_stats_path.into())
+        .unwrap_or_else(|e| panic!("Invalid duel results: {}", e));
+
+    // Team battle results live in PUZZLE_TEAM_STATS, team_stats.json by default
+    let team_stats_path = env::var("PUZZLE_TEAM_STATS").unwrap_or_else(|_| "team_stats.json".to_string());
+    let team_stats = teams::TeamStats::load(team_stats_path.into())
+        .unwrap_or_else(|e| panic!("Invalid team results: {}", e));
+
+    // Tournament placements live in PUZZLE_TOURNAMENT_STATS, tournament_stats.json by default
+    let tournament_stats_path = env::var("PUZZLE_TOURNAMENT_STATS").unwrap_or_else(|_| "tournament_stats.json".to_string());
+    let tournament_stats = tournament::TournamentStats::load(tournament_stats_path.into())
+        .unwrap_or_else(|e| panic!("Invalid tournament results: {}", e));
+
+    let dev_guild = env::var("DISCORD_DEV_GUILD").ok().map(|id| {
+        GuildId(id.parse().unwrap_or_else(|_| panic!("Invalid DISCORD_DEV_GUILD \"{}\"", id)))
+    });
+
+    // Prefix commands need the privileged MESSAGE_CONTENT intent; slash commands do not
+    let prefix_commands = env::var("PUZZLE_PREFIX_COMMANDS").map_or(true, |value| value != "off");
+    let mut intents = GatewayIntents::GUILDS | GatewayIntents::GUILD_MESSAGES;
+    if prefix_commands {
+        intents |= GatewayIntents::MESSAGE_CONTENT;
+    }
 
     let mut client = Client::builder(&token, intents)
         .framework(StandardFramework::new()
-            .configure(|c| c.prefix("!"))
-            .group(&GENERAL_GROUP))
-        .event_handler(Handler)
+            .configure(|c| c.prefix("").dynamic_prefix(guild_prefix))
+            .on_dispatch_error(dispatch_error)
+            .group(&GENERAL_GROUP)
+            .group(&CONFIG_GROUP))
+        .event_handler(Handler { dev_guild })
         .await
         .expect("Error creating client");
 
     {
         let mut data = client.data.write().await;
-        data.insert::<GameKey>(PicturePuzzleGame::new());
+        data.insert::<GameKey>(HashMap::new());
+        data.insert::<ScoresKey>(HashMap::new());
+        data.insert::<RepostAfterKey>(repost_after);
+        data.insert::<StatsKey>(PuzzleStats::default());
+        data.insert::<RevealDelayKey>(reveal_delay);
+        data.insert::<RaceKey>(HashMap::new());
+        data.insert::<RaceTimingKey>(race_timing);
+        data.insert::<TeamBattleKey>(HashMap::new());
+        data.insert::<TeamStatsKey>(team_stats);
+        data.insert::<DuelKey>(HashMap::new());
+        data.insert::<DuelExpiryKey>(duel_expiry);
+        data.insert::<DuelStatsKey>(duel_stats);
+        data.insert::<RatingsKey>(rating_store);
+        data.insert::<EconomyKey>(economy);
+        data.insert::<TournamentKey>(HashMap::new());
+        data.insert::<TournamentSignupKey>(tournament_signup);
+        data.insert::<TournamentStatsKey>(tournament_stats);
+        data.insert::<ManifestKey>(Arc::new(manifest));
+        data.insert::<RendererKey>(Arc::new(renderer));
+        data.insert::<EncoderKey>(encoder);
+        data.insert::<AccessibilityKey>(HashMap::new());
+        data.insert::<LabelStyleKey>(label_style);
+        data.insert::<ConfigKey>(config_store);
     }
 
+    tokio::spawn(config::keep_saved::<ConfigKey>(client.data.clone()));
+    tokio::spawn(config::keep_saved::<RatingsKey>(client.data.clone()));
+    tokio::spawn(config::keep_saved::<DuelStatsKey>(client.data.clone()));
+    tokio::spawn(config::keep_saved::<TournamentStatsKey>(client.data.clone()));
+    tokio::spawn(config::keep_saved::<TeamStatsKey>(client.data.clone()));
+    tokio::spawn(config::keep_saved::<EconomyKey>(client.data.clone()));
+
     if let Err(why) = client.start().await {
         println!("Client error: {:?}", why);
     }
 }
 
-fn create_button_row() -> CreateActionRow {
-    let mut row = CreateActionRow::default();
-    row.add_button({
-        let mut button = CreateButton::default();
-        button.label("Solve Puzzle")
-              .custom_id("solve_puzzle")
-              .style(ButtonStyle::Primary);
-        button
-    });
-    row.add_button({
-        let mut button = CreateButton::default();
-        button.label("Swap Tiles")
-              .custom_id("swap_tiles")
-              .style(ButtonStyle::Secondary);
-        button
-    });
-    row
+// Each server's own prefix, or `!` in direct messages
+#[hook]
+async fn guild_prefix(ctx: &Context, msg: &Message) -> Option<String> {
+    Some(ctx.data.read().await.get::<ConfigKey>().unwrap().prefix(msg.guild_id).to_string())
+}
+
+// Ignore puzzle commands outside a server's allowed channels. Only the General group has this
+// check, so settings can be changed anywhere and admins can always fix a channel list that leaves
+// nowhere to play.
+#[check]
+#[name = "AllowedChannel"]
+async fn allowed_channel(ctx: &Context, msg: &Message, _args: &mut Args, _options: &CommandOptions) -> Result<(), Reason> {
+    if guild_config(ctx, msg.guild_id).await.allows_channel(msg.channel_id) {
+        Ok(())
+    } else {
+        Err(Reason::Log("Command used outside the allowed channels".to_string()))
+    }
+}
+
+#[hook]
+async fn dispatch_error(ctx: &Context, msg: &Message, error: DispatchError, _command_name: &str) {
+    let text = match error {
+        DispatchError::LackingPermissions(_) => "Only members with Manage Server can change the bot's settings.",
+        DispatchError::OnlyForGuilds => "Settings can only be changed in a server.",
+        _ => return,
+    };
+    if let Err(e) = msg.channel_id.say(&ctx.http, text).await {
+        eprintln!("Failed to report command error: {:?}", e);
+    }
+}
+
+// Send an action's feedback to the channel, without pinging anyone it mentions
+async fn reply(ctx: &Context, msg: &Message, result: ActionResult) -> CommandResult {
+    if let Some(text) = result? {
+        msg.channel_id
+            .send_message(&ctx.http, |m| m.content(text).allowed_mentions(|mentions| mentions.empty_parse()))
+            .await?;
+    }
+    Ok(())
 }
 
 #[command]
-async fn start_picture_puzzle(ctx: &Context, msg: &Message) -> CommandResult {
-    let mut data = ctx.data.write().await;
-    let game = data.get_mut::<GameKey>().unwrap();
+async fn start_picture_puzzle(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    // An optional argument picks a category or tag, e.g. `!start_picture_puzzle nature`
+    let filter = args.rest().trim();
+    let filter = (!filter.is_empty()).then_some(filter);
+    reply(ctx, msg, actions::start(ctx, &Invoker::from_message(msg), filter).await).await
+}
+
+// Start a puzzle where players take turns, e.g. `!coop nature`
+#[command]
+async fn coop(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let filter = args.rest().trim();
+    let filter = (!filter.is_empty()).then_some(filter);
+    reply(ctx, msg, actions::start_coop(ctx, &Invoker::from_message(msg), filter).await).await
+}
+
+// Start a puzzle the whole channel plays by voting for each swap, e.g. `!channel_plays nature`
+#[command]
+async fn channel_plays(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let filter = args.rest().trim();
+    let filter = (!filter.is_empty()).then_some(filter);
+    reply(ctx, msg, actions::start_channel_plays(ctx, &Invoker::from_message(msg), filter).await).await
+}
+
+// Vote for a swap in a channel plays puzzle, e.g. `!vote 3 7`
+#[command]
+async fn vote(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let tile1 = args.single::<String>()?;
+    let tile2 = args.single::<String>()?;
+    reply(ctx, msg, actions::vote(ctx, &Invoker::from_message(msg), &tile1, &tile2).await).await
+}
 
-    let image_url = "https://images.pexels.com/photos/7418561/pexels-photo-7418561.jpeg?auto=compress&cs=tinysrgb&w=1260&h=750&dpr=2";
-    game.load_image(image_url).await;
+// Race everyone who joins within the window, each on a private copy of the same scramble
+#[command]
+async fn race(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let filter = args.rest().trim();
+    let filter = (!filter.is_empty()).then_some(filter);
+    reply(ctx, msg, race::start(ctx, &Invoker::from_message(msg), filter).await).await
+}
 
-    // Send the scrambled image and buttons
+// Challenge someone to a head-to-head race, e.g. `!duel @someone 4` for a 4x4 board, or
+// `!duel @someone 4 wager 50` to stake 50 coins each
+#[command]
+async fn duel(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let words: Vec<&str> = args.rest().split_whitespace().filter(|word| !word.starts_with("<@")).collect();
+    let (size, wager) = match words.iter().position(|word| word.eq_ignore_ascii_case("wager")) {
+        Some(index) => (words[..index].first().copied(), Some(words.get(index + 1).copied().unwrap_or(""))),
+        None => (words.first().copied(), None),
+    };
+    let invoker = Invoker::from_message(msg);
+    reply(ctx, msg, duel::challenge(ctx, &invoker, msg.mentions.first(), size, wager).await).await
+}
+
+// Your duel rating, or another player's with `!duel_record @someone`. Mention two players to see
+// their record against each other.
+#[command]
+async fn duel_record(ctx: &Context, msg: &Message) -> CommandResult {
+    let (user_id, other) = match msg.mentions.as_slice() {
+        [] => (msg.author.id, None),
+        [other] => (msg.author.id, Some(other.id)),
+        [first, second, ..] => (first.id, Some(second.id)),
+    };
+    reply(ctx, msg, duel::record(ctx, user_id, other).await).await
+}
+
+// Teams named in the arguments, or role mentions, e.g. `!battle @Red @Blue`; Red and Blue by default
+#[command]
+async fn battle(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let team_args: Vec<&str> = args.rest().split_whitespace().collect();
+    reply(ctx, msg, teams::start(ctx, &Invoker::from_message(msg), &team_args).await).await
+}
+
+#[command]
+async fn team_leaderboard(ctx: &Context, msg: &Message) -> CommandResult {
+    reply(ctx, msg, teams::leaderboard(ctx, msg.guild_id).await).await
+}
+
+// `!tournament create [single|double] [category]`, then `start`, `bracket`, `cancel` or `stats [@user]`
+#[command]
+async fn tournament(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let invoker = Invoker::from_message(msg);
+    let (action, rest) = args.rest().trim().split_once(' ').unwrap_or((args.rest().trim(), ""));
+    let result = match action.to_lowercase().as_str() {
+        "create" => tournament::create(ctx, &invoker, rest).await,
+        "start" => tournament::start_now(ctx, &invoker).await,
+        "bracket" => tournament::show_bracket(ctx, msg.channel_id).await,
+        "cancel" => tournament::cancel(ctx, &invoker).await,
+        "stats" => {
+            let user_id = msg.mentions.first().map_or(msg.author.id, |user| user.id);
+            tournament::stats(ctx, msg.guild_id, user_id).await
+        }
+        _ => Ok(Some(
+            "Use `!tournament create [single|double] [category]`, then `start`, `bracket`, `cancel` or `stats [@user]`."
+                .to_string(),
+        )),
+    };
+    reply(ctx, msg, result).await
+}
+
+#[command]
+async fn submit_guess(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    reply(ctx, msg, actions::guess(ctx, &Invoker::from_message(msg), args.rest()).await).await
+}
+
+#[command]
+async fn swap_tiles(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    // Get the two tiles to swap from the command arguments, as numbers or coordinates
+    let tile1 = args.single::<String>()?;
+    let tile2 = args.single::<String>()?;
+    reply(ctx, msg, actions::swap(ctx, &Invoker::from_message(msg), &tile1, &tile2).await).await
+}
+
+#[command]
+async fn hint(ctx: &Context, msg: &Message) -> CommandResult {
+    reply(ctx, msg, actions::hint(ctx, &Invoker::from_message(msg)).await).await
+}
+
+#[command]
+async fn giveup(ctx: &Context, msg: &Message) -> CommandResult {
+    reply(ctx, msg, actions::give_up(ctx, &Invoker::from_message(msg)).await).await
+}
+
+#[command]
+async fn leaderboard(ctx: &Context, msg: &Message) -> CommandResult {
+    reply(ctx, msg, actions::leaderboard(ctx).await).await
+}
+
+// `!peek` sends you a private look at the original picture, for coins
+#[command]
+async fn peek(ctx: &Context, msg: &Message) -> CommandResult {
+    reply(ctx, msg, actions::peek(ctx, &Invoker::from_message(msg)).await).await
+}
+
+// `!coins [@user]` shows a balance; also `history [@user]`, and `grant` or `revoke @user <amount> [reason]`
+// for members with Manage Server
+#[command]
+async fn coins(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let invoker = Invoker::from_message(msg);
+    let user_id = msg.mentions.first().map_or(msg.author.id, |user| user.id);
+    let words: Vec<&str> = args.rest().split_whitespace().filter(|word| !word.starts_with("<@")).collect();
+    let result = match words.as_slice() {
+        [] => economy::balance(ctx, msg.guild_id, user_id).await,
+        ["history", ..] => economy::history(ctx, msg.guild_id, user_id).await,
+        [action @ ("grant" | "revoke"), amount, reason @ ..] => {
+            let target = msg.mentions.first().map(|user| user.id);
+            economy::adjust(ctx, &invoker, *action == "grant", target, amount, &reason.join(" ")).await
+        }
+        _ => Ok(Some(
+            "Use `coins [@user]`, `coins history [@user]`, or `coins grant|revoke @user <amount> [reason]`.".to_string(),
+        )),
+    };
+    reply(ctx, msg, result).await
+}
+
+#[command]
+async fn daily(ctx: &Context, msg: &Message) -> CommandResult {
+    reply(ctx, msg, economy::daily(ctx, &Invoker::from_message(msg)).await).await
+}
+
+// `!shop` lists what coins can buy; `!shop buy <theme>` and `!shop use <theme|server>`
+#[command]
+async fn shop(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let (action, name) = args.rest().trim().split_once(' ').unwrap_or((args.rest().trim(), ""));
+    reply(ctx, msg, economy::shop(ctx, &Invoker::from_message(msg), &action.to_lowercase(), name).await).await
+}
+
+// `!rating [@user]` shows a player's skill rating; your own by default
+#[command]
+async fn rating(ctx: &Context, msg: &Message) -> CommandResult {
+    let user_id = msg.mentions.first().map_or(msg.author.id, |user| user.id);
+    reply(ctx, msg, ratings::show(ctx, user_id).await).await
+}
+
+#[command]
+async fn rated_leaderboard(ctx: &Context, msg: &Message) -> CommandResult {
+    reply(ctx, msg, ratings::leaderboard(ctx).await).await
+}
+
+// `!help [command]` lists every command, or shows one command's usage and examples
+#[command]
+async fn help(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let config = guild_config(ctx, msg.guild_id).await;
+    let topic = args.rest().trim();
+    let mut embed = CreateEmbed::default();
+    let result = help::help_embed(&mut embed, &config, (!topic.is_empty()).then_some(topic));
     msg.channel_id
         .send_message(&ctx.http, |m| {
-            m.content("Puzzle Mastermind! Solve the puzzle by swapping tiles or submitting guesses.")
-                .add_file("scrambled_image.png")
-                .components(|c| {
-                    c.add_action_row(create_button_row())
-                })
+            match result {
+                Ok(()) => m.set_embed(embed),
+                Err(text) => m.content(text),
+            };
+            m.components(|c| c.add_action_row(tutorial::start_button(TutorialMode::Classic)))
+                .allowed_mentions(|mentions| mentions.empty_parse())
         })
         .await?;
+    Ok(())
+}
 
+// `!tutorial [mode]` offers a private practice game to whoever clicks the button
+#[command]
+async fn tutorial(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let mode: TutorialMode = match args.rest().trim().parse() {
+        Ok(mode) => mode,
+        Err(e) => {
+            msg.channel_id.say(&ctx.http, format!("Could not start the tutorial: {}", e)).await?;
+            return Ok(());
+        }
+    };
+    msg.channel_id
+        .send_message(&ctx.http, |m| {
+            m.content("Learn to play on a small practice puzzle that only you can see.")
+                .components(|c| c.add_action_row(tutorial::start_button(mode)))
+        })
+        .await?;
     Ok(())
 }
 
+// `!accessibility [setting value]` shows or changes your board preferences, e.g.
+// `!accessibility display text`, `notation coordinates`, `edges on`, `contrast high`,
+// `colors colorblind`, `overlay on`
 #[command]
-async fn submit_guess(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
-    let guess = args.rest();
-    let user_id = msg.author.id;
+async fn accessibility(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let change = if args.is_empty() {
+        None
+    } else {
+        Some((args.single::<String>()?.to_lowercase(), args.rest().trim().to_lowercase()))
+    };
 
-    let mut data = ctx.data.write().await;
-    let game = data.get_mut::<GameKey>().unwrap();
+    // Tile names are shown for the server's board size, or the usual 3x3
+    let grid_size = guild_config(ctx, msg.guild_id).await.grid_size.unwrap_or(3);
 
-    if game.check_guess(guess) {
-        game.increase_score(user_id);
-        msg.channel_id.say(&ctx.http, "Correct!").await?;
-    } else {
-        msg.channel_id.say(&ctx.http, "Incorrect guess. Try again!").await?;
-    }
+    // Only hold the data lock while the settings change, not while replying
+    let reply = {
+        let mut data = ctx.data.write().await;
+        let prefs = data.get_mut::<AccessibilityKey>().unwrap();
+        let options = prefs.entry(msg.author.id).or_default();
+        update_accessibility(options, change, grid_size)
+    };
+    msg.channel_id.say(&ctx.http, reply).await?;
 
     Ok(())
 }
 
+// Apply `!accessibility <setting> <value>`, if given, and describe the settings or the problem
+fn update_accessibility(options: &mut BoardOptions, change: Option<(String, String)>, grid_size: u32) -> String {
+    if let Some((setting, value)) = change {
+        let result = match setting.as_str() {
+            "notation" => value.parse().map(|notation| options.notation = notation),
+            "edges" | "edge_labels" => parse_toggle(&value).map(|on| options.edge_labels = on),
+            "contrast" => match value.as_str() {
+                "high" => Ok(true),
+                "normal" => Ok(false),
+                _ => Err(format!("unknown contrast \"{}\" (use high or normal)", value)),
+            }
+            .map(|high| options.high_contrast = high),
+            "colors" | "colours" | "palette" => value.parse().map(|palette| options.palette = palette),
+            "overlay" => parse_toggle(&value).map(|on| options.correctness_overlay = on),
+            "display" | "board" => value.parse().map(|display| options.display = display),
+            _ => Err(format!("unknown setting \"{}\" (use display, notation, edges, contrast, colors or overlay)", setting)),
+        };
+        if let Err(e) = result {
+            return format!("Could not update your settings: {}", e);
+        }
+    }
+
+    format!(
+        "Your board settings (applied to puzzles you start):\n{}",
+        options.describe(grid_size)
+    )
+}
+
+// `!overlay on|off` toggles the correctness overlay for the current game and reposts the board
 #[command]
-async fn swap_tiles(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
-    let mut data = ctx.data.write().await;
-    let game = data.get_mut::<GameKey>().unwrap();
+async fn overlay(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let enabled = match parse_toggle(&args.rest().trim().to_lowercase()) {
+        Ok(enabled) => enabled,
+        Err(e) => {
+            msg.channel_id.say(&ctx.http, format!("Usage: !overlay on|off ({})", e)).await?;
+            return Ok(());
+        }
+    };
 
-    // Get the two indices to swap from the command arguments
-    let index1 = args.single::<usize>()?;
-    let index2 = args.single::<usize>()?;
+    let text = if enabled {
+        "Correctness overlay on: tiles in the right place are marked with a check."
+    } else {
+        "Correctness overlay off."
+    };
+    change_board_options(ctx, msg, text, |options| options.correctness_overlay = enabled).await
+}
 
-    // Ensure the indices are valid (1 through 9)
-    if index1 < 1 || index1 > 9 || index2 < 1 || index2 > 9 {
-        msg.channel_id.say(&ctx.http, "Invalid tile indices! Please use numbers between 1 and 9.").await?;
-        return Ok(());
-    }
+// `!board image|text|emoji` switches how the current game's board is shown
+#[command]
+async fn board(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let display: BoardDisplay = match args.rest().trim().parse() {
+        Ok(display) => display,
+        Err(e) => {
+            msg.channel_id.say(&ctx.http, format!("Usage: !board image|text|emoji ({})", e)).await?;
+            return Ok(());
+        }
+    };
 
-    // Adjust for 0-based indexing
-    let index1 = index1 - 1;
-    let index2 = index2 - 1;
+    change_board_options(ctx, msg, "Board display changed.", |options| options.display = display).await
+}
 
-    // Swap the tiles in the game's tile_positions
-    game.tile_positions.swap(index1, index2);
+// Apply a change to the current game's board options and show the updated board
+async fn change_board_options(
+    ctx: &Context,
+    msg: &Message,
+    text: &str,
+    change: impl FnOnce(&mut BoardOptions),
+) -> CommandResult {
+    let (game, renderer, encoder) = game_context(ctx, msg.channel_id).await;
+    let mut game = game.lock().await;
+    change(&mut game.board_options);
 
-    // Re-create the scrambled image based on the new tile positions
-    if let Some(new_scrambled_image) = game.recreate_scrambled_image() {
-        new_scrambled_image.save("scrambled_image.png").expect("Failed to save image");
+    let board_renderer = game.board_renderer(&renderer, encoder);
+    let board = match game.recreate_scrambled_image(board_renderer).await {
+        Some(board) => board?,
+        None => {
+            msg.channel_id.say(&ctx.http, "There is no puzzle in progress.").await?;
+            return Ok(());
+        }
+    };
+    update_board(ctx, msg.channel_id, &mut game, board, text).await?;
 
-        msg.channel_id
-            .send_message(&ctx.http, |m| {
-                m.content("Here is the updated puzzle after the swap!")
-                    .add_file("scrambled_image.png")
-            })
-            .await?;
+    Ok(())
+}
+
+// `!theme` shows the server's theme. Admins with Manage Server can change it:
+// `!theme <name>`, `!theme custom <property> <color>` and `!theme watermark <text|off>`.
+// Changes apply to the next puzzle.
+#[command]
+async fn theme(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    change_theme(ctx, msg, args).await
+}
+
+// Show the server's theme, or change it for members with Manage Server
+async fn change_theme(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = match msg.guild_id {
+        Some(guild_id) => guild_id,
+        None => {
+            msg.channel_id.say(&ctx.http, "Themes can only be set in a server.").await?;
+            return Ok(());
+        }
+    };
+
+    if !args.is_empty() {
+        let is_admin = match msg.member(ctx).await {
+            Ok(member) => member.permissions(&ctx.cache).is_ok_and(|permissions| permissions.manage_guild()),
+            Err(_) => false,
+        };
+        if !is_admin {
+            msg.channel_id.say(&ctx.http, "Only members with Manage Server can change the theme.").await?;
+            return Ok(());
+        }
+
+        let setting = args.single::<String>()?.to_lowercase();
+        let rest = args.rest().trim().to_string();
+        let mut data = ctx.data.write().await;
+        let result = data
+            .get_mut::<ConfigKey>()
+            .unwrap()
+            .update(guild_id, |config| config.theme.change(&setting, &rest));
+        drop(data);
+        if let Err(e) = result {
+            msg.channel_id.say(&ctx.http, format!("Could not change the theme: {}", e)).await?;
+            return Ok(());
+        }
     }
 
-    // Check if the puzzle is solved
-    if game.tile_positions == game.correct_positions {
-        msg.channel_id.say(&ctx.http, "Congratulations! You solved the puzzle!").await?;
-        game.increase_score(msg.author.id);
+    let summary = format!(
+        "{}\nAvailable themes: {}",
+        guild_config(ctx, Some(guild_id)).await.theme.describe(),
+        THEME_NAMES.join(", ")
+    );
+    msg.channel_id.say(&ctx.http, summary).await?;
+
+    Ok(())
+}
+
+fn parse_toggle(value: &str) -> Result<bool, String> {
+    match value {
+        "on" | "yes" | "true" => Ok(true),
+        "off" | "no" | "false" => Ok(false),
+        _ => Err(format!("expected on or off, got \"{}\"", value)),
     }
+}
+
+#[group]
+#[prefix = "config"]
+#[only_in(guilds)]
+#[required_permissions(MANAGE_GUILD)]
+#[default_command(config_show)]
+#[commands(config_show, config_prefix, config_grid, config_mode, config_channels, config_theme, config_scoring, config_reveal, config_turns, config_votes, config_prices)]
+struct Config;
 
+// Apply a settings change for the message's server, then show the result
+async fn update_config(
+    ctx: &Context,
+    msg: &Message,
+    change: impl FnOnce(&mut GuildConfig) -> Result<(), String>,
+) -> CommandResult {
+    let guild_id = msg.guild_id.ok_or("config commands only run in servers")?;
+    let result = ctx.data.write().await.get_mut::<ConfigKey>().unwrap().update(guild_id, change);
+    let text = match result {
+        Ok(()) => format!("Settings updated.\n{}", guild_config(ctx, Some(guild_id)).await.describe()),
+        Err(e) => format!("Could not change the settings: {}", e),
+    };
+    msg.channel_id
+        .send_message(&ctx.http, |m| m.content(text).allowed_mentions(|mentions| mentions.empty_parse()))
+        .await?;
+    Ok(())
+}
+
+#[command("show")]
+async fn config_show(ctx: &Context, msg: &Message) -> CommandResult {
+    let text = format!("**Server settings**\n{}", guild_config(ctx, msg.guild_id).await.describe());
+    msg.channel_id
+        .send_message(&ctx.http, |m| m.content(text).allowed_mentions(|mentions| mentions.empty_parse()))
+        .await?;
     Ok(())
 }
+
+#[command("prefix")]
+async fn config_prefix(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let prefix = args.rest().trim().to_string();
+    update_config(ctx, msg, |config| config.set_prefix(&prefix)).await
+}
+
+#[command("grid")]
+async fn config_grid(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let value = args.rest().trim().to_lowercase();
+    update_config(ctx, msg, |config| config.set_grid_size(&value)).await
+}
+
+#[command("mode")]
+async fn config_mode(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let value = args.rest().trim().to_lowercase();
+    update_config(ctx, msg, |config| config.set_mode(&value)).await
+}
+
+// `add #channel`, `remove #channel` or `all`
+#[command("channels")]
+async fn config_channels(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let action = args.single::<String>().unwrap_or_default().to_lowercase();
+    // Channel mentions or IDs
+    let channels: Vec<ChannelId> = args.iter::<ChannelId>().filter_map(Result::ok).collect();
+    update_config(ctx, msg, |config| {
+        match action.as_str() {
+            "all" => config.allowed_channels.clear(),
+            "add" if !channels.is_empty() => {
+                for channel_id in channels {
+                    if !config.allowed_channels.contains(&channel_id) {
+                        config.allowed_channels.push(channel_id);
+                    }
+                }
+            }
+            "remove" if !channels.is_empty() => config.allowed_channels.retain(|channel_id| !channels.contains(channel_id)),
+            _ => return Err("usage: channels add #channel, channels remove #channel or channels all".to_string()),
+        }
+        Ok(())
+    })
+    .await
+}
+
+#[command("theme")]
+async fn config_theme(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    change_theme(ctx, msg, args).await
+}
+
+#[command("scoring")]
+async fn config_scoring(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let kind = args.single::<String>().unwrap_or_default().to_lowercase();
+    let value = args.rest().trim().to_string();
+    update_config(ctx, msg, |config| config.set_scoring(&kind, &value)).await
+}
+
+#[command("reveal")]
+async fn config_reveal(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let value = args.rest().trim().to_lowercase();
+    update_config(ctx, msg, |config| config.set_reveal(&value)).await
+}
+
+#[command("turns")]
+async fn config_turns(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let value = args.rest().trim().to_lowercase();
+    update_config(ctx, msg, |config| config.set_turn_timeout(&value)).await
+}
+
+#[command("votes")]
+async fn config_votes(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let value = args.rest().trim().to_lowercase();
+    update_config(ctx, msg, |config| config.set_vote_window(&value)).await
+}
+
+#[command("prices")]
+async fn config_prices(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let kind = args.single::<String>().unwrap_or_default().to_lowercase();
+    let value = args.rest().trim().to_string();
+    update_config(ctx, msg, |config| config.set_price(&kind, &value)).await
+}
@@ -0,0 +1,477 @@
+use serde::de::IgnoredAny;
+use serde::Deserialize;
+use serde_json::value::RawValue;
+use std::collections::HashSet;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use rand::seq::SliceRandom;
+
+// Smallest and largest grid a manifest entry may suggest (tiles per side)
+pub const MIN_GRID_SIZE: u32 = 2;
+pub const MAX_GRID_SIZE: u32 = 8;
+
+fn default_grid_size() -> u32 {
+    3
+}
+
+fn default_category() -> String {
+    "general".to_string()
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Attribution {
+    #[serde(default)]
+    pub author: Option<String>,
+    #[serde(default)]
+    pub source_url: Option<String>,
+    pub license: String,
+}
+
+impl fmt::Display for Attribution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.author {
+            Some(author) => write!(f, "Image by {}", author)?,
+            None => write!(f, "Image")?,
+        }
+        if let Some(url) = &self.source_url {
+            write!(f, " ({})", url)?;
+        }
+        write!(f, ", licensed under {}", self.license)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImageSource {
+    Url(String),
+    Path(PathBuf),
+}
+
+impl ImageSource {
+    fn parse(raw: &str, base_dir: &Path) -> Self {
+        if raw.starts_with("http://") || raw.starts_with("https://") {
+            ImageSource::Url(raw.to_string())
+        } else {
+            ImageSource::Path(base_dir.join(raw))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PuzzleEntry {
+    // Either an http(s) URL or a path relative to the manifest file
+    pub source: String,
+    pub title: String,
+    pub answer: String,
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    pub attribution: Attribution,
+    #[serde(default = "default_category")]
+    pub category: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default = "default_grid_size")]
+    pub grid_size: u32,
+    pub nsfw_safe: bool,
+    #[serde(skip)]
+    pub image: Option<ImageSource>,
+}
+
+impl PuzzleEntry {
+    pub fn image_source(&self) -> ImageSource {
+        self.image
+            .clone()
+            .unwrap_or_else(|| ImageSource::parse(&self.source, Path::new(".")))
+    }
+
+    pub fn has_category_or_tag(&self, name: &str) -> bool {
+        self.category.eq_ignore_ascii_case(name)
+            || self.tags.iter().any(|tag| tag.eq_ignore_ascii_case(name))
+    }
+
+    // Compare a guess against the answer and aliases, ignoring case and extra whitespace
+    pub fn matches_answer(&self, guess: &str) -> bool {
+        let guess = normalize_answer(guess);
+        !guess.is_empty()
+            && std::iter::once(&self.answer)
+                .chain(self.aliases.iter())
+                .any(|candidate| normalize_answer(candidate) == guess)
+    }
+}
+
+fn normalize_answer(text: &str) -> String {
+    text.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ManifestFormat {
+    Toml,
+    Json,
+}
+
+impl ManifestFormat {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "toml" => Some(ManifestFormat::Toml),
+            "json" => Some(ManifestFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ValidationIssue {
+    pub entry: usize,
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+#[derive(Debug)]
+pub enum ManifestError {
+    Io(PathBuf, std::io::Error),
+    UnsupportedFormat(PathBuf),
+    Parse {
+        line: usize,
+        column: usize,
+        context: String,
+        message: String,
+    },
+    Invalid(Vec<ValidationIssue>),
+}
+
+impl fmt::Display for ManifestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ManifestError::Io(path, e) => write!(f, "failed to read {}: {}", path.display(), e),
+            ManifestError::UnsupportedFormat(path) => write!(
+                f,
+                "unsupported manifest format for {} (expected .toml or .json)",
+                path.display()
+            ),
+            ManifestError::Parse { line, column, context, message } => {
+                writeln!(f, "line {}, column {}: {}", line, column, message.trim_end())?;
+                write!(f, "{:>5} | {}", line, context)
+            }
+            ManifestError::Invalid(issues) => {
+                writeln!(f, "{} invalid manifest entr{}:", issues.len(), if issues.len() == 1 { "y" } else { "ies" })?;
+                for issue in issues {
+                    match issue.line {
+                        Some(line) => writeln!(f, "  puzzle #{} (line {}): {}", issue.entry + 1, line, issue.message)?,
+                        None => writeln!(f, "  puzzle #{}: {}", issue.entry + 1, issue.message)?,
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ManifestError {}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    #[serde(rename = "puzzle", default)]
+    pub puzzles: Vec<PuzzleEntry>,
+}
+
+impl Manifest {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ManifestError> {
+        let path = path.as_ref();
+        let format = ManifestFormat::from_path(path)
+            .ok_or_else(|| ManifestError::UnsupportedFormat(path.to_path_buf()))?;
+        let source = std::fs::read_to_string(path)
+            .map_err(|e| ManifestError::Io(path.to_path_buf(), e))?;
+        let base_dir = path.parent().unwrap_or_else(|| Path::new("."));
+        Self::parse(&source, format, base_dir)
+    }
+
+    pub fn parse(source: &str, format: ManifestFormat, base_dir: &Path) -> Result<Self, ManifestError> {
+        let mut manifest: Manifest = match format {
+            ManifestFormat::Toml => toml::from_str(source).map_err(|e| {
+                let offset = e.span().map(|span| span.start).unwrap_or(0);
+                let (line, column) = line_and_column(source, offset);
+                parse_error(source, line, column, e.message())
+            })?,
+            ManifestFormat::Json => serde_json::from_str(source)
+                .map_err(|e| parse_error(source, e.line(), e.column(), &e.to_string()))?,
+        };
+
+        manifest.validate(&entry_lines(source, format))?;
+        for entry in &mut manifest.puzzles {
+            entry.image = Some(ImageSource::parse(&entry.source, base_dir));
+        }
+        Ok(manifest)
+    }
+
+    // `lines` holds the line each entry starts on, where it is known
+    fn validate(&self, lines: &[Option<usize>]) -> Result<(), ManifestError> {
+        let mut issues = Vec::new();
+        let mut seen_titles = HashSet::new();
+
+        if self.puzzles.is_empty() {
+            issues.push(ValidationIssue {
+                entry: 0,
+                line: None,
+                message: "manifest does not contain any puzzles".to_string(),
+            });
+        }
+
+        for (index, entry) in self.puzzles.iter().enumerate() {
+            let line = lines.get(index).copied().flatten();
+            let mut report = |message: String| {
+                issues.push(ValidationIssue { entry: index, line, message });
+            };
+
+            if entry.title.trim().is_empty() {
+                report("title must not be empty".to_string());
+            } else if !seen_titles.insert(entry.title.to_lowercase()) {
+                report(format!("duplicate title \"{}\"", entry.title));
+            }
+            if entry.source.trim().is_empty() {
+                report("source must be a URL or a file path".to_string());
+            }
+            if entry.answer.trim().is_empty() {
+                report("answer must not be empty".to_string());
+            }
+            if entry.aliases.iter().any(|alias| alias.trim().is_empty()) {
+                report("aliases must not contain empty strings".to_string());
+            }
+            if entry.category.trim().is_empty() {
+                report("category must not be empty".to_string());
+            }
+            if entry.attribution.license.trim().is_empty() {
+                report("attribution.license must not be empty".to_string());
+            }
+            if !(MIN_GRID_SIZE..=MAX_GRID_SIZE).contains(&entry.grid_size) {
+                report(format!(
+                    "grid_size {} is out of range ({}-{})",
+                    entry.grid_size, MIN_GRID_SIZE, MAX_GRID_SIZE
+                ));
+            }
+        }
+
+        if issues.is_empty() {
+            Ok(())
+        } else {
+            Err(ManifestError::Invalid(issues))
+        }
+    }
+
+    // Pick a random puzzle, skipping entries that are not safe for this channel.
+    // An optional filter restricts the choice to a category or tag.
+    pub fn pick(&self, nsfw_channel: bool, filter: Option<&str>) -> Option<&PuzzleEntry> {
+        let candidates: Vec<_> = self
+            .puzzles
+            .iter()
+            .filter(|entry| nsfw_channel || entry.nsfw_safe)
+            .filter(|entry| filter.is_none_or(|filter| entry.has_category_or_tag(filter)))
+            .collect();
+        candidates.choose(&mut rand::thread_rng()).copied()
+    }
+
+    // Pick a random puzzle from the third of the candidates closest to a target, by a distance
+    // such as the gap between a puzzle's difficulty rating and the player's skill. Ties are
+    // broken randomly, so unrated puzzles still come up in turn.
+    pub fn pick_near(
+        &self,
+        nsfw_channel: bool,
+        filter: Option<&str>,
+        distance: impl Fn(&PuzzleEntry) -> f64,
+    ) -> Option<&PuzzleEntry> {
+        let mut rng = rand::thread_rng();
+        let mut candidates: Vec<_> = self
+            .puzzles
+            .iter()
+            .filter(|entry| nsfw_channel || entry.nsfw_safe)
+            .filter(|entry| filter.is_none_or(|filter| entry.has_category_or_tag(filter)))
+            .map(|entry| (distance(entry), entry))
+            .collect();
+        candidates.shuffle(&mut rng);
+        candidates.sort_by(|a, b| a.0.total_cmp(&b.0));
+        candidates.truncate(candidates.len().div_ceil(3));
+        candidates.choose(&mut rng).map(|&(_, entry)| entry)
+    }
+
+    // Every category and tag, lowercased and sorted, for suggestions
+    pub fn categories(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .puzzles
+            .iter()
+            .flat_map(|entry| std::iter::once(&entry.category).chain(entry.tags.iter()))
+            .map(|name| name.to_lowercase())
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+}
+
+fn parse_error(source: &str, line: usize, column: usize, message: &str) -> ManifestError {
+    let context = source
+        .lines()
+        .nth(line.saturating_sub(1))
+        .unwrap_or_default()
+        .to_string();
+    ManifestError::Parse {
+        line,
+        column,
+        context,
+        message: message.to_string(),
+    }
+}
+
+// Convert a byte offset into a 1-based line and column
+fn line_and_column(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset.min(source.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.len() - before.rfind('\n').map(|i| i + 1).unwrap_or(0) + 1;
+    (line, column)
+}
+
+// Where each entry is in a manifest that has already been parsed, read back from the
+// deserializer so duplicate or escaped titles can't throw the line numbers off
+#[derive(Deserialize)]
+struct TomlPositions {
+    #[serde(rename = "puzzle", default)]
+    puzzles: Vec<toml::Spanned<IgnoredAny>>,
+}
+
+#[derive(Deserialize)]
+struct JsonPositions<'a> {
+    #[serde(rename = "puzzle", default, borrow)]
+    puzzles: Vec<&'a RawValue>,
+}
+
+// The line each entry starts on, in order
+fn entry_lines(source: &str, format: ManifestFormat) -> Vec<Option<usize>> {
+    let offsets: Vec<usize> = match format {
+        ManifestFormat::Toml => toml::from_str::<TomlPositions>(source)
+            .map(|positions| positions.puzzles.iter().map(|entry| entry.span().start).collect())
+            .unwrap_or_default(),
+        // Each entry borrows its own text from `source`, so its offset is where that text starts
+        ManifestFormat::Json => serde_json::from_str::<JsonPositions>(source)
+            .map(|positions| {
+                positions
+                    .puzzles
+                    .iter()
+                    .map(|entry| entry.get().as_ptr() as usize - source.as_ptr() as usize)
+                    .collect()
+            })
+            .unwrap_or_default(),
+    };
+    offsets.into_iter().map(|offset| Some(line_and_column(source, offset).0)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOML_MANIFEST: &str = r#"
+[[puzzle]]
+source = "cat.png"
+title = "Cat"
+answer = "cat"
+nsfw_safe = true
+attribution = { license = "CC0" }
+
+[[puzzle]]
+source = "https://example.com/dog.png"
+title = "Dog"
+answer = "dog"
+grid_size = 4
+nsfw_safe = true
+attribution = { author = "Someone", license = "CC-BY-4.0" }
+"#;
+
+    const JSON_MANIFEST: &str = r#"{
+  "puzzle": [
+    {
+      "source": "cat.png",
+      "title": "Cat",
+      "answer": "cat",
+      "nsfw_safe": true,
+      "attribution": { "license": "CC0" }
+    },
+    {
+      "source": "dog.png",
+      "title": "Dog",
+      "answer": "dog",
+      "nsfw_safe": true,
+      "attribution": { "license": "CC0" }
+    }
+  ]
+}"#;
+
+    fn invalid_issues(source: &str, format: ManifestFormat) -> Vec<ValidationIssue> {
+        match Manifest::parse(source, format, Path::new(".")) {
+            Err(ManifestError::Invalid(issues)) => issues,
+            other => panic!("expected validation errors, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn defaults_fill_in_grid_size_and_category() {
+        let manifest = Manifest::parse(TOML_MANIFEST, ManifestFormat::Toml, Path::new("puzzles")).unwrap();
+        assert_eq!(manifest.puzzles[0].grid_size, 3);
+        assert_eq!(manifest.puzzles[0].category, "general");
+        assert_eq!(manifest.puzzles[1].grid_size, 4);
+        assert_eq!(manifest.puzzles[0].image, Some(ImageSource::Path(Path::new("puzzles").join("cat.png"))));
+        assert_eq!(manifest.puzzles[1].image, Some(ImageSource::Url("https://example.com/dog.png".to_string())));
+    }
+
+    #[test]
+    fn entry_lines_point_at_each_toml_entry() {
+        assert_eq!(entry_lines(TOML_MANIFEST, ManifestFormat::Toml), vec![Some(2), Some(9)]);
+    }
+
+    #[test]
+    fn entry_lines_point_at_each_json_entry() {
+        assert_eq!(entry_lines(JSON_MANIFEST, ManifestFormat::Json), vec![Some(3), Some(10)]);
+    }
+
+    #[test]
+    fn empty_manifest_is_invalid() {
+        let issues = invalid_issues("", ManifestFormat::Toml);
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].message, "manifest does not contain any puzzles");
+        assert_eq!(issues[0].line, None);
+    }
+
+    #[test]
+    fn validation_errors_carry_entry_and_line() {
+        let source = TOML_MANIFEST.replace("title = \"Dog\"", "title = \"cat\"").replace("grid_size = 4", "grid_size = 9");
+        let issues = invalid_issues(&source, ManifestFormat::Toml);
+        let messages: Vec<_> = issues.iter().map(|issue| issue.message.as_str()).collect();
+        assert_eq!(messages, ["duplicate title \"cat\"", "grid_size 9 is out of range (2-8)"]);
+        assert!(issues.iter().all(|issue| issue.entry == 1 && issue.line == Some(9)));
+    }
+
+    #[test]
+    fn blank_fields_are_invalid() {
+        let source = JSON_MANIFEST
+            .replacen("\"answer\": \"cat\"", "\"answer\": \" \"", 1)
+            .replacen("\"license\": \"CC0\"", "\"license\": \"\"", 1);
+        let issues = invalid_issues(&source, ManifestFormat::Json);
+        let messages: Vec<_> = issues.iter().map(|issue| issue.message.as_str()).collect();
+        assert_eq!(messages, ["answer must not be empty", "attribution.license must not be empty"]);
+        assert!(issues.iter().all(|issue| issue.entry == 0 && issue.line == Some(3)));
+    }
+
+    #[test]
+    fn parse_errors_report_line_and_column() {
+        let source = TOML_MANIFEST.replace("nsfw_safe = true\nattribution = { author", "nsfw_safe = maybe\nattribution = { author");
+        match Manifest::parse(&source, ManifestFormat::Toml, Path::new(".")) {
+            Err(ManifestError::Parse { line, context, .. }) => {
+                assert_eq!(line, 14);
+                assert_eq!(context, "nsfw_safe = maybe");
+            }
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,171 @@
+use serenity::model::application::component::{ActionRowComponent, ButtonStyle, InputTextStyle};
+use serenity::model::application::interaction::message_component::MessageComponentInteraction;
+use serenity::model::application::interaction::modal::ModalSubmitInteraction;
+use serenity::model::application::interaction::InteractionResponseType;
+use serenity::prelude::*;
+
+use crate::actions::{self, Invoker, NO_PUZZLE, respond_ephemeral};
+use crate::{board, game_context};
+
+// Button ids that open a form. A "Try again" button adds the previous answer after a colon,
+// so Discord's 100 character limit on ids caps how long an answer can be. The board's own Swap
+// Tiles button asks in a prompt first, which offers the form (see prompts.rs).
+const SWAP_BUTTON: &str = "swap_tiles";
+const GUESS_BUTTON: &str = "guess";
+const MAX_INPUT_LENGTH: u64 = 80;
+
+const SWAP_FORM: &str = "swap_form";
+const GUESS_FORM: &str = "guess_form";
+const FORM_INPUT: &str = "input";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Form {
+    Swap,
+    Guess,
+}
+
+impl Form {
+    fn button_id(self) -> &'static str {
+        match self {
+            Form::Swap => SWAP_BUTTON,
+            Form::Guess => GUESS_BUTTON,
+        }
+    }
+}
+
+// The form a button opens, and the answer to fill in when it is a "Try again" button
+pub fn requested_form(custom_id: &str) -> Option<(Form, &str)> {
+    let (id, previous) = custom_id.split_once(':').unwrap_or((custom_id, ""));
+    match id {
+        SWAP_BUTTON => Some((Form::Swap, previous)),
+        GUESS_BUTTON => Some((Form::Guess, previous)),
+        _ => None,
+    }
+}
+
+// Open the swap or guess form for whoever pressed the button
+pub async fn show_form(ctx: &Context, component: &MessageComponentInteraction, form: Form, previous: &str) {
+    let example = {
+        let (game, _, _) = game_context(ctx, component.channel_id).await;
+        let game = game.lock().await;
+        if game.puzzle.is_none() {
+            respond_ephemeral(ctx, component, NO_PUZZLE).await;
+            return;
+        }
+        let label = |slot| board::tile_label(slot, game.grid_size, game.board_options.notation);
+        format!("{}-{} {}-{}", label(0), label(4.min(game.tile_positions.len() - 1)), label(1), label(2))
+    };
+
+    let (form_id, title, label, style, placeholder) = match form {
+        Form::Swap => (SWAP_FORM, "Swap tiles", "Swaps, separated by spaces", InputTextStyle::Paragraph, example),
+        Form::Guess => (GUESS_FORM, "Guess the picture", "What does the picture show?", InputTextStyle::Short, String::new()),
+    };
+    if let Err(e) = component
+        .create_interaction_response(&ctx.http, |response| {
+            response.kind(InteractionResponseType::Modal).interaction_response_data(|data| {
+                data.custom_id(form_id).title(title).components(|c| {
+                    c.create_action_row(|row| {
+                        row.create_input_text(|input| {
+                            input
+                                .custom_id(FORM_INPUT)
+                                .label(label)
+                                .style(style)
+                                .max_length(MAX_INPUT_LENGTH)
+                                .required(true);
+                            if !placeholder.is_empty() {
+                                input.placeholder(placeholder);
+                            }
+                            if !previous.is_empty() {
+                                input.value(previous);
+                            }
+                            input
+                        })
+                    })
+                })
+            })
+        })
+        .await
+    {
+        eprintln!("Failed to open {:?} form: {:?}", form, e);
+    }
+}
+
+// Validate and apply a submitted form. Problems are shown only to the submitter, with a
+// button that reopens the form holding their answer so it can be corrected.
+pub async fn handle_submit(ctx: &Context, submission: &ModalSubmitInteraction) {
+    let form = match submission.data.custom_id.as_str() {
+        SWAP_FORM => Form::Swap,
+        GUESS_FORM => Form::Guess,
+        _ => return,
+    };
+    let answer = form_input(submission).unwrap_or_default();
+    let invoker = Invoker {
+        channel_id: submission.channel_id,
+        guild_id: submission.guild_id,
+        user_id: submission.user.id,
+    };
+
+    // Rendering the board can take longer than the three seconds Discord allows
+    if let Err(e) = submission
+        .create_interaction_response(&ctx.http, |response| {
+            response
+                .kind(InteractionResponseType::DeferredChannelMessageWithSource)
+                .interaction_response_data(|message| message.ephemeral(true))
+        })
+        .await
+    {
+        eprintln!("Failed to defer {:?} form: {:?}", form, e);
+        return;
+    }
+
+    let result = match form {
+        Form::Swap => actions::swap_many(ctx, &invoker, &answer).await,
+        Form::Guess => actions::guess(ctx, &invoker, &answer).await,
+    };
+    let (content, retry) = match result {
+        Ok(None) => (
+            match form {
+                Form::Swap => "Tiles swapped.".to_string(),
+                Form::Guess => "Correct!".to_string(),
+            },
+            false,
+        ),
+        Ok(Some(feedback)) if feedback == NO_PUZZLE => (feedback, false),
+        Ok(Some(feedback)) => (feedback, true),
+        Err(e) => {
+            eprintln!("{:?} form failed: {:?}", form, e);
+            ("Something went wrong. Please try again.".to_string(), true)
+        }
+    };
+
+    let retry_id = format!("{}:{}", form.button_id(), answer.trim());
+    if let Err(e) = submission
+        .edit_original_interaction_response(&ctx.http, |message| {
+            message.content(content).allowed_mentions(|mentions| mentions.empty_parse());
+            if retry {
+                message.components(|c| {
+                    c.create_action_row(|row| {
+                        row.create_button(|button| button.label("Try again").custom_id(retry_id).style(ButtonStyle::Primary))
+                    })
+                });
+            }
+            message
+        })
+        .await
+    {
+        eprintln!("Failed to respond to {:?} form: {:?}", form, e);
+    }
+}
+
+// The text typed into the form
+fn form_input(submission: &ModalSubmitInteraction) -> Option<String> {
+    submission
+        .data
+        .components
+        .iter()
+        .flat_map(|row| row.components.iter())
+        .find_map(|component| match component {
+            ActionRowComponent::InputText(input) if input.custom_id == FORM_INPUT => Some(input.value.clone()),
+            _ => None,
+        })
+}
@@ -0,0 +1,189 @@
+use futures::StreamExt;
+use serenity::collector::{ComponentInteractionCollectorBuilder, MessageCollectorBuilder, ModalInteractionCollectorBuilder};
+use serenity::model::application::component::ButtonStyle;
+use serenity::model::application::interaction::message_component::MessageComponentInteraction;
+use serenity::model::application::interaction::InteractionResponseType;
+use serenity::prelude::*;
+use std::time::Duration;
+use tokio::time::{sleep_until, Instant};
+
+use crate::actions::{self, Invoker, NO_PUZZLE, respond_ephemeral};
+use crate::modals::{self, Form};
+use crate::{board, game_context};
+
+// How long a prompt waits for an answer before giving up
+const PROMPT_TIMEOUT: Duration = Duration::from_secs(60);
+
+const FORM_BUTTON: &str = "prompt_form";
+const CANCEL_BUTTON: &str = "prompt_cancel";
+
+// How a prompt ended
+enum Outcome {
+    Done,
+    Form, // Answered with the swap form, which reports back on its own
+    Cancelled,
+    TimedOut,
+}
+
+// Ask the user who pressed Swap Tiles which tiles to swap. They can answer with their next
+// message in the channel or through the swap form, which also takes several swaps at once; the
+// prompt can be cancelled and expires after a minute.
+// The prompt is only visible to them and is updated with feedback on every bad answer.
+pub async fn prompt_swap(ctx: &Context, component: &MessageComponentInteraction) {
+    let invoker = Invoker {
+        channel_id: component.channel_id,
+        guild_id: component.guild_id,
+        user_id: component.user.id,
+    };
+
+    let example = {
+        let (game, _, _) = game_context(ctx, invoker.channel_id).await;
+        let game = game.lock().await;
+        if game.puzzle.is_none() {
+            respond_ephemeral(ctx, component, NO_PUZZLE).await;
+            return;
+        }
+        format!(
+            "{} {}",
+            board::tile_label(0, game.grid_size, game.board_options.notation),
+            board::tile_label(1, game.grid_size, game.board_options.notation)
+        )
+    };
+
+    let instructions = format!(
+        "Reply in this channel with the two tiles you want to swap, separated by a space (e.g. '{}'), or use the form to make several swaps. \
+         Type 'cancel' or press Cancel to stop. This prompt expires in {} seconds.",
+        example,
+        PROMPT_TIMEOUT.as_secs()
+    );
+    if let Err(e) = component
+        .create_interaction_response(&ctx.http, |response| {
+            response
+                .kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|message| {
+                    message.content(&instructions).ephemeral(true).components(|c| {
+                        c.create_action_row(|row| {
+                            row.create_button(|button| {
+                                button.label("Use a form").custom_id(FORM_BUTTON).style(ButtonStyle::Primary)
+                            })
+                            .create_button(|button| {
+                                button.label("Cancel").custom_id(CANCEL_BUTTON).style(ButtonStyle::Secondary)
+                            })
+                        })
+                    })
+                })
+        })
+        .await
+    {
+        eprintln!("Failed to send swap prompt: {:?}", e);
+        return;
+    }
+    let prompt = match component.get_interaction_response(&ctx.http).await {
+        Ok(prompt) => prompt,
+        Err(e) => {
+            eprintln!("Failed to fetch swap prompt: {:?}", e);
+            return;
+        }
+    };
+
+    // Only this user's messages in this channel, and interactions with this prompt, count as answers
+    let mut replies = MessageCollectorBuilder::new(ctx)
+        .channel_id(invoker.channel_id)
+        .author_id(invoker.user_id)
+        .timeout(PROMPT_TIMEOUT)
+        .build();
+    let mut buttons = ComponentInteractionCollectorBuilder::new(ctx)
+        .message_id(prompt.id)
+        .author_id(invoker.user_id)
+        .timeout(PROMPT_TIMEOUT)
+        .build();
+    let mut forms = ModalInteractionCollectorBuilder::new(ctx)
+        .message_id(prompt.id)
+        .author_id(invoker.user_id)
+        .timeout(PROMPT_TIMEOUT)
+        .build();
+    let deadline = Instant::now() + PROMPT_TIMEOUT;
+
+    let outcome = loop {
+        let answer = tokio::select! {
+            reply = replies.next() => match reply {
+                Some(reply) if reply.content.trim().eq_ignore_ascii_case("cancel") => break Outcome::Cancelled,
+                Some(reply) => reply.content.clone(),
+                None => break Outcome::TimedOut,
+            },
+            click = buttons.next() => match click {
+                Some(click) => {
+                    if click.data.custom_id == CANCEL_BUTTON {
+                        acknowledge(ctx, &click).await;
+                        break Outcome::Cancelled;
+                    }
+                    modals::show_form(ctx, &click, Form::Swap, "").await;
+                    continue;
+                }
+                None => break Outcome::TimedOut,
+            },
+            // The form's own handler validates and applies it
+            form = forms.next() => match form {
+                Some(_) => break Outcome::Form,
+                None => break Outcome::TimedOut,
+            },
+            _ = sleep_until(deadline) => break Outcome::TimedOut,
+        };
+
+        let tiles: Vec<_> = answer.split(|c: char| c.is_whitespace() || c == ',').filter(|word| !word.is_empty()).collect();
+        let feedback = match tiles[..] {
+            [tile1, tile2] => match actions::swap(ctx, &invoker, tile1, tile2).await {
+                Ok(None) => break Outcome::Done,
+                Ok(Some(feedback)) if feedback == NO_PUZZLE => {
+                    update_prompt(ctx, component, NO_PUZZLE).await;
+                    return;
+                }
+                Ok(Some(feedback)) => feedback,
+                Err(e) => {
+                    eprintln!("Failed to swap tiles: {:?}", e);
+                    "Something went wrong. Please try again.".to_string()
+                }
+            },
+            _ => format!("Please give exactly two tiles, e.g. '{}'.", example),
+        };
+        update_prompt_with_buttons(ctx, component, &format!("{}\n{}", feedback, instructions)).await;
+    };
+
+    let text = match outcome {
+        Outcome::Done => "Tiles swapped.",
+        Outcome::Form => "Answered with the form.",
+        Outcome::Cancelled => "Swap cancelled.",
+        Outcome::TimedOut => "No answer received in time, so the swap was cancelled. Press Swap Tiles to try again.",
+    };
+    update_prompt(ctx, component, text).await;
+}
+
+// Replace the prompt text and remove its buttons
+async fn update_prompt(ctx: &Context, component: &MessageComponentInteraction, text: &str) {
+    if let Err(e) = component
+        .edit_original_interaction_response(&ctx.http, |message| message.content(text).components(|c| c))
+        .await
+    {
+        eprintln!("Failed to update prompt: {:?}", e);
+    }
+}
+
+// Replace the prompt text, keeping its buttons
+async fn update_prompt_with_buttons(ctx: &Context, component: &MessageComponentInteraction, text: &str) {
+    if let Err(e) = component
+        .edit_original_interaction_response(&ctx.http, |message| message.content(text))
+        .await
+    {
+        eprintln!("Failed to update prompt: {:?}", e);
+    }
+}
+
+// Acknowledge a button press without changing the message; the prompt is edited afterwards
+async fn acknowledge(ctx: &Context, click: &MessageComponentInteraction) {
+    if let Err(e) = click
+        .create_interaction_response(&ctx.http, |response| response.kind(InteractionResponseType::DeferredUpdateMessage))
+        .await
+    {
+        eprintln!("Failed to acknowledge button: {:?}", e);
+    }
+}
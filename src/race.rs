@@ -0,0 +1,906 @@
+use image::RgbaImage;
+use serenity::builder::CreateActionRow;
+use serenity::model::application::component::ButtonStyle;
+use serenity::model::application::interaction::message_component::MessageComponentInteraction;
+use serenity::model::application::interaction::InteractionResponseType;
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::actions::{ActionResult, Invoker, player_board_options, respond_ephemeral};
+use crate::board::{self, BoardOptions};
+use crate::components;
+use crate::manifest::PuzzleEntry;
+use crate::render::{board_renderer, describe_upload, encode_blocking, render_blocking, BoardSnapshot, RenderedBoard};
+use crate::theme::Theme;
+use crate::{duel, economy, ratings, tournament};
+use crate::{
+    load_image, BoxError, ConfigKey, EconomyKey, EncoderKey, LabelStyleKey, ManifestKey, RaceKey, RaceTimingKey, RatingsKey,
+    RendererKey, ScoresKey, StatsKey,
+};
+
+// Join and Leave sit on the race announcement in the channel, as "race:join:<race id>". Tile
+// controls on each racer's private board are scoped "race:<race id>:", so a click finds its race.
+const RACE_PREFIX: &str = "race:";
+const JOIN_PREFIX: &str = "race:join:";
+const LEAVE_PREFIX: &str = "race:leave:";
+const MIN_RACERS: usize = 2;
+
+// How long players have to join, and to finish once the race starts
+#[derive(Debug, Clone, Copy)]
+pub struct RaceTiming {
+    pub join_window: Duration,
+    pub time_limit: Duration,
+}
+
+// A race and where it is played, readable without locking the race. Tournament matches can run
+// side by side in a channel; otherwise a channel has one race at a time.
+pub struct RaceEntry {
+    channel_id: ChannelId,
+    is_match: bool,
+    race: Arc<Mutex<Race>>,
+}
+
+// Every racer solves their own copy of the same scramble in their DMs
+pub struct Race {
+    id: u64, // Also the scramble's seed
+    channel_id: ChannelId,
+    guild_id: Option<GuildId>,
+    title: String, // "Race", or the match being played
+    match_of: Option<MatchOf>, // Reported the result when it ends
+    host: UserId,
+    puzzle: PuzzleEntry,
+    image: Arc<RgbaImage>,
+    grid_size: u32,
+    theme: Theme,
+    racers: Vec<UserId>, // In the order they joined
+    // Each racer's board has its own lock, so one player's render never holds up another's move
+    boards: HashMap<UserId, Arc<Mutex<RaceBoard>>>,
+    progress: HashMap<UserId, Progress>,
+    finishers: Vec<(UserId, Duration, usize)>, // Time taken and moves, in finishing order
+    started_at: Option<Instant>,                // None while players can still join
+    announcement: Option<MessageId>,
+    standings: Option<MessageId>,
+}
+
+struct RaceBoard {
+    tiles: Vec<usize>,
+    options: BoardOptions,
+    selected: Option<usize>,
+    last_swap: Vec<usize>,
+    moves: usize,
+    message: Option<(ChannelId, MessageId)>,
+    finished: bool,
+}
+
+// What the standings show for a racer still solving
+#[derive(Debug, Clone, Copy, Default)]
+struct Progress {
+    percent: usize,
+    moves: usize,
+}
+
+impl Race {
+    fn scope(&self) -> String {
+        format!("{}{}:", RACE_PREFIX, self.id)
+    }
+
+    fn announcement_text(&self, timing: &RaceTiming) -> String {
+        let racers: Vec<_> = self.racers.iter().map(|user_id| user_id.mention().to_string()).collect();
+        format!(
+            "**Race!** {} wants to race. Press Join within {} seconds. Everyone gets the same scrambled \
+             picture in their direct messages, and the first to put it back together wins.\nRacers: {}",
+            self.host.mention(),
+            timing.join_window.as_secs(),
+            racers.join(", ")
+        )
+    }
+
+    // Racers still solving, by how much of the picture they have in place, then fewest moves
+    fn solving(&self) -> Vec<(UserId, Progress)> {
+        let mut solving: Vec<_> = self
+            .racers
+            .iter()
+            .filter(|user_id| !self.finishers.iter().any(|(finisher, _, _)| finisher == *user_id))
+            .map(|&user_id| (user_id, self.progress.get(&user_id).copied().unwrap_or_default()))
+            .collect();
+        solving.sort_by(|a, b| b.1.percent.cmp(&a.1.percent).then(a.1.moves.cmp(&b.1.moves)));
+        solving
+    }
+
+    // Everyone from first to last: finishers in order, then the rest as the standings show them
+    fn ranking(&self) -> Vec<UserId> {
+        self.finishers
+            .iter()
+            .map(|&(user_id, _, _)| user_id)
+            .chain(self.solving().into_iter().map(|(user_id, _)| user_id))
+            .collect()
+    }
+
+    // Finishers in order, then everyone else by how much of the picture they have in place
+    fn standings_text(&self) -> String {
+        let mut text = format!("**{} standings**", self.title);
+        for (place, (user_id, taken, moves)) in self.finishers.iter().enumerate() {
+            text.push_str(&format!(
+                "\n{}. {} finished in {} moves ({})",
+                place + 1,
+                user_id.mention(),
+                moves,
+                clock(*taken)
+            ));
+        }
+        for (rank, (user_id, progress)) in self.solving().iter().enumerate() {
+            text.push_str(&format!(
+                "\n{}. {}: {}% in place, {} move{}",
+                self.finishers.len() + rank + 1,
+                user_id.mention(),
+                progress.percent,
+                progress.moves,
+                if progress.moves == 1 { "" } else { "s" }
+            ));
+        }
+        text
+    }
+}
+
+impl RaceBoard {
+    fn solved(&self) -> bool {
+        self.tiles.iter().enumerate().all(|(slot, &tile)| slot == tile)
+    }
+
+    fn controls(&self, grid_size: u32, scope: &str, disabled: bool) -> Vec<CreateActionRow> {
+        components::tile_controls(grid_size, &self.tiles, &self.options, self.selected, disabled, scope)
+    }
+
+    fn caption(&self, title: &str, channel_id: ChannelId, board: &RenderedBoard) -> String {
+        let status = if self.finished {
+            format!("Finished in {} moves! See how you placed in {}.", self.moves, channel_id.mention())
+        } else {
+            format!(
+                "{} in {}: put the picture back together.\nMoves: {}\nSolved: {}%",
+                title,
+                channel_id.mention(),
+                self.moves,
+                board::percent_solved(&self.tiles)
+            )
+        };
+        match board {
+            RenderedBoard::Image(..) => status,
+            RenderedBoard::Text(grid) => format!("{}\n{}", status, grid),
+        }
+    }
+}
+
+// Pick and load a puzzle for a race in the channel, with nobody in it yet
+async fn load_race(
+    ctx: &Context,
+    channel_id: ChannelId,
+    guild_id: Option<GuildId>,
+    nsfw_channel: bool,
+    filter: Option<&str>,
+    host: UserId,
+) -> Result<Race, String> {
+    let (manifest, config, theme) = {
+        let data = ctx.data.read().await;
+        let config = data.get::<ConfigKey>().unwrap().get(guild_id);
+        let theme = config.theme.resolve(data.get::<LabelStyleKey>().unwrap());
+        (data.get::<ManifestKey>().unwrap().clone(), config, theme)
+    };
+    let puzzle = manifest
+        .pick(nsfw_channel, filter)
+        .ok_or("There are no puzzles available for this channel.")?;
+    let image = load_image(puzzle).await.map_err(|e| {
+        eprintln!("Failed to load puzzle \"{}\": {:?}", puzzle.title, e);
+        "Failed to load the puzzle image. Please try again."
+    })?;
+    Ok(Race {
+        id: rand::random(),
+        channel_id,
+        guild_id,
+        title: "Race".to_string(),
+        match_of: None,
+        host,
+        puzzle: puzzle.clone(),
+        image,
+        grid_size: config.grid_size.unwrap_or(puzzle.grid_size),
+        theme,
+        racers: Vec::new(),
+        boards: HashMap::new(),
+        progress: HashMap::new(),
+        finishers: Vec::new(),
+        started_at: None,
+        announcement: None,
+        standings: None,
+    })
+}
+
+// Whether a race other than a match is open or running in the channel
+fn channel_busy(races: &HashMap<u64, RaceEntry>, channel_id: ChannelId) -> bool {
+    races.values().any(|entry| entry.channel_id == channel_id && !entry.is_match)
+}
+
+// Announce a race, wait for players to join, then send everyone their board
+pub async fn start(ctx: &Context, invoker: &Invoker, filter: Option<&str>) -> ActionResult {
+    let nsfw_channel = match invoker.channel_id.to_channel(ctx).await? {
+        Channel::Guild(channel) => channel.nsfw,
+        _ => false,
+    };
+    let (config, timing) = {
+        let data = ctx.data.read().await;
+        (data.get::<ConfigKey>().unwrap().get(invoker.guild_id), *data.get::<RaceTimingKey>().unwrap())
+    };
+    if !config.allows_channel(invoker.channel_id) {
+        return Ok(Some("Puzzles can't be played in this channel.".to_string()));
+    }
+    if channel_busy(ctx.data.read().await.get::<RaceKey>().unwrap(), invoker.channel_id) {
+        return Ok(Some("A race is already running in this channel.".to_string()));
+    }
+
+    let mut race = match load_race(ctx, invoker.channel_id, invoker.guild_id, nsfw_channel, filter, invoker.user_id).await {
+        Ok(race) => race,
+        Err(e) => return Ok(Some(e)),
+    };
+    if let Err(e) = welcome(ctx, invoker.channel_id, invoker.user_id).await {
+        eprintln!("Failed to DM race host: {:?}", e);
+        return Ok(Some(
+            "I couldn't send you a direct message, which is where race boards go. \
+             Allow direct messages from this server's members and try again."
+                .to_string(),
+        ));
+    }
+    race.racers.push(invoker.user_id);
+
+    let (race_id, text) = (race.id, race.announcement_text(&timing));
+    let race = Arc::new(Mutex::new(race));
+    {
+        let mut data = ctx.data.write().await;
+        let races = data.get_mut::<RaceKey>().unwrap();
+        // Someone may have started one while the image loaded
+        if channel_busy(races, invoker.channel_id) {
+            return Ok(Some("A race is already running in this channel.".to_string()));
+        }
+        races.insert(
+            race_id,
+            RaceEntry {
+                channel_id: invoker.channel_id,
+                is_match: false,
+                race: race.clone(),
+            },
+        );
+    }
+
+    let announcement = invoker
+        .channel_id
+        .send_message(&ctx.http, |m| {
+            m.content(text)
+                .allowed_mentions(|mentions| mentions.empty_parse())
+                .components(|c| c.add_action_row(join_buttons(race_id)))
+        })
+        .await;
+    match announcement {
+        Ok(message) => race.lock().await.announcement = Some(message.id),
+        Err(e) => {
+            ctx.data.write().await.get_mut::<RaceKey>().unwrap().remove(&race_id);
+            return Err(e.into());
+        }
+    }
+
+    tokio::time::sleep(timing.join_window).await;
+    begin(ctx, race, timing).await?;
+    Ok(None)
+}
+
+// What a head-to-head match race is played for
+#[derive(Debug, Clone, Copy)]
+pub enum MatchOf {
+    Tournament(u64, usize), // Tournament id and match
+    Duel(u64), // Duel id, which also holds any wagers
+}
+
+// A head-to-head race a tournament or duel asks for
+pub struct MatchSetup {
+    pub channel_id: ChannelId,
+    pub guild_id: Option<GuildId>,
+    pub nsfw_channel: bool,
+    pub filter: Option<String>,
+    pub racers: [UserId; 2],
+    pub title: String,
+    pub grid_size: Option<u32>, // The server's or puzzle's size when None
+    pub match_of: MatchOf,
+}
+
+// Race a match between the given players straight away, with no join window
+pub async fn start_match(ctx: &Context, setup: MatchSetup) -> Result<(), BoxError> {
+    let channel_id = setup.channel_id;
+    let mut race = load_race(
+        ctx,
+        channel_id,
+        setup.guild_id,
+        setup.nsfw_channel,
+        setup.filter.as_deref(),
+        setup.racers[0],
+    )
+    .await?;
+    race.racers = setup.racers.to_vec();
+    race.title = setup.title;
+    race.match_of = Some(setup.match_of);
+    if let Some(grid_size) = setup.grid_size {
+        race.grid_size = grid_size;
+    }
+
+    let race_id = race.id;
+    let race = Arc::new(Mutex::new(race));
+    let timing = {
+        let mut data = ctx.data.write().await;
+        data.get_mut::<RaceKey>().unwrap().insert(
+            race_id,
+            RaceEntry {
+                channel_id,
+                is_match: true,
+                race: race.clone(),
+            },
+        );
+        *data.get::<RaceTimingKey>().unwrap()
+    };
+    begin(ctx, race, timing).await
+}
+
+// Close the join window and send every racer their board, or call the race off
+async fn begin(ctx: &Context, race: Arc<Mutex<Race>>, timing: RaceTiming) -> Result<(), BoxError> {
+    let (renderer, encoder) = {
+        let data = ctx.data.read().await;
+        (data.get::<RendererKey>().unwrap().clone(), *data.get::<EncoderKey>().unwrap())
+    };
+
+    let mut race_guard = race.lock().await;
+    let (race_id, channel_id, announcement) = (race_guard.id, race_guard.channel_id, race_guard.announcement);
+    if race_guard.racers.len() < MIN_RACERS {
+        drop(race_guard);
+        ctx.data.write().await.get_mut::<RaceKey>().unwrap().remove(&race_id);
+        if let Some(message_id) = announcement {
+            channel_id
+                .edit_message(&ctx.http, message_id, |m| {
+                    m.content(format!("Not enough players joined, so the race was called off. It takes at least {}.", MIN_RACERS))
+                        .components(|c| c)
+                })
+                .await?;
+        }
+        return Ok(());
+    }
+
+    let tiles = board::seeded_positions((race_guard.grid_size * race_guard.grid_size) as usize, race_id);
+    let options = {
+        let data = ctx.data.read().await;
+        let config = data.get::<ConfigKey>().unwrap().get(race_guard.guild_id);
+        race_guard
+            .racers
+            .iter()
+            .map(|&user_id| (user_id, player_board_options(&data, &config, user_id)))
+            .collect::<Vec<_>>()
+    };
+    for (user_id, options) in options {
+        let board = RaceBoard {
+            tiles: tiles.clone(),
+            options,
+            selected: None,
+            last_swap: Vec::new(),
+            moves: 0,
+            message: None,
+            finished: false,
+        };
+        race_guard.boards.insert(user_id, Arc::new(Mutex::new(board)));
+        race_guard.progress.insert(
+            user_id,
+            Progress {
+                percent: board::percent_solved(&tiles),
+                moves: 0,
+            },
+        );
+    }
+    race_guard.started_at = Some(Instant::now());
+
+    let racer_list: Vec<_> = race_guard.racers.iter().map(|user_id| user_id.mention().to_string()).collect();
+    if let Some(message_id) = announcement {
+        let text = format!("**Race!** The race is on. Racers: {}", racer_list.join(", "));
+        if let Err(e) = channel_id
+            .edit_message(&ctx.http, message_id, |m| m.content(text).components(|c| c))
+            .await
+        {
+            eprintln!("Failed to close race announcement: {:?}", e);
+        }
+    }
+
+    let scope = race_guard.scope();
+    for (&user_id, board) in &race_guard.boards {
+        let mut board = board.lock().await;
+        let snapshot = BoardSnapshot {
+            image: race_guard.image.clone(),
+            grid_size: race_guard.grid_size,
+            tile_positions: board.tiles.clone(),
+            options: board.options,
+            highlights: Vec::new(),
+            theme: race_guard.theme.clone(),
+        };
+        let rendered = render_blocking(board_renderer(board.options.display, renderer.clone(), encoder), snapshot).await?;
+        let content = board.caption(&race_guard.title, channel_id, &rendered);
+        let rows = board.controls(race_guard.grid_size, &scope, false);
+        let sent = match user_id.create_dm_channel(ctx).await {
+            Ok(dm) => dm
+                .send_message(&ctx.http, |m| {
+                    m.content(content);
+                    if let RenderedBoard::Image(attachment, alt_text) = rendered {
+                        m.add_file(attachment);
+                        describe_upload(&mut m.0, &alt_text);
+                    }
+                    m.components(|c| c.set_action_rows(rows))
+                })
+                .await
+                .map(|message| (dm.id, message.id)),
+            Err(e) => Err(e),
+        };
+        match sent {
+            Ok(message) => board.message = Some(message),
+            Err(e) => eprintln!("Failed to send race board to {}: {:?}", user_id, e),
+        }
+    }
+
+    let standings = race_guard.standings_text();
+    drop(race_guard);
+    let message = channel_id
+        .send_message(&ctx.http, |m| m.content(standings).allowed_mentions(|mentions| mentions.empty_parse()))
+        .await?;
+    race.lock().await.standings = Some(message.id);
+
+    let ctx = ctx.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(timing.time_limit).await;
+        finish(&ctx, race_id, "Time's up!").await;
+    });
+    Ok(())
+}
+
+// A DM to check a player can be reached before they join
+async fn welcome(ctx: &Context, channel_id: ChannelId, user_id: UserId) -> Result<(), BoxError> {
+    user_id
+        .create_dm_channel(ctx)
+        .await?
+        .say(
+            &ctx.http,
+            format!("You're in the race in {}! Your board will appear here when it starts.", channel_id.mention()),
+        )
+        .await?;
+    Ok(())
+}
+
+fn join_buttons(race_id: u64) -> CreateActionRow {
+    let mut row = CreateActionRow::default();
+    row.create_button(|button| {
+        button
+            .label("Join")
+            .custom_id(format!("{}{}", JOIN_PREFIX, race_id))
+            .style(ButtonStyle::Success)
+    });
+    row.create_button(|button| {
+        button
+            .label("Leave")
+            .custom_id(format!("{}{}", LEAVE_PREFIX, race_id))
+            .style(ButtonStyle::Secondary)
+    });
+    row
+}
+
+pub fn is_race_button(custom_id: &str) -> bool {
+    custom_id.starts_with(RACE_PREFIX)
+}
+
+// Join and Leave on the announcement, or a tile on someone's race board
+pub async fn handle_component(ctx: &Context, component: &MessageComponentInteraction) {
+    let custom_id = component.data.custom_id.as_str();
+    if let Some(race_id) = custom_id.strip_prefix(JOIN_PREFIX).and_then(|id| id.parse().ok()) {
+        return handle_join(ctx, component, race_id, true).await;
+    }
+    if let Some(race_id) = custom_id.strip_prefix(LEAVE_PREFIX).and_then(|id| id.parse().ok()) {
+        return handle_join(ctx, component, race_id, false).await;
+    }
+    let parsed = custom_id.strip_prefix(RACE_PREFIX).and_then(|rest| {
+        let (race_id, control) = rest.split_once(':')?;
+        let slot = components::clicked_tile(control, &component.data.values)?;
+        Some((race_id.parse::<u64>().ok()?, slot))
+    });
+    if let Some((race_id, slot)) = parsed {
+        handle_tile_click(ctx, component, race_id, slot).await;
+    }
+}
+
+async fn handle_join(ctx: &Context, component: &MessageComponentInteraction, race_id: u64, joining: bool) {
+    let (race, timing) = {
+        let data = ctx.data.read().await;
+        (
+            data.get::<RaceKey>().unwrap().get(&race_id).map(|entry| entry.race.clone()),
+            *data.get::<RaceTimingKey>().unwrap(),
+        )
+    };
+    let race = match race {
+        Some(race) => race,
+        None => return respond_ephemeral(ctx, component, "This race is over.").await,
+    };
+    let user_id = component.user.id;
+
+    if joining {
+        {
+            let race = race.lock().await;
+            if race.started_at.is_some() {
+                return respond_ephemeral(ctx, component, "This race has already started.").await;
+            }
+            if race.racers.contains(&user_id) {
+                return respond_ephemeral(ctx, component, "You're already in this race.").await;
+            }
+        }
+        // Sending the DM can be slow, so it happens without holding the race
+        if let Err(e) = welcome(ctx, component.channel_id, user_id).await {
+            eprintln!("Failed to DM race entrant: {:?}", e);
+            return respond_ephemeral(
+                ctx,
+                component,
+                "I couldn't send you a direct message, which is where race boards go. \
+                 Allow direct messages from this server's members, then press Join again.",
+            )
+            .await;
+        }
+    }
+
+    let text = {
+        let mut race = race.lock().await;
+        if race.started_at.is_some() {
+            drop(race);
+            return respond_ephemeral(ctx, component, "This race has already started.").await;
+        }
+        if joining {
+            if !race.racers.contains(&user_id) {
+                race.racers.push(user_id);
+            }
+        } else if race.racers.contains(&user_id) {
+            race.racers.retain(|&racer| racer != user_id);
+        } else {
+            drop(race);
+            return respond_ephemeral(ctx, component, "You haven't joined this race.").await;
+        }
+        race.announcement_text(&timing)
+    };
+    if let Err(e) = component
+        .create_interaction_response(&ctx.http, |response| {
+            response.kind(InteractionResponseType::UpdateMessage).interaction_response_data(|message| {
+                message.content(text).allowed_mentions(|mentions| mentions.empty_parse())
+            })
+        })
+        .await
+    {
+        eprintln!("Failed to update race announcement: {:?}", e);
+    }
+}
+
+// Pick up a tile, put it back, or swap it with the one picked up, then update the standings
+async fn handle_tile_click(
+    ctx: &Context,
+    component: &MessageComponentInteraction,
+    race_id: u64,
+    slot: usize,
+) {
+    let race = ctx.data.read().await.get::<RaceKey>().unwrap().get(&race_id).map(|entry| entry.race.clone());
+    let race = match race {
+        Some(race) => race,
+        None => return respond_ephemeral(ctx, component, "This race is over.").await,
+    };
+    let (board, snapshot, scope, started_at, channel_id, title) = {
+        let race = race.lock().await;
+        let board = race.boards.get(&component.user.id).cloned();
+        match (board, race.started_at) {
+            (Some(board), Some(started_at)) => (
+                board,
+                BoardSnapshot {
+                    image: race.image.clone(),
+                    grid_size: race.grid_size,
+                    tile_positions: Vec::new(),
+                    options: BoardOptions::default(),
+                    highlights: Vec::new(),
+                    theme: race.theme.clone(),
+                },
+                race.scope(),
+                started_at,
+                race.channel_id,
+                race.title.clone(),
+            ),
+            _ => {
+                drop(race);
+                return respond_ephemeral(ctx, component, "This race is over.").await;
+            }
+        }
+    };
+    let grid_size = snapshot.grid_size;
+
+    // The board is released before the race is locked again, so the two are never held together
+    let (progress, finished) = {
+        let mut board = board.lock().await;
+        if board.finished {
+            drop(board);
+            return respond_ephemeral(ctx, component, "You've already finished. Watch the standings in the channel!").await;
+        }
+        if slot >= board.tiles.len() {
+            drop(board);
+            return respond_ephemeral(ctx, component, "That tile isn't on your board.").await;
+        }
+
+        let first = match board.selected {
+            Some(first) if first != slot => first,
+            selected => {
+                board.selected = if selected == Some(slot) { None } else { Some(slot) };
+                let rows = board.controls(grid_size, &scope, false);
+                if let Err(e) = component
+                    .create_interaction_response(&ctx.http, |response| {
+                        response
+                            .kind(InteractionResponseType::UpdateMessage)
+                            .interaction_response_data(|message| message.components(|c| c.set_action_rows(rows)))
+                    })
+                    .await
+                {
+                    eprintln!("Failed to update race tile buttons: {:?}", e);
+                }
+                return;
+            }
+        };
+        // Rendering can take longer than the three seconds Discord allows
+        if let Err(e) = component
+            .create_interaction_response(&ctx.http, |response| response.kind(InteractionResponseType::DeferredUpdateMessage))
+            .await
+        {
+            eprintln!("Failed to acknowledge race tile click: {:?}", e);
+        }
+
+        board.tiles.swap(first, slot);
+        board.last_swap = vec![first, slot];
+        board.selected = None;
+        board.moves += 1;
+        board.finished = board.solved();
+
+        let (renderer, encoder) = {
+            let data = ctx.data.read().await;
+            (data.get::<RendererKey>().unwrap().clone(), *data.get::<EncoderKey>().unwrap())
+        };
+        let snapshot = BoardSnapshot {
+            tile_positions: board.tiles.clone(),
+            options: board.options,
+            highlights: board.last_swap.clone(),
+            ..snapshot
+        };
+        match render_blocking(board_renderer(board.options.display, renderer, encoder), snapshot).await {
+            Ok(rendered) => {
+                if let Err(e) = edit_board(ctx, &board, &title, grid_size, channel_id, &scope, rendered).await {
+                    eprintln!("Failed to update race board: {:?}", e);
+                }
+            }
+            Err(e) => eprintln!("Failed to render race board: {:?}", e),
+        }
+        (
+            Progress {
+                percent: board::percent_solved(&board.tiles),
+                moves: board.moves,
+            },
+            board.finished,
+        )
+    };
+
+    let (standings, standings_message, decided) = {
+        let mut race = race.lock().await;
+        let user_id = component.user.id;
+        race.progress.insert(user_id, progress);
+        if finished {
+            race.finishers.push((user_id, started_at.elapsed(), progress.moves));
+        }
+        // A match is decided by its first finisher
+        let decided = if race.finishers.len() == race.racers.len() {
+            Some("Everyone finished!")
+        } else if race.match_of.is_some() && finished {
+            Some("We have a winner!")
+        } else {
+            None
+        };
+        (race.standings_text(), race.standings, decided)
+    };
+    if let Some(message_id) = standings_message {
+        if let Err(e) = channel_id
+            .edit_message(&ctx.http, message_id, |m| m.content(standings).allowed_mentions(|mentions| mentions.empty_parse()))
+            .await
+        {
+            eprintln!("Failed to update race standings: {:?}", e);
+        }
+    }
+    if let Some(reason) = decided {
+        finish(ctx, race_id, reason).await;
+    }
+}
+
+// Show a racer's board after a move, replacing the old image
+async fn edit_board(
+    ctx: &Context,
+    board: &RaceBoard,
+    title: &str,
+    grid_size: u32,
+    channel_id: ChannelId,
+    scope: &str,
+    rendered: RenderedBoard,
+) -> Result<(), BoxError> {
+    let (dm, message_id) = match board.message {
+        Some(message) => message,
+        None => return Ok(()),
+    };
+    let content = board.caption(title, channel_id, &rendered);
+    let rows = board.controls(grid_size, scope, board.finished);
+    dm.edit_message(&ctx.http, message_id, |m| {
+        m.content(content)
+            .components(|c| c.set_action_rows(rows))
+            .remove_all_attachments();
+        if let RenderedBoard::Image(attachment, alt_text) = &rendered {
+            m.attachment(attachment.clone());
+            describe_upload(&mut m.0, alt_text);
+        }
+        m
+    })
+    .await?;
+    Ok(())
+}
+
+// End the race if it is still running: score and rate the placements, reveal the picture and lock
+// every board. Finishers earn one point per racer they beat plus one, times the server's solve score.
+async fn finish(ctx: &Context, race_id: u64, reason: &str) {
+    let race_handle = match ctx.data.read().await.get::<RaceKey>().unwrap().get(&race_id) {
+        Some(entry) => entry.race.clone(),
+        None => return,
+    };
+    let race = race_handle.lock().await;
+    // It may already have been ended while we waited for the lock
+    {
+        let mut data = ctx.data.write().await;
+        let races = data.get_mut::<RaceKey>().unwrap();
+        if !races.get(&race_id).is_some_and(|entry| Arc::ptr_eq(&entry.race, &race_handle)) {
+            return;
+        }
+        races.remove(&race_id);
+    }
+    let channel_id = race.channel_id;
+
+    let solve_points = {
+        let data = ctx.data.read().await;
+        data.get::<ConfigKey>().unwrap().get(race.guild_id).scoring.solve
+    };
+    let awards: Vec<(UserId, usize)> = (0..race.finishers.len())
+        .map(|place| (race.finishers[place].0, (race.racers.len() - place) * solve_points))
+        .collect();
+    let placements: Vec<(UserId, Option<usize>)> = race
+        .racers
+        .iter()
+        .map(|&user_id| (user_id, race.finishers.iter().position(|&(finisher, _, _)| finisher == user_id)))
+        .collect();
+    let rating_changes = {
+        let mut data = ctx.data.write().await;
+        let scores = data.get_mut::<ScoresKey>().unwrap();
+        for &(user_id, points) in &awards {
+            *scores.entry(user_id).or_insert(0) += points;
+        }
+        if let Some(guild_id) = race.guild_id {
+            let ledger = data.get_mut::<EconomyKey>().unwrap();
+            for (place, &(user_id, points)) in awards.iter().enumerate() {
+                let reason = format!("{}, {} place", race.title, ordinal(place + 1));
+                ledger.credit(guild_id, user_id, points as u64 * economy::COINS_PER_POINT, &reason);
+            }
+        }
+        let stats = data.get_mut::<StatsKey>().unwrap();
+        if awards.is_empty() {
+            stats.abandoned += 1;
+        } else {
+            stats.solved += 1;
+        }
+        data.get_mut::<RatingsKey>().unwrap().record_race(&placements)
+    };
+    let rating = |user_id: &UserId| {
+        rating_changes
+            .get(user_id)
+            .map_or(String::new(), |&change| format!(", rating {}", ratings::describe_change(change)))
+    };
+
+    let mut text = format!("**{} over!** {}", race.title, reason);
+    for (place, (user_id, taken, moves)) in race.finishers.iter().enumerate() {
+        let points = awards[place].1;
+        text.push_str(&format!(
+            "\n{} {} in {} moves ({}), +{} point{}{}",
+            ordinal(place + 1),
+            user_id.mention(),
+            moves,
+            clock(*taken),
+            points,
+            if points == 1 { "" } else { "s" },
+            rating(user_id)
+        ));
+    }
+    for (user_id, _) in placements.iter().filter(|(_, place)| place.is_none()) {
+        let percent = race.progress.get(user_id).map_or(0, |progress| progress.percent);
+        text.push_str(&format!(
+            "\n{} did not finish ({}% in place){}",
+            user_id.mention(),
+            percent,
+            rating(user_id)
+        ));
+    }
+    text.push_str(&format!(
+        "\nThe picture was **{}**: **{}**\n{}",
+        race.puzzle.answer, race.puzzle.title, race.puzzle.attribution
+    ));
+
+    let encoder = *ctx.data.read().await.get::<EncoderKey>().unwrap();
+    let attachment = match encode_blocking(race.image.clone(), encoder, "original").await {
+        Ok(attachment) => Some(attachment),
+        Err(e) => {
+            eprintln!("Failed to encode race picture: {:?}", e);
+            None
+        }
+    };
+    if let Err(e) = channel_id
+        .send_message(&ctx.http, |m| {
+            m.content(text).allowed_mentions(|mentions| mentions.empty_parse());
+            if let Some(attachment) = attachment {
+                m.add_file(attachment);
+            }
+            m
+        })
+        .await
+    {
+        eprintln!("Failed to post race results: {:?}", e);
+    }
+
+    let scope = race.scope();
+    for board in race.boards.values() {
+        let board = board.lock().await;
+        if let Some((dm, message_id)) = board.message {
+            let rows = board.controls(race.grid_size, &scope, true);
+            if let Err(e) = dm
+                .edit_message(&ctx.http, message_id, |m| m.components(|c| c.set_action_rows(rows)))
+                .await
+            {
+                eprintln!("Failed to disable race board: {:?}", e);
+            }
+        }
+    }
+
+    match race.match_of {
+        Some(MatchOf::Tournament(tournament_id, index)) => {
+            let ranking = race.ranking();
+            drop(race);
+            tournament::report(ctx, channel_id, tournament_id, index, ranking).await;
+        }
+        Some(MatchOf::Duel(duel_id)) => {
+            let players = [race.racers[0], race.racers[1]];
+            let winner = race.finishers.first().map(|&(user_id, _, _)| user_id);
+            drop(race);
+            duel::report(ctx, channel_id, duel_id, players, winner).await;
+        }
+        None => {}
+    }
+}
+
+// e.g. 1:05
+fn clock(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+pub fn ordinal(place: usize) -> String {
+    let suffix = match (place % 10, place % 100) {
+        (_, 11..=13) => "th",
+        (1, _) => "st",
+        (2, _) => "nd",
+        (3, _) => "rd",
+        _ => "th",
+    };
+    format!("{}{}", place, suffix)
+}
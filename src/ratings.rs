@@ -0,0 +1,282 @@
+use serde::{Deserialize, Serialize};
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+use std::collections::HashMap;
+use std::f64::consts::{LN_10, PI};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::Notify;
+
+use crate::actions::ActionResult;
+use crate::config::SavedStore;
+use crate::manifest::PuzzleEntry;
+use crate::RatingsKey;
+
+const STARTING_RATING: f64 = 1500.0;
+const STARTING_DEVIATION: f64 = 350.0;
+// Keeps ratings moving a little however many games someone plays
+const MIN_DEVIATION: f64 = 50.0;
+
+// A Glicko rating: the estimate, and how unsure it still is
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Rating {
+    pub value: f64,
+    pub deviation: f64,
+    pub games: usize,
+}
+
+impl Default for Rating {
+    fn default() -> Self {
+        Rating {
+            value: STARTING_RATING,
+            deviation: STARTING_DEVIATION,
+            games: 0,
+        }
+    }
+}
+
+impl Rating {
+    // Glicko-1 update after a set of games played at the same time, each scored
+    // 1 for a win, 0.5 for a draw and 0 for a loss against the given opponent
+    fn updated(self, results: &[(Rating, f64)]) -> Rating {
+        if results.is_empty() {
+            return self;
+        }
+        let q = LN_10 / 400.0;
+        let (mut information, mut change) = (0.0, 0.0);
+        for &(opponent, score) in results {
+            let g = 1.0 / (1.0 + 3.0 * q * q * opponent.deviation * opponent.deviation / (PI * PI)).sqrt();
+            let expected = 1.0 / (1.0 + 10f64.powf(-g * (self.value - opponent.value) / 400.0));
+            information += q * q * g * g * expected * (1.0 - expected);
+            change += g * (score - expected);
+        }
+        let precision = 1.0 / (self.deviation * self.deviation) + information;
+        Rating {
+            value: self.value + q / precision * change,
+            deviation: (1.0 / precision).sqrt().max(MIN_DEVIATION),
+            games: self.games + 1,
+        }
+    }
+}
+
+// A rating before and after a result
+pub type RatingChange = (Rating, Rating);
+
+// Puzzles are rated per board size, since the same picture is far harder cut into more tiles
+pub fn puzzle_key(puzzle: &PuzzleEntry, grid_size: u32) -> String {
+    format!("{}#{}", puzzle.source, grid_size)
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct RatingData {
+    players: HashMap<UserId, Rating>,
+    puzzles: HashMap<String, Rating>,
+}
+
+// Skill ratings for players and difficulty ratings for puzzles, saved to a JSON file by `keep_saved` after every change.
+// Races, duels and tournament matches rate players against each other; a solo puzzle is rated like a
+// chess puzzle, as a game between the player and the puzzle.
+pub struct RatingStore {
+    path: PathBuf,
+    data: RatingData,
+    changed: Arc<Notify>,
+}
+
+impl RatingStore {
+    // Load the ratings, starting empty if the file does not exist yet
+    pub fn load(path: PathBuf) -> Result<Self, String> {
+        let data = match std::fs::read_to_string(&path) {
+            Ok(json) => serde_json::from_str(&json).map_err(|e| format!("{}: {}", path.display(), e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => RatingData::default(),
+            Err(e) => return Err(format!("{}: {}", path.display(), e)),
+        };
+        Ok(RatingStore {
+            path,
+            data,
+            changed: Arc::new(Notify::new()),
+        })
+    }
+
+    pub fn player(&self, user_id: UserId) -> Rating {
+        self.data.players.get(&user_id).copied().unwrap_or_default()
+    }
+
+    pub fn puzzle(&self, key: &str) -> Rating {
+        self.data.puzzles.get(key).copied().unwrap_or_default()
+    }
+
+    // Players with at least one rated game, best first
+    pub fn ranked_players(&self) -> Vec<(UserId, Rating)> {
+        let mut players: Vec<_> = self
+            .data
+            .players
+            .iter()
+            .filter(|(_, rating)| rating.games > 0)
+            .map(|(&user_id, &rating)| (user_id, rating))
+            .collect();
+        players.sort_by(|a, b| b.1.value.total_cmp(&a.1.value).then(a.0.cmp(&b.0)));
+        players
+    }
+
+    // Rate a race between players, given each one's finishing place or None if they didn't
+    // finish. Every pair of players counts as a game: the better place wins, and two players
+    // who didn't finish draw.
+    pub fn record_race(&mut self, placements: &[(UserId, Option<usize>)]) -> HashMap<UserId, RatingChange> {
+        let before: HashMap<UserId, Rating> = placements.iter().map(|&(user_id, _)| (user_id, self.player(user_id))).collect();
+        let mut changes = HashMap::new();
+        for &(user_id, place) in placements {
+            let results: Vec<(Rating, f64)> = placements
+                .iter()
+                .filter(|&&(other, _)| other != user_id)
+                .map(|&(other, other_place)| {
+                    let score = match (place, other_place) {
+                        (Some(place), Some(other_place)) if place < other_place => 1.0,
+                        (Some(place), Some(other_place)) if place > other_place => 0.0,
+                        (Some(_), None) => 1.0,
+                        (None, Some(_)) => 0.0,
+                        _ => 0.5,
+                    };
+                    (before[&other], score)
+                })
+                .collect();
+            let after = before[&user_id].updated(&results);
+            self.data.players.insert(user_id, after);
+            changes.insert(user_id, (before[&user_id], after));
+        }
+        self.changed.notify_one();
+        changes
+    }
+
+    // Rate a solo attempt at a puzzle as a game the player wins by solving it
+    pub fn record_attempt(&mut self, user_id: UserId, puzzle_key: &str, solved: bool) -> RatingChange {
+        let (player, puzzle) = (self.player(user_id), self.puzzle(puzzle_key));
+        let score = if solved { 1.0 } else { 0.0 };
+        let after = player.updated(&[(puzzle, score)]);
+        self.data.players.insert(user_id, after);
+        self.data.puzzles.insert(puzzle_key.to_string(), puzzle.updated(&[(player, 1.0 - score)]));
+        self.changed.notify_one();
+        (player, after)
+    }
+}
+
+impl SavedStore for RatingStore {
+    const NAME: &'static str = "ratings";
+
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn changes(&self) -> &Arc<Notify> {
+        &self.changed
+    }
+
+    fn to_json(&self) -> Result<String, String> {
+        serde_json::to_string_pretty(&self.data).map_err(|e| e.to_string())
+    }
+}
+
+// "1532 (+18)" for a rating change
+pub fn describe_change((before, after): RatingChange) -> String {
+    format!("{:.0} ({:+.0})", after.value, after.value - before.value)
+}
+
+// "1532 ±84 (12 games)" for a rating
+fn describe(rating: Rating) -> String {
+    format!(
+        "{:.0} ±{:.0} ({} game{})",
+        rating.value,
+        rating.deviation,
+        rating.games,
+        if rating.games == 1 { "" } else { "s" }
+    )
+}
+
+pub async fn show(ctx: &Context, user_id: UserId) -> ActionResult {
+    let data = ctx.data.read().await;
+    let ratings = data.get::<RatingsKey>().unwrap();
+    let rating = ratings.player(user_id);
+    if rating.games == 0 {
+        return Ok(Some(format!(
+            "{} has no rated games yet. Solve a puzzle or join a race to get a rating!",
+            user_id.mention()
+        )));
+    }
+    let rank = ratings.ranked_players().iter().position(|&(ranked, _)| ranked == user_id).unwrap_or(0) + 1;
+    Ok(Some(format!("{}'s rating: {}, ranked #{}", user_id.mention(), describe(rating), rank)))
+}
+
+pub async fn leaderboard(ctx: &Context) -> ActionResult {
+    let data = ctx.data.read().await;
+    let players = data.get::<RatingsKey>().unwrap().ranked_players();
+    if players.is_empty() {
+        return Ok(Some("No one has a rating yet. Solve a puzzle or join a race to get one!".to_string()));
+    }
+    let mut text = String::from("**Rated leaderboard**");
+    for (rank, (user_id, rating)) in players.iter().take(10).enumerate() {
+        text.push_str(&format!("\n{}. {}: {}", rank + 1, user_id.mention(), describe(*rating)));
+    }
+    Ok(Some(text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rating(value: f64, deviation: f64) -> Rating {
+        Rating { value, deviation, games: 0 }
+    }
+
+    fn store() -> RatingStore {
+        RatingStore {
+            path: PathBuf::new(),
+            data: RatingData::default(),
+            changed: Arc::new(Notify::new()),
+        }
+    }
+
+    // The worked example from Glickman's "The Glicko system"
+    #[test]
+    fn matches_the_paper_example() {
+        let after = rating(1500.0, 200.0).updated(&[
+            (rating(1400.0, 30.0), 1.0),
+            (rating(1550.0, 100.0), 0.0),
+            (rating(1700.0, 300.0), 0.0),
+        ]);
+        assert!((after.value - 1464.0).abs() < 0.5, "{}", after.value);
+        assert!((after.deviation - 151.4).abs() < 0.5, "{}", after.deviation);
+        assert_eq!(after.games, 1);
+    }
+
+    #[test]
+    fn no_games_changes_nothing() {
+        let before = rating(1500.0, 200.0);
+        let after = before.updated(&[]);
+        assert_eq!((after.value, after.deviation, after.games), (before.value, before.deviation, before.games));
+    }
+
+    #[test]
+    fn deviation_never_drops_below_the_floor() {
+        let mut player = rating(1500.0, 60.0);
+        for _ in 0..50 {
+            player = player.updated(&[(rating(1500.0, 50.0), 0.5); 10]);
+        }
+        assert_eq!(player.deviation, MIN_DEVIATION);
+    }
+
+    #[test]
+    fn race_ranks_finishers_above_players_who_did_not_finish() {
+        let mut ratings = store();
+        let (first, second, dnf, other_dnf) = (UserId(1), UserId(2), UserId(3), UserId(4));
+        let changes = ratings.record_race(&[(first, Some(1)), (second, Some(2)), (dnf, None), (other_dnf, None)]);
+        let after = |user_id| changes[&user_id].1.value;
+        assert!(after(first) > after(second));
+        assert!(after(second) > STARTING_RATING);
+        assert!(after(dnf) < STARTING_RATING);
+        assert_eq!(after(dnf), after(other_dnf));
+        // Everyone started level, so the points won and lost balance out
+        let total: f64 = changes.values().map(|(before, after)| after.value - before.value).sum();
+        assert!(total.abs() < 1e-9);
+        assert!(changes.values().all(|&(_, after)| after.games == 1));
+        assert_eq!(ratings.ranked_players().first().map(|&(user_id, _)| user_id), Some(first));
+    }
+}
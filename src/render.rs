@@ -0,0 +1,215 @@
+use image::{DynamicImage, RgbaImage};
+use serenity::json::Value;
+use serenity::model::channel::AttachmentType;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::board::{self, column_name, BoardDisplay, BoardOptions, Notation};
+use crate::encoding::{self, EncodeError, EncoderSettings};
+use crate::image_processing::Renderer;
+use crate::theme::Theme;
+use crate::BoxError;
+
+// Everything a backend needs to draw the current state of a game
+pub struct BoardView<'a> {
+    pub image: &'a RgbaImage,
+    pub grid_size: u32,
+    pub tile_positions: &'a [usize],
+    pub options: &'a BoardOptions,
+    pub highlights: &'a [usize],
+    pub theme: &'a Theme,
+}
+
+// Owned copy of a board, so it can be rendered on another thread while the game stays unlocked
+pub struct BoardSnapshot {
+    pub image: Arc<RgbaImage>,
+    pub grid_size: u32,
+    pub tile_positions: Vec<usize>,
+    pub options: BoardOptions,
+    pub highlights: Vec<usize>,
+    pub theme: Theme,
+}
+
+impl BoardSnapshot {
+    pub fn view(&self) -> BoardView<'_> {
+        BoardView {
+            image: &self.image,
+            grid_size: self.grid_size,
+            tile_positions: &self.tile_positions,
+            options: &self.options,
+            highlights: &self.highlights,
+            theme: &self.theme,
+        }
+    }
+}
+
+pub enum RenderedBoard {
+    Image(AttachmentType<'static>, String), // The upload and its alt text for screen readers
+    Text(String),
+}
+
+// Discord's limit on an attachment's description
+const MAX_DESCRIPTION_LENGTH: usize = 1024;
+
+// Set the description of the first file uploaded with a message, which screen readers announce
+// in place of the image. `fields` is a message builder's JSON body: serenity 0.11 has no builder
+// method for attachment descriptions, so the entry for the upload is added by hand.
+pub fn describe_upload(fields: &mut HashMap<&'static str, Value>, description: &str) {
+    let description: String = description.chars().take(MAX_DESCRIPTION_LENGTH).collect();
+    let attachments = fields.entry("attachments").or_insert_with(|| Value::Array(Vec::new()));
+    if let Value::Array(attachments) = attachments {
+        attachments.push(serde_json::json!({ "id": 0, "description": description }));
+    }
+}
+
+pub trait BoardRenderer: Send + Sync {
+    fn render(&self, board: &BoardView<'_>) -> Result<RenderedBoard, BoxError>;
+}
+
+// Build a filename that is unique across concurrent games, so uploads never collide
+pub fn unique_filename(prefix: &str, extension: &str) -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or_default();
+    let id = COUNTER.fetch_add(1, Ordering::Relaxed);
+    format!("{}-{}-{}.{}", prefix, millis, id, extension)
+}
+
+// Every image we upload goes through the encoder, so it fits the configured format and size budget
+pub fn image_attachment(image: &DynamicImage, settings: &EncoderSettings, prefix: &str) -> Result<AttachmentType<'static>, EncodeError> {
+    let encoded = encoding::encode(image, settings)?;
+    if (encoded.width, encoded.height) != (image.width(), image.height()) {
+        eprintln!(
+            "Downscaled {} from {}x{} to {}x{} to fit the upload budget",
+            prefix, image.width(), image.height(), encoded.width, encoded.height
+        );
+    }
+    Ok(AttachmentType::Bytes {
+        filename: unique_filename(prefix, encoded.format.extension()),
+        data: Cow::Owned(encoded.bytes),
+    })
+}
+
+// Compositing and encoding are CPU-bound, so both run on tokio's blocking pool
+// instead of stalling the async runtime that serves every other game
+pub async fn render_blocking(renderer: Arc<dyn BoardRenderer>, snapshot: BoardSnapshot) -> Result<RenderedBoard, BoxError> {
+    tokio::task::spawn_blocking(move || renderer.render(&snapshot.view())).await?
+}
+
+pub async fn encode_blocking(image: Arc<RgbaImage>, settings: EncoderSettings, prefix: &'static str) -> Result<AttachmentType<'static>, BoxError> {
+    let attachment = tokio::task::spawn_blocking(move || {
+        image_attachment(&DynamicImage::ImageRgba8(image.as_ref().clone()), &settings, prefix)
+    })
+    .await??;
+    Ok(attachment)
+}
+
+pub struct ImageBoardRenderer {
+    pub renderer: Arc<Renderer>,
+    pub encoder: EncoderSettings,
+}
+
+impl BoardRenderer for ImageBoardRenderer {
+    fn render(&self, board: &BoardView<'_>) -> Result<RenderedBoard, BoxError> {
+        let image = self.renderer.render_board(
+            board.image,
+            board.grid_size,
+            board.tile_positions,
+            board.options,
+            board.highlights,
+            board.theme,
+        );
+        let image = DynamicImage::ImageRgba8(image);
+        let alt_text = board::alt_text(board.grid_size, board.tile_positions, board.options);
+        Ok(RenderedBoard::Image(image_attachment(&image, &self.encoder, "scrambled")?, alt_text))
+    }
+}
+
+pub struct TextBoardRenderer {
+    pub emoji: bool,
+}
+
+const KEYCAPS: [&str; 10] = ["1️⃣", "2️⃣", "3️⃣", "4️⃣", "5️⃣", "6️⃣", "7️⃣", "8️⃣", "9️⃣", "🔟"];
+
+impl TextBoardRenderer {
+    // Each cell shows the number of the tile it holds; the puzzle is solved when they read 1, 2, 3, ...
+    fn emoji_grid(&self, board: &BoardView<'_>) -> String {
+        let size = board.grid_size as usize;
+        let mut text = String::new();
+        for (row, slots) in board.tile_positions.chunks(size).enumerate() {
+            for (column, &tile) in slots.iter().enumerate() {
+                let slot = row * size + column;
+                if board.options.correctness_overlay && tile == slot {
+                    text.push('✅');
+                } else {
+                    text.push_str(KEYCAPS[tile]);
+                }
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    fn number_grid(&self, board: &BoardView<'_>) -> String {
+        let size = board.grid_size as usize;
+        let width = format!("{}", board.tile_positions.len()).len();
+        let coordinates = board.options.notation == Notation::Coordinates;
+        let mut text = String::from("```\n");
+        if coordinates {
+            text.push_str("   ");
+            for column in 0..board.grid_size {
+                text.push_str(&format!(" {:>width$} ", column_name(column), width = width));
+            }
+            text.push('\n');
+        }
+        for (row, slots) in board.tile_positions.chunks(size).enumerate() {
+            if coordinates {
+                text.push_str(&format!("{:>2} ", row + 1));
+            }
+            for (column, &tile) in slots.iter().enumerate() {
+                let slot = row * size + column;
+                let (open, close) = if board.options.correctness_overlay && tile == slot {
+                    ('(', ')')
+                } else {
+                    ('[', ']')
+                };
+                text.push_str(&format!("{}{:>width$}{}", open, tile + 1, close, width = width));
+            }
+            text.push('\n');
+        }
+        text.push_str("```");
+        text
+    }
+}
+
+impl BoardRenderer for TextBoardRenderer {
+    fn render(&self, board: &BoardView<'_>) -> Result<RenderedBoard, BoxError> {
+        let fits_emoji = board.tile_positions.len() <= KEYCAPS.len();
+        let mut text = if self.emoji && fits_emoji {
+            self.emoji_grid(board)
+        } else {
+            self.number_grid(board)
+        };
+        text.push_str(&format!(
+            "\nSlots are named {} from the top left. Swap them to put the tiles back in order.",
+            board::tile_range(board.grid_size, board.options.notation)
+        ));
+        if board.options.correctness_overlay {
+            text.push_str(" Tiles in the right place are marked.");
+        }
+        Ok(RenderedBoard::Text(text))
+    }
+}
+
+pub fn board_renderer(display: BoardDisplay, renderer: Arc<Renderer>, encoder: EncoderSettings) -> Arc<dyn BoardRenderer> {
+    match display {
+        BoardDisplay::Image => Arc::new(ImageBoardRenderer { renderer, encoder }),
+        BoardDisplay::Text => Arc::new(TextBoardRenderer { emoji: false }),
+        BoardDisplay::Emoji => Arc::new(TextBoardRenderer { emoji: true }),
+    }
+}
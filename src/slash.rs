@@ -0,0 +1,306 @@
+use serenity::builder::{CreateApplicationCommands, CreateEmbed};
+use serenity::model::application::command::{Command, CommandOptionType};
+use serenity::model::application::interaction::application_command::{ApplicationCommandInteraction, CommandDataOption};
+use serenity::model::application::interaction::autocomplete::AutocompleteInteraction;
+use serenity::model::application::interaction::InteractionResponseType;
+use serenity::model::id::GuildId;
+use serenity::prelude::*;
+
+use crate::actions::{self, ActionResult, Invoker};
+use crate::tutorial::{self, TutorialMode};
+use crate::{guild_config, help, BoxError, ManifestKey};
+
+// Discord shows at most 25 autocomplete suggestions
+const MAX_SUGGESTIONS: usize = 25;
+
+// Register the slash commands. With a development guild they are registered there and update
+// instantly; otherwise they are registered globally, which can take up to an hour to show up.
+pub async fn register(ctx: &Context, dev_guild: Option<GuildId>) -> Result<(), BoxError> {
+    match dev_guild {
+        Some(guild_id) => {
+            guild_id.set_application_commands(&ctx.http, define_commands).await?;
+            println!("Registered slash commands in guild {}", guild_id);
+        }
+        None => {
+            Command::set_global_application_commands(&ctx.http, define_commands).await?;
+            println!("Registered global slash commands");
+        }
+    }
+    Ok(())
+}
+
+fn define_commands(commands: &mut CreateApplicationCommands) -> &mut CreateApplicationCommands {
+    commands
+        .create_application_command(|command| {
+            command
+                .name("puzzle")
+                .description("Play the picture puzzle")
+                .dm_permission(false)
+                .create_option(|option| {
+                    option
+                        .name("start")
+                        .description("Start a new puzzle")
+                        .kind(CommandOptionType::SubCommand)
+                        .create_sub_option(|category| {
+                            category
+                                .name("category")
+                                .description("Category or tag to pick a puzzle from")
+                                .kind(CommandOptionType::String)
+                                .set_autocomplete(true)
+                        })
+                })
+                .create_option(|option| {
+                    option
+                        .name("swap")
+                        .description("Swap two tiles")
+                        .kind(CommandOptionType::SubCommand)
+                        .create_sub_option(|tile| {
+                            tile.name("tile1")
+                                .description("First tile, e.g. 3 or C1")
+                                .kind(CommandOptionType::String)
+                                .required(true)
+                        })
+                        .create_sub_option(|tile| {
+                            tile.name("tile2")
+                                .description("Second tile, e.g. 4 or A2")
+                                .kind(CommandOptionType::String)
+                                .required(true)
+                        })
+                })
+                .create_option(|option| {
+                    option
+                        .name("guess")
+                        .description("Guess what the picture shows")
+                        .kind(CommandOptionType::SubCommand)
+                        .create_sub_option(|answer| {
+                            answer
+                                .name("answer")
+                                .description("Your guess")
+                                .kind(CommandOptionType::String)
+                                .required(true)
+                        })
+                })
+                .create_option(|option| {
+                    option
+                        .name("hint")
+                        .description("Suggest a swap that puts a tile in place")
+                        .kind(CommandOptionType::SubCommand)
+                })
+                .create_option(|option| {
+                    option
+                        .name("giveup")
+                        .description("End the puzzle and reveal the answer")
+                        .kind(CommandOptionType::SubCommand)
+                })
+        })
+        .create_application_command(|command| {
+            command
+                .name("leaderboard")
+                .description("Show the top puzzle solvers")
+                .dm_permission(false)
+        })
+        .create_application_command(|command| {
+            command
+                .name("help")
+                .description("List the bot's commands, or show how to use one")
+                .create_option(|option| {
+                    option
+                        .name("command")
+                        .description("Command to explain")
+                        .kind(CommandOptionType::String)
+                        // There are more commands than the 25 choices Discord allows
+                        .set_autocomplete(true)
+                })
+        })
+        .create_application_command(|command| {
+            command
+                .name("tutorial")
+                .description("Learn to play on a small practice puzzle")
+                .create_option(|option| {
+                    option
+                        .name("mode")
+                        .description("Game mode to learn")
+                        .kind(CommandOptionType::String);
+                    for name in TutorialMode::NAMES {
+                        option.add_string_choice(name, name);
+                    }
+                    option
+                })
+        })
+}
+
+// String value of a named option
+fn string_option<'a>(options: &'a [CommandDataOption], name: &str) -> Option<&'a str> {
+    options
+        .iter()
+        .find(|option| option.name == name)
+        .and_then(|option| option.value.as_ref())
+        .and_then(|value| value.as_str())
+}
+
+async fn run_subcommand(ctx: &Context, invoker: &Invoker, subcommand: &CommandDataOption) -> ActionResult {
+    let options = &subcommand.options;
+    match subcommand.name.as_str() {
+        "start" => actions::start(ctx, invoker, string_option(options, "category")).await,
+        "swap" => {
+            let tile1 = string_option(options, "tile1").unwrap_or_default();
+            let tile2 = string_option(options, "tile2").unwrap_or_default();
+            actions::swap(ctx, invoker, tile1, tile2).await
+        }
+        "guess" => actions::guess(ctx, invoker, string_option(options, "answer").unwrap_or_default()).await,
+        "hint" => actions::hint(ctx, invoker).await,
+        "giveup" => actions::give_up(ctx, invoker).await,
+        _ => Ok(None),
+    }
+}
+
+// Private confirmation for actions that only posted to the channel
+fn acknowledgement(subcommand: &str) -> &'static str {
+    match subcommand {
+        "start" => "Puzzle started.",
+        "swap" => "Tiles swapped.",
+        "guess" => "Correct!",
+        "giveup" => "Puzzle revealed.",
+        _ => "Done.",
+    }
+}
+
+pub async fn handle_command(ctx: &Context, command: &ApplicationCommandInteraction) {
+    // Help and tutorials are answered straight away, only to the invoker
+    match command.data.name.as_str() {
+        "help" => return respond_help(ctx, command).await,
+        "tutorial" => return respond_tutorial(ctx, command).await,
+        _ => {}
+    }
+
+    let invoker = Invoker {
+        channel_id: command.channel_id,
+        guild_id: command.guild_id,
+        user_id: command.user.id,
+    };
+
+    // Loading and rendering can take longer than the three seconds Discord allows, so defer first.
+    // Feedback is only shown to the invoker; the leaderboard is for everyone.
+    let public = command.data.name == "leaderboard";
+    let deferred = if public {
+        command.defer(&ctx.http).await
+    } else {
+        command.defer_ephemeral(&ctx.http).await
+    };
+    if let Err(e) = deferred {
+        eprintln!("Failed to defer /{}: {:?}", command.data.name, e);
+        return;
+    }
+
+    let (result, subcommand) = match command.data.name.as_str() {
+        "leaderboard" => (actions::leaderboard(ctx).await, ""),
+        "puzzle" => match command.data.options.first() {
+            Some(subcommand) => (run_subcommand(ctx, &invoker, subcommand).await, subcommand.name.as_str()),
+            None => (Ok(None), ""),
+        },
+        _ => (Ok(None), ""),
+    };
+
+    let content = match result {
+        Ok(Some(text)) => text,
+        Ok(None) => acknowledgement(subcommand).to_string(),
+        Err(e) => {
+            eprintln!("/{} {} failed: {:?}", command.data.name, subcommand, e);
+            "Something went wrong. Please try again.".to_string()
+        }
+    };
+    if let Err(e) = command
+        .edit_original_interaction_response(&ctx.http, |response| {
+            response.content(content).allowed_mentions(|mentions| mentions.empty_parse())
+        })
+        .await
+    {
+        eprintln!("Failed to respond to /{}: {:?}", command.data.name, e);
+    }
+}
+
+// Suggest categories and tags, or help topics, matching what the user has typed so far
+pub async fn handle_autocomplete(ctx: &Context, autocomplete: &AutocompleteInteraction) {
+    let options = &autocomplete.data.options;
+    let typed = options
+        .iter()
+        .chain(options.iter().flat_map(|subcommand| subcommand.options.iter()))
+        .find(|option| option.focused)
+        .and_then(|option| option.value.as_ref())
+        .and_then(|value| value.as_str())
+        .unwrap_or_default()
+        .to_lowercase();
+
+    let suggestions: Vec<String> = if autocomplete.data.name == "help" {
+        help::command_names()
+            .filter(|name| name.contains(&typed))
+            .take(MAX_SUGGESTIONS)
+            .map(str::to_string)
+            .collect()
+    } else {
+        let manifest = ctx.data.read().await.get::<ManifestKey>().unwrap().clone();
+        manifest
+            .categories()
+            .into_iter()
+            .filter(|name| name.contains(&typed))
+            .take(MAX_SUGGESTIONS)
+            .collect()
+    };
+
+    if let Err(e) = autocomplete
+        .create_autocomplete_response(&ctx.http, |response| {
+            for name in &suggestions {
+                response.add_string_choice(name, name);
+            }
+            response
+        })
+        .await
+    {
+        eprintln!("Failed to send autocomplete suggestions: {:?}", e);
+    }
+}
+
+async fn respond_help(ctx: &Context, command: &ApplicationCommandInteraction) {
+    let config = guild_config(ctx, command.guild_id).await;
+    let mut embed = CreateEmbed::default();
+    let result = help::help_embed(&mut embed, &config, string_option(&command.data.options, "command"));
+    if let Err(e) = command
+        .create_interaction_response(&ctx.http, |response| {
+            response
+                .kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|message| {
+                    match result {
+                        Ok(()) => message.add_embed(embed),
+                        Err(text) => message.content(text),
+                    };
+                    message
+                        .ephemeral(true)
+                        .components(|c| c.add_action_row(tutorial::start_button(TutorialMode::Classic)))
+                })
+        })
+        .await
+    {
+        eprintln!("Failed to respond to /help: {:?}", e);
+    }
+}
+
+async fn respond_tutorial(ctx: &Context, command: &ApplicationCommandInteraction) {
+    let mode = string_option(&command.data.options, "mode")
+        .unwrap_or_default()
+        .parse()
+        .unwrap_or(TutorialMode::Classic);
+    let prefix = guild_config(ctx, command.guild_id).await.prefix;
+    let (text, rows) = tutorial::opening(mode, &prefix);
+    if let Err(e) = command
+        .create_interaction_response(&ctx.http, |response| {
+            response
+                .kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|message| {
+                    message.content(text).ephemeral(true).components(|c| c.set_action_rows(rows))
+                })
+        })
+        .await
+    {
+        eprintln!("Failed to respond to /tutorial: {:?}", e);
+    }
+}
@@ -0,0 +1,755 @@
+use image::RgbaImage;
+use serde::{Deserialize, Serialize};
+use serenity::builder::CreateActionRow;
+use serenity::model::application::component::ButtonStyle;
+use serenity::model::application::interaction::message_component::MessageComponentInteraction;
+use serenity::model::application::interaction::InteractionResponseType;
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::Notify;
+
+use crate::actions::{ActionResult, Invoker, respond_ephemeral};
+use crate::board::{self, BoardDisplay, BoardOptions};
+use crate::components;
+use crate::config::SavedStore;
+use crate::manifest::PuzzleEntry;
+use crate::race::RaceTiming;
+use crate::render::{board_renderer, describe_upload, encode_blocking, render_blocking, BoardSnapshot, RenderedBoard};
+use crate::theme::Theme;
+use crate::{
+    load_image, BoxError, ConfigKey, EncoderKey, LabelStyleKey, ManifestKey, RaceTimingKey, RendererKey, StatsKey,
+    TeamBattleKey, TeamStatsKey,
+};
+
+// Join buttons are "battle:join:<team>". Tile controls on a team's board are scoped
+// "battle:<battle id>:<team>:", so a click finds its battle and team.
+const BATTLE_PREFIX: &str = "battle:";
+const JOIN_PREFIX: &str = "battle:join:";
+const MIN_TEAMS: usize = 2;
+// One Join button per team, all on one row
+const MAX_TEAMS: usize = 5;
+const DEFAULT_TEAMS: [&str; 2] = ["Red", "Blue"];
+
+// A server's results for one team name, kept apart from players' own scores
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct TeamRecord {
+    pub name: String,
+    pub wins: usize,
+    pub battles: usize,
+}
+
+// Every server's team results by lowercased team name, saved to a JSON file by `keep_saved`
+// after each battle. Battles in direct messages aren't counted.
+pub struct TeamStats {
+    path: PathBuf,
+    guilds: HashMap<GuildId, HashMap<String, TeamRecord>>,
+    changed: Arc<Notify>,
+}
+
+impl TeamStats {
+    // Load the results, starting empty if the file does not exist yet
+    pub fn load(path: PathBuf) -> Result<Self, String> {
+        let guilds = match std::fs::read_to_string(&path) {
+            Ok(json) => serde_json::from_str(&json).map_err(|e| format!("{}: {}", path.display(), e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(format!("{}: {}", path.display(), e)),
+        };
+        Ok(TeamStats {
+            path,
+            guilds,
+            changed: Arc::new(Notify::new()),
+        })
+    }
+
+    // Count a battle between `teams`. `winner` is the index of the team that won, if any.
+    fn record(&mut self, guild_id: GuildId, teams: &[&str], winner: Option<usize>) {
+        let records = self.guilds.entry(guild_id).or_default();
+        for (index, &name) in teams.iter().enumerate() {
+            let record = records.entry(name.to_lowercase()).or_insert_with(|| TeamRecord {
+                name: name.to_string(),
+                ..TeamRecord::default()
+            });
+            record.battles += 1;
+            if winner == Some(index) {
+                record.wins += 1;
+            }
+        }
+        self.changed.notify_one();
+    }
+}
+
+impl SavedStore for TeamStats {
+    const NAME: &'static str = "team results";
+
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn changes(&self) -> &Arc<Notify> {
+        &self.changed
+    }
+
+    fn to_json(&self) -> Result<String, String> {
+        serde_json::to_string_pretty(&self.guilds).map_err(|e| e.to_string())
+    }
+}
+
+// Teams are either picked by pressing Join, or made of everyone with a Discord role
+struct Team {
+    name: String,
+    role: Option<RoleId>,
+    members: Vec<UserId>,
+    board: Arc<Mutex<TeamBoard>>,
+}
+
+impl Team {
+    fn includes(&self, component: &MessageComponentInteraction) -> bool {
+        match self.role {
+            Some(role) => component.member.as_ref().is_some_and(|member| member.roles.contains(&role)),
+            None => self.members.contains(&component.user.id),
+        }
+    }
+
+    fn roster(&self) -> String {
+        match self.role {
+            Some(role) => format!("everyone with {}", role.mention()),
+            None if self.members.is_empty() => "nobody yet".to_string(),
+            None => {
+                let members: Vec<_> = self.members.iter().map(|user_id| user_id.mention().to_string()).collect();
+                members.join(", ")
+            }
+        }
+    }
+}
+
+struct TeamBoard {
+    tiles: Vec<usize>,
+    selected: Option<(UserId, usize)>,
+    last_swap: Vec<usize>,
+    moves: usize,
+    message: Option<MessageId>,
+    solved: bool,
+}
+
+// One battle per channel. Every team moves on its own board, all starting from the same scramble.
+pub struct TeamBattle {
+    id: u64, // Also the scramble's seed
+    guild_id: Option<GuildId>,
+    puzzle: PuzzleEntry,
+    image: Arc<RgbaImage>,
+    grid_size: u32,
+    theme: Theme,
+    options: BoardOptions,
+    teams: Vec<Team>,
+    started_at: Option<Instant>, // None while players can still join
+}
+
+impl TeamBattle {
+    fn scope(&self, team: usize) -> String {
+        format!("{}{}:{}:", BATTLE_PREFIX, self.id, team)
+    }
+
+    fn rosters(&self) -> String {
+        self.teams
+            .iter()
+            .map(|team| format!("**{}**: {}", team.name, team.roster()))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn join_buttons(&self) -> CreateActionRow {
+        let mut row = CreateActionRow::default();
+        for (index, team) in self.teams.iter().enumerate().filter(|(_, team)| team.role.is_none()) {
+            row.create_button(|button| {
+                button
+                    .label(format!("Join {}", team.name))
+                    .custom_id(format!("{}{}", JOIN_PREFIX, index))
+                    .style(ButtonStyle::Primary)
+            });
+        }
+        row
+    }
+}
+
+impl TeamBoard {
+    fn controls(&self, battle: &TeamBattle, team: usize, disabled: bool) -> Vec<CreateActionRow> {
+        let selected = self.selected.map(|(_, slot)| slot);
+        components::tile_controls(battle.grid_size, &self.tiles, &battle.options, selected, disabled, &battle.scope(team))
+    }
+
+    fn caption(&self, team_name: &str, status: &str, board: &RenderedBoard) -> String {
+        let mut caption = format!(
+            "**Team {}**: {}\nMoves: {}\nSolved: {}%",
+            team_name,
+            status,
+            self.moves,
+            board::percent_solved(&self.tiles)
+        );
+        match board {
+            RenderedBoard::Image(..) => {}
+            RenderedBoard::Text(grid) => caption.push_str(&format!("\n{}", grid)),
+        }
+        caption
+    }
+}
+
+// Split `!battle` arguments into teams: role mentions become role teams, anything else names a
+// team players join with a button. With no arguments there are two teams, Red and Blue.
+fn parse_teams(ctx: &Context, args: &[&str]) -> Result<Vec<(String, Option<RoleId>)>, String> {
+    let mut teams: Vec<(String, Option<RoleId>)> = Vec::new();
+    let args: Vec<&str> = if args.is_empty() { DEFAULT_TEAMS.to_vec() } else { args.to_vec() };
+    for arg in args {
+        let team = match arg.parse::<RoleId>() {
+            Ok(role_id) if arg.starts_with("<@&") => {
+                let name = role_id
+                    .to_role_cached(&ctx.cache)
+                    .map_or_else(|| format!("Team {}", teams.len() + 1), |role| role.name);
+                (name, Some(role_id))
+            }
+            _ => (arg.to_string(), None),
+        };
+        if teams.iter().any(|(name, _)| name.eq_ignore_ascii_case(&team.0)) {
+            return Err(format!("There are two teams called \"{}\".", team.0));
+        }
+        teams.push(team);
+    }
+    if !(MIN_TEAMS..=MAX_TEAMS).contains(&teams.len()) {
+        return Err(format!("A battle needs {} to {} teams.", MIN_TEAMS, MAX_TEAMS));
+    }
+    Ok(teams)
+}
+
+// Set up a battle between the named or role-mapped teams. Named teams get a join window first.
+pub async fn start(ctx: &Context, invoker: &Invoker, team_args: &[&str]) -> ActionResult {
+    let teams = match parse_teams(ctx, team_args) {
+        Ok(teams) => teams,
+        Err(e) => return Ok(Some(e)),
+    };
+    if invoker.guild_id.is_none() && teams.iter().any(|(_, role)| role.is_some()) {
+        return Ok(Some("Teams can only be made from roles in a server.".to_string()));
+    }
+
+    let (nsfw_channel, can_attach) = match invoker.channel_id.to_channel(ctx).await? {
+        Channel::Guild(channel) => {
+            let can_attach = channel
+                .permissions_for_user(&ctx.cache, ctx.cache.current_user_id())
+                .map_or(true, |permissions| permissions.attach_files());
+            (channel.nsfw, can_attach)
+        }
+        _ => (false, true),
+    };
+    let (manifest, config, theme, timing) = {
+        let data = ctx.data.read().await;
+        let config = data.get::<ConfigKey>().unwrap().get(invoker.guild_id);
+        let theme = config.theme.resolve(data.get::<LabelStyleKey>().unwrap());
+        (
+            data.get::<ManifestKey>().unwrap().clone(),
+            config,
+            theme,
+            *data.get::<RaceTimingKey>().unwrap(),
+        )
+    };
+    if !config.allows_channel(invoker.channel_id) {
+        return Ok(Some("Puzzles can't be played in this channel.".to_string()));
+    }
+    if ctx.data.read().await.get::<TeamBattleKey>().unwrap().contains_key(&invoker.channel_id) {
+        return Ok(Some("A team battle is already running in this channel.".to_string()));
+    }
+
+    let puzzle = match manifest.pick(nsfw_channel, None) {
+        Some(puzzle) => puzzle,
+        None => return Ok(Some("There are no puzzles available for this channel.".to_string())),
+    };
+    let image = match load_image(puzzle).await {
+        Ok(image) => image,
+        Err(e) => {
+            eprintln!("Failed to load puzzle \"{}\": {:?}", puzzle.title, e);
+            return Ok(Some("Failed to load the puzzle image. Please try again.".to_string()));
+        }
+    };
+
+    // Every team's board uses the server's mode; image boards need permission to attach files
+    let mut options = BoardOptions {
+        display: config.mode.unwrap_or(BoardDisplay::Image),
+        ..BoardOptions::default()
+    };
+    if !can_attach && options.display == BoardDisplay::Image {
+        options.display = BoardDisplay::Text;
+    }
+    let id: u64 = rand::random();
+    let grid_size = config.grid_size.unwrap_or(puzzle.grid_size);
+    let tiles = board::seeded_positions((grid_size * grid_size) as usize, id);
+    let battle = TeamBattle {
+        id,
+        guild_id: invoker.guild_id,
+        puzzle: puzzle.clone(),
+        image,
+        grid_size,
+        theme,
+        options,
+        teams: teams
+            .into_iter()
+            .map(|(name, role)| Team {
+                name,
+                role,
+                members: Vec::new(),
+                board: Arc::new(Mutex::new(TeamBoard {
+                    tiles: tiles.clone(),
+                    selected: None,
+                    last_swap: Vec::new(),
+                    moves: 0,
+                    message: None,
+                    solved: false,
+                })),
+            })
+            .collect(),
+        started_at: None,
+    };
+    let needs_joining = battle.teams.iter().any(|team| team.role.is_none());
+    let (text, buttons) = (
+        format!(
+            "**Team battle!** {}Every team gets the same scrambled picture on its own board, and the first team \
+             to put it back together wins.\n{}",
+            if needs_joining {
+                format!("Pick a team within {} seconds. ", timing.join_window.as_secs())
+            } else {
+                String::new()
+            },
+            battle.rosters()
+        ),
+        battle.join_buttons(),
+    );
+    let battle = Arc::new(Mutex::new(battle));
+    {
+        let mut data = ctx.data.write().await;
+        let battles = data.get_mut::<TeamBattleKey>().unwrap();
+        // Someone may have started one while the image loaded
+        if battles.contains_key(&invoker.channel_id) {
+            return Ok(Some("A team battle is already running in this channel.".to_string()));
+        }
+        battles.insert(invoker.channel_id, battle.clone());
+    }
+
+    let announcement = invoker
+        .channel_id
+        .send_message(&ctx.http, |m| {
+            m.content(text).allowed_mentions(|mentions| mentions.empty_parse());
+            if needs_joining {
+                m.components(|c| c.add_action_row(buttons));
+            }
+            m
+        })
+        .await;
+    let announcement = match announcement {
+        Ok(message) => message,
+        Err(e) => {
+            ctx.data.write().await.get_mut::<TeamBattleKey>().unwrap().remove(&invoker.channel_id);
+            return Err(e.into());
+        }
+    };
+
+    if needs_joining {
+        tokio::time::sleep(timing.join_window).await;
+    }
+    begin(ctx, invoker.channel_id, battle, announcement.id, timing).await?;
+    Ok(None)
+}
+
+// Close joining and post every team's board, or call the battle off if too few teams have players
+async fn begin(
+    ctx: &Context,
+    channel_id: ChannelId,
+    battle: Arc<Mutex<TeamBattle>>,
+    announcement: MessageId,
+    timing: RaceTiming,
+) -> Result<(), BoxError> {
+    let (renderer, encoder) = {
+        let data = ctx.data.read().await;
+        (data.get::<RendererKey>().unwrap().clone(), *data.get::<EncoderKey>().unwrap())
+    };
+
+    let mut battle_guard = battle.lock().await;
+    battle_guard.teams.retain(|team| team.role.is_some() || !team.members.is_empty());
+    if battle_guard.teams.len() < MIN_TEAMS {
+        drop(battle_guard);
+        ctx.data.write().await.get_mut::<TeamBattleKey>().unwrap().remove(&channel_id);
+        channel_id
+            .edit_message(&ctx.http, announcement, |m| {
+                m.content(format!("Not enough teams had players, so the battle was called off. It takes at least {}.", MIN_TEAMS))
+                    .components(|c| c)
+            })
+            .await?;
+        return Ok(());
+    }
+    battle_guard.started_at = Some(Instant::now());
+
+    let text = format!("**Team battle!** The battle is on.\n{}", battle_guard.rosters());
+    if let Err(e) = channel_id
+        .edit_message(&ctx.http, announcement, |m| {
+            m.content(text).allowed_mentions(|mentions| mentions.empty_parse()).components(|c| c)
+        })
+        .await
+    {
+        eprintln!("Failed to close team battle announcement: {:?}", e);
+    }
+
+    let board_renderer = board_renderer(battle_guard.options.display, renderer, encoder);
+    for (index, team) in battle_guard.teams.iter().enumerate() {
+        let mut board = team.board.lock().await;
+        let snapshot = BoardSnapshot {
+            image: battle_guard.image.clone(),
+            grid_size: battle_guard.grid_size,
+            tile_positions: board.tiles.clone(),
+            options: battle_guard.options,
+            highlights: Vec::new(),
+            theme: battle_guard.theme.clone(),
+        };
+        let rendered = render_blocking(board_renderer.clone(), snapshot).await?;
+        let content = board.caption(&team.name, "only this team can move here.", &rendered);
+        let rows = board.controls(&battle_guard, index, false);
+        let message = channel_id
+            .send_message(&ctx.http, |m| {
+                m.content(content).allowed_mentions(|mentions| mentions.empty_parse());
+                if let RenderedBoard::Image(attachment, alt_text) = rendered {
+                    m.add_file(attachment);
+                    describe_upload(&mut m.0, &alt_text);
+                }
+                m.components(|c| c.set_action_rows(rows))
+            })
+            .await?;
+        board.message = Some(message.id);
+    }
+    let battle_id = battle_guard.id;
+    drop(battle_guard);
+
+    let ctx = ctx.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(timing.time_limit).await;
+        finish(&ctx, channel_id, battle_id, None).await;
+    });
+    Ok(())
+}
+
+pub fn is_battle_button(custom_id: &str) -> bool {
+    custom_id.starts_with(BATTLE_PREFIX)
+}
+
+// A Join button on the announcement, or a tile on a team's board
+pub async fn handle_component(ctx: &Context, component: &MessageComponentInteraction) {
+    let custom_id = component.data.custom_id.as_str();
+    if let Some(team) = custom_id.strip_prefix(JOIN_PREFIX).and_then(|team| team.parse().ok()) {
+        return handle_join(ctx, component, team).await;
+    }
+    let parsed = custom_id.strip_prefix(BATTLE_PREFIX).and_then(|rest| {
+        let (battle_id, rest) = rest.split_once(':')?;
+        let (team, control) = rest.split_once(':')?;
+        let slot = components::clicked_tile(control, &component.data.values)?;
+        Some((battle_id.parse::<u64>().ok()?, team.parse::<usize>().ok()?, slot))
+    });
+    if let Some((battle_id, team, slot)) = parsed {
+        handle_tile_click(ctx, component, battle_id, team, slot).await;
+    }
+}
+
+// Put the player on the team whose button they pressed, moving them off any other
+async fn handle_join(ctx: &Context, component: &MessageComponentInteraction, team: usize) {
+    let battle = ctx.data.read().await.get::<TeamBattleKey>().unwrap().get(&component.channel_id).cloned();
+    let battle = match battle {
+        Some(battle) => battle,
+        None => return respond_ephemeral(ctx, component, "This battle is over.").await,
+    };
+    let mut battle = battle.lock().await;
+    if battle.started_at.is_some() {
+        drop(battle);
+        return respond_ephemeral(ctx, component, "This battle has already started.").await;
+    }
+    if team >= battle.teams.len() {
+        drop(battle);
+        return respond_ephemeral(ctx, component, "That team isn't in this battle.").await;
+    }
+    let user_id = component.user.id;
+    if battle.teams[team].members.contains(&user_id) {
+        let text = format!("You're already on {}.", battle.teams[team].name);
+        drop(battle);
+        return respond_ephemeral(ctx, component, &text).await;
+    }
+    for other in battle.teams.iter_mut() {
+        other.members.retain(|&member| member != user_id);
+    }
+    battle.teams[team].members.push(user_id);
+
+    let text = format!(
+        "**Team battle!** Pick a team. Every team gets the same scrambled picture on its own board, and the first \
+         team to put it back together wins.\n{}",
+        battle.rosters()
+    );
+    drop(battle);
+    if let Err(e) = component
+        .create_interaction_response(&ctx.http, |response| {
+            response.kind(InteractionResponseType::UpdateMessage).interaction_response_data(|message| {
+                message.content(text).allowed_mentions(|mentions| mentions.empty_parse())
+            })
+        })
+        .await
+    {
+        eprintln!("Failed to update team rosters: {:?}", e);
+    }
+}
+
+// Pick up a tile, put it back, or swap it with the tile the same player picked up
+async fn handle_tile_click(
+    ctx: &Context,
+    component: &MessageComponentInteraction,
+    battle_id: u64,
+    team: usize,
+    slot: usize,
+) {
+    let battle = ctx.data.read().await.get::<TeamBattleKey>().unwrap().get(&component.channel_id).cloned();
+    let battle = match battle {
+        Some(battle) => battle,
+        None => return respond_ephemeral(ctx, component, "This battle is over.").await,
+    };
+    let (board, team_name, snapshot, scope) = {
+        let battle = battle.lock().await;
+        let on_team = battle.teams.get(team).map(|team| team.includes(component));
+        match on_team {
+            _ if battle.id != battle_id || battle.started_at.is_none() => {
+                drop(battle);
+                return respond_ephemeral(ctx, component, "This battle is over.").await;
+            }
+            Some(true) => {}
+            _ => {
+                let text = format!("Only Team {} can move on this board.", battle.teams.get(team).map_or("", |team| team.name.as_str()));
+                drop(battle);
+                return respond_ephemeral(ctx, component, &text).await;
+            }
+        }
+        (
+            battle.teams[team].board.clone(),
+            battle.teams[team].name.clone(),
+            BoardSnapshot {
+                image: battle.image.clone(),
+                grid_size: battle.grid_size,
+                tile_positions: Vec::new(),
+                options: battle.options,
+                highlights: Vec::new(),
+                theme: battle.theme.clone(),
+            },
+            battle.scope(team),
+        )
+    };
+    let (grid_size, options) = (snapshot.grid_size, snapshot.options);
+    let controls = |board: &TeamBoard, disabled: bool| {
+        let selected = board.selected.map(|(_, slot)| slot);
+        components::tile_controls(grid_size, &board.tiles, &options, selected, disabled, &scope)
+    };
+
+    // The board is released before the battle is locked again, so the two are never held together
+    let solved = {
+        let mut board = board.lock().await;
+        if board.solved {
+            drop(board);
+            return respond_ephemeral(ctx, component, "This board is already solved.").await;
+        }
+        if slot >= board.tiles.len() {
+            drop(board);
+            return respond_ephemeral(ctx, component, "That tile isn't on your team's board.").await;
+        }
+
+        let user_id = component.user.id;
+        let first = board
+            .selected
+            .filter(|&(selector, _)| selector == user_id)
+            .map(|(_, first)| first);
+        let first = match first {
+            Some(first) if first != slot => first,
+            first => {
+                board.selected = if first == Some(slot) { None } else { Some((user_id, slot)) };
+                let rows = controls(&board, false);
+                if let Err(e) = component
+                    .create_interaction_response(&ctx.http, |response| {
+                        response
+                            .kind(InteractionResponseType::UpdateMessage)
+                            .interaction_response_data(|message| message.components(|c| c.set_action_rows(rows)))
+                    })
+                    .await
+                {
+                    eprintln!("Failed to update team tile buttons: {:?}", e);
+                }
+                return;
+            }
+        };
+        // Rendering can take longer than the three seconds Discord allows
+        if let Err(e) = component
+            .create_interaction_response(&ctx.http, |response| response.kind(InteractionResponseType::DeferredUpdateMessage))
+            .await
+        {
+            eprintln!("Failed to acknowledge team tile click: {:?}", e);
+        }
+
+        board.tiles.swap(first, slot);
+        board.last_swap = vec![first, slot];
+        board.selected = None;
+        board.moves += 1;
+        board.solved = board.tiles.iter().enumerate().all(|(slot, &tile)| slot == tile);
+
+        let (renderer, encoder) = {
+            let data = ctx.data.read().await;
+            (data.get::<RendererKey>().unwrap().clone(), *data.get::<EncoderKey>().unwrap())
+        };
+        let snapshot = BoardSnapshot {
+            tile_positions: board.tiles.clone(),
+            highlights: board.last_swap.clone(),
+            ..snapshot
+        };
+        let status = if board.solved {
+            "solved!".to_string()
+        } else {
+            format!(
+                "{} swapped {} and {}.",
+                user_id.mention(),
+                board::tile_label(first, grid_size, options.notation),
+                board::tile_label(slot, grid_size, options.notation)
+            )
+        };
+        match render_blocking(board_renderer(options.display, renderer, encoder), snapshot).await {
+            Ok(rendered) => {
+                let content = board.caption(&team_name, &status, &rendered);
+                let rows = controls(&board, board.solved);
+                let result = component
+                    .channel_id
+                    .edit_message(&ctx.http, component.message.id, |m| {
+                        m.content(content)
+                            .allowed_mentions(|mentions| mentions.empty_parse())
+                            .components(|c| c.set_action_rows(rows))
+                            .remove_all_attachments();
+                        if let RenderedBoard::Image(attachment, alt_text) = rendered {
+                            m.attachment(attachment);
+                            describe_upload(&mut m.0, &alt_text);
+                        }
+                        m
+                    })
+                    .await;
+                if let Err(e) = result {
+                    eprintln!("Failed to update team board: {:?}", e);
+                }
+            }
+            Err(e) => eprintln!("Failed to render team board: {:?}", e),
+        }
+        board.solved
+    };
+
+    if solved {
+        finish(ctx, component.channel_id, battle_id, Some(team)).await;
+    }
+}
+
+// End the battle if it is still running: record the result for every team, reveal the picture
+// and lock the boards. `winner` is None when time ran out.
+async fn finish(ctx: &Context, channel_id: ChannelId, battle_id: u64, winner: Option<usize>) {
+    let battle_handle = match ctx.data.read().await.get::<TeamBattleKey>().unwrap().get(&channel_id).cloned() {
+        Some(battle) => battle,
+        None => return,
+    };
+    let battle = battle_handle.lock().await;
+    // A new battle may have replaced this one, or it may already have been won
+    {
+        let mut data = ctx.data.write().await;
+        let battles = data.get_mut::<TeamBattleKey>().unwrap();
+        let current = battles.get(&channel_id).is_some_and(|current| Arc::ptr_eq(current, &battle_handle));
+        if battle.id != battle_id || !current {
+            return;
+        }
+        battles.remove(&channel_id);
+
+        if let Some(guild_id) = battle.guild_id {
+            let names: Vec<&str> = battle.teams.iter().map(|team| team.name.as_str()).collect();
+            data.get_mut::<TeamStatsKey>().unwrap().record(guild_id, &names, winner);
+        }
+        let stats = data.get_mut::<StatsKey>().unwrap();
+        match winner {
+            Some(_) => stats.solved += 1,
+            None => stats.abandoned += 1,
+        }
+    }
+
+    let mut text = match winner {
+        Some(team) => {
+            let moves = battle.teams[team].board.lock().await.moves;
+            format!("**Team {} wins!** They put the picture back together in {} moves.", battle.teams[team].name, moves)
+        }
+        None => "**Time's up!** No team finished the picture.".to_string(),
+    };
+    text.push_str(&format!(
+        "\nThe picture was **{}**: **{}**\n{}",
+        battle.puzzle.answer, battle.puzzle.title, battle.puzzle.attribution
+    ));
+    let encoder = *ctx.data.read().await.get::<EncoderKey>().unwrap();
+    let attachment = match encode_blocking(battle.image.clone(), encoder, "original").await {
+        Ok(attachment) => Some(attachment),
+        Err(e) => {
+            eprintln!("Failed to encode battle picture: {:?}", e);
+            None
+        }
+    };
+    if let Err(e) = channel_id
+        .send_message(&ctx.http, |m| {
+            m.content(text).allowed_mentions(|mentions| mentions.empty_parse());
+            if let Some(attachment) = attachment {
+                m.add_file(attachment);
+            }
+            m
+        })
+        .await
+    {
+        eprintln!("Failed to post battle result: {:?}", e);
+    }
+
+    for (index, team) in battle.teams.iter().enumerate() {
+        let board = team.board.lock().await;
+        if let Some(message_id) = board.message {
+            let rows = board.controls(&battle, index, true);
+            if let Err(e) = channel_id
+                .edit_message(&ctx.http, message_id, |m| m.components(|c| c.set_action_rows(rows)))
+                .await
+            {
+                eprintln!("Failed to disable team board: {:?}", e);
+            }
+        }
+    }
+}
+
+// Teams in this server by wins, then by fewest battles
+pub async fn leaderboard(ctx: &Context, guild_id: Option<GuildId>) -> ActionResult {
+    let guild_id = match guild_id {
+        Some(guild_id) => guild_id,
+        None => return Ok(Some("Team results are kept per server, so use this in a server.".to_string())),
+    };
+    let data = ctx.data.read().await;
+    let mut records: Vec<_> = match data.get::<TeamStatsKey>().unwrap().guilds.get(&guild_id) {
+        Some(records) => records.values().collect(),
+        None => Vec::new(),
+    };
+    if records.is_empty() {
+        return Ok(Some("No team battles have been played here yet. Start one with !battle.".to_string()));
+    }
+    records.sort_by(|a, b| b.wins.cmp(&a.wins).then(a.battles.cmp(&b.battles)).then(a.name.cmp(&b.name)));
+
+    let mut text = String::from("**Team leaderboard**");
+    for (rank, record) in records.iter().take(10).enumerate() {
+        text.push_str(&format!(
+            "\n{}. {}: {} win{} from {} battle{}",
+            rank + 1,
+            record.name,
+            record.wins,
+            if record.wins == 1 { "" } else { "s" },
+            record.battles,
+            if record.battles == 1 { "" } else { "s" }
+        ));
+    }
+    Ok(Some(text))
+}
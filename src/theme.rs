@@ -0,0 +1,269 @@
+use image::Rgba;
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::image_processing::{parse_hex_color, LabelStyle};
+
+// Names accepted by `!theme`, in the order they are listed
+pub const THEME_NAMES: [&str; 5] = ["classic", "dark", "neon", "minimal", "seasonal"];
+
+// Colors and sizes used to draw a board
+#[derive(Debug, Clone)]
+pub struct Theme {
+    // Shown behind the tiles and in the edge label margin
+    pub background: Rgba<u8>,
+    pub border_color: Rgba<u8>,
+    pub border_thickness: u32,
+    pub label: LabelStyle,
+    // Column letters and row numbers, drawn on the background rather than over tiles
+    pub edge_label_color: Rgba<u8>,
+    // Faint text in the corner of every board, e.g. the server name
+    pub watermark: Option<String>,
+}
+
+impl Theme {
+    // Built-in theme by name. Label position and size come from `base_label`,
+    // so operator label settings apply whichever theme a server picks.
+    pub fn builtin(name: &str, base_label: &LabelStyle) -> Option<Theme> {
+        let theme = |background, border_color, border_thickness, text_color, label_background, opacity, edge_label_color| Theme {
+            background,
+            border_color,
+            border_thickness,
+            label: LabelStyle {
+                text_color,
+                background_color: label_background,
+                opacity,
+                ..base_label.clone()
+            },
+            edge_label_color,
+            watermark: None,
+        };
+
+        let theme = match name {
+            "classic" => Theme {
+                background: Rgba([32, 32, 32, 255]),
+                border_color: Rgba([0, 0, 0, 255]),
+                border_thickness: 5,
+                label: base_label.clone(),
+                edge_label_color: base_label.text_color,
+                watermark: None,
+            },
+            "dark" => theme(
+                Rgba([18, 18, 18, 255]),
+                Rgba([48, 48, 48, 255]),
+                4,
+                Rgba([230, 230, 230, 255]),
+                Rgba([40, 40, 40, 255]),
+                0.85,
+                Rgba([160, 160, 160, 255]),
+            ),
+            "neon" => theme(
+                Rgba([12, 0, 24, 255]),
+                Rgba([255, 0, 200, 255]),
+                6,
+                Rgba([0, 255, 240, 255]),
+                Rgba([24, 0, 48, 255]),
+                0.9,
+                Rgba([0, 255, 240, 255]),
+            ),
+            "minimal" => theme(
+                Rgba([245, 245, 245, 255]),
+                Rgba([245, 245, 245, 255]),
+                2,
+                Rgba([33, 33, 33, 255]),
+                Rgba([255, 255, 255, 255]),
+                0.7,
+                Rgba([90, 90, 90, 255]),
+            ),
+            "seasonal" => {
+                let (background, border_color, text_color, label_background) = seasonal_colors(current_month());
+                theme(background, border_color, 5, text_color, label_background, 0.9, text_color)
+            }
+            _ => return None,
+        };
+        Some(theme)
+    }
+}
+
+// Palette for the month (1-12): winter ice, spring blossom, summer sun, autumn leaves
+fn seasonal_colors(month: u32) -> (Rgba<u8>, Rgba<u8>, Rgba<u8>, Rgba<u8>) {
+    match month {
+        3..=5 => (Rgba([232, 245, 233, 255]), Rgba([129, 199, 132, 255]), Rgba([255, 255, 255, 255]), Rgba([236, 64, 122, 255])),
+        6..=8 => (Rgba([255, 248, 225, 255]), Rgba([255, 179, 0, 255]), Rgba([33, 33, 33, 255]), Rgba([255, 213, 79, 255])),
+        9..=11 => (Rgba([62, 39, 35, 255]), Rgba([191, 54, 12, 255]), Rgba([255, 224, 178, 255]), Rgba([109, 76, 65, 255])),
+        _ => (Rgba([13, 27, 42, 255]), Rgba([144, 202, 249, 255]), Rgba([227, 242, 253, 255]), Rgba([21, 101, 192, 255])),
+    }
+}
+
+// Current month (1-12) in UTC, from the civil-from-days algorithm
+fn current_month() -> u32 {
+    let days = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() / 86_400)
+        .unwrap_or_default() as i64;
+    let z = days + 719_468;
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    (if month < 10 { month + 3 } else { month - 9 }) as u32
+}
+
+// Colors a server has changed on top of its chosen theme, saved as hex strings
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ThemeOverrides {
+    #[serde(with = "hex_color")]
+    pub background: Option<Rgba<u8>>,
+    #[serde(with = "hex_color")]
+    pub border_color: Option<Rgba<u8>>,
+    pub border_thickness: Option<u32>,
+    #[serde(with = "hex_color")]
+    pub label_color: Option<Rgba<u8>>,
+    #[serde(with = "hex_color")]
+    pub label_background: Option<Rgba<u8>>,
+}
+
+mod hex_color {
+    use image::Rgba;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    use crate::image_processing::parse_hex_color;
+
+    pub fn serialize<S: Serializer>(color: &Option<Rgba<u8>>, serializer: S) -> Result<S::Ok, S::Error> {
+        match color {
+            Some(Rgba([r, g, b, a])) => serializer.serialize_str(&format!("#{:02x}{:02x}{:02x}{:02x}", r, g, b, a)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Rgba<u8>>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|hex| parse_hex_color(&hex).map_err(serde::de::Error::custom))
+            .transpose()
+    }
+}
+
+impl ThemeOverrides {
+    fn is_empty(&self) -> bool {
+        self.background.is_none()
+            && self.border_color.is_none()
+            && self.border_thickness.is_none()
+            && self.label_color.is_none()
+            && self.label_background.is_none()
+    }
+}
+
+// A server's theme choice, applied to every puzzle started there
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GuildTheme {
+    pub theme: String,
+    pub overrides: ThemeOverrides,
+    pub watermark: Option<String>,
+}
+
+impl Default for GuildTheme {
+    fn default() -> Self {
+        GuildTheme {
+            theme: "classic".to_string(),
+            overrides: ThemeOverrides::default(),
+            watermark: None,
+        }
+    }
+}
+
+impl GuildTheme {
+    pub fn resolve(&self, base_label: &LabelStyle) -> Theme {
+        let mut theme = Theme::builtin(&self.theme, base_label)
+            .or_else(|| Theme::builtin("classic", base_label))
+            .expect("classic theme is built in");
+        let overrides = &self.overrides;
+        if let Some(color) = overrides.background {
+            theme.background = color;
+        }
+        if let Some(color) = overrides.border_color {
+            theme.border_color = color;
+        }
+        if let Some(thickness) = overrides.border_thickness {
+            theme.border_thickness = thickness;
+        }
+        if let Some(color) = overrides.label_color {
+            theme.label.text_color = color;
+            theme.edge_label_color = color;
+        }
+        if let Some(color) = overrides.label_background {
+            theme.label.background_color = color;
+        }
+        theme.watermark = self.watermark.clone();
+        theme
+    }
+
+    // Change one property, e.g. `border #ff00ff` or `thickness 3`
+    pub fn customise(&mut self, property: &str, value: &str) -> Result<(), String> {
+        let overrides = &mut self.overrides;
+        match property {
+            "background" => overrides.background = Some(parse_hex_color(value)?),
+            "border" => overrides.border_color = Some(parse_hex_color(value)?),
+            "thickness" => {
+                let thickness = value
+                    .parse()
+                    .ok()
+                    .filter(|thickness| (0..=20).contains(thickness))
+                    .ok_or_else(|| format!("invalid thickness \"{}\" (use 0-20)", value))?;
+                overrides.border_thickness = Some(thickness);
+            }
+            "label" => overrides.label_color = Some(parse_hex_color(value)?),
+            "label_background" | "label-background" => overrides.label_background = Some(parse_hex_color(value)?),
+            _ => {
+                return Err(format!(
+                    "unknown property \"{}\" (use background, border, thickness, label or label-background)",
+                    property
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    // Apply a `!theme` setting: a theme name, `custom <property> <value>` or `watermark <text|off>`
+    pub fn change(&mut self, setting: &str, rest: &str) -> Result<(), String> {
+        match setting {
+            "custom" => {
+                let (property, value) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+                self.customise(&property.to_lowercase(), value.trim())
+            }
+            "watermark" => match rest {
+                "" => Err("usage: !theme watermark <text|off>".to_string()),
+                "off" | "none" => {
+                    self.watermark = None;
+                    Ok(())
+                }
+                _ => {
+                    self.watermark = Some(rest.chars().take(40).collect());
+                    Ok(())
+                }
+            },
+            name if THEME_NAMES.contains(&name) => {
+                *self = GuildTheme {
+                    theme: name.to_string(),
+                    watermark: self.watermark.take(),
+                    ..GuildTheme::default()
+                };
+                Ok(())
+            }
+            _ => Err(format!("unknown theme \"{}\" (use {})", setting, THEME_NAMES.join(", "))),
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        let mut text = format!("Theme: {}", self.theme);
+        if !self.overrides.is_empty() {
+            text.push_str(" (customised)");
+        }
+        text.push_str(&format!(
+            "\nWatermark: {}",
+            self.watermark.as_deref().unwrap_or("none")
+        ));
+        text
+    }
+}
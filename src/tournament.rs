@@ -0,0 +1,1027 @@
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use serenity::builder::CreateActionRow;
+use serenity::model::application::component::ButtonStyle;
+use serenity::model::application::interaction::message_component::MessageComponentInteraction;
+use serenity::model::application::interaction::InteractionResponseType;
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::Notify;
+
+use crate::actions::{ActionResult, Invoker, is_moderator, respond_ephemeral};
+use crate::config::SavedStore;
+use crate::image_processing::{BracketMatch, BracketSection};
+use crate::race;
+use crate::render::encode_blocking;
+use crate::{BoxError, ConfigKey, EncoderKey, RatingsKey, RendererKey, TournamentKey, TournamentSignupKey, TournamentStatsKey};
+
+// Sign-up buttons are "tournament:register:<id>" and "tournament:withdraw:<id>"
+const TOURNAMENT_PREFIX: &str = "tournament:";
+const REGISTER_PREFIX: &str = "tournament:register:";
+const WITHDRAW_PREFIX: &str = "tournament:withdraw:";
+const MIN_ENTRANTS: usize = 2;
+// Keeps the bracket image a readable size
+const MAX_ENTRANTS: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Single,
+    Double,
+}
+
+impl Format {
+    fn name(self) -> &'static str {
+        match self {
+            Format::Single => "single-elimination",
+            Format::Double => "double-elimination",
+        }
+    }
+}
+
+// Who plays in one side of a match
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Slot {
+    Pending, // Waiting on an earlier match
+    Player(UserId),
+    Bye, // Nobody will come, so whoever is on the other side goes through
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Section {
+    Winners,
+    Losers,
+    GrandFinal,
+}
+
+// One head-to-head race in the bracket
+struct Match {
+    section: Section,
+    round: usize, // From 1 within its section
+    position: usize,
+    slots: [Slot; 2],
+    winner_to: Option<(usize, usize)>, // Match and slot the winner moves on to
+    loser_to: Option<(usize, usize)>,  // Where the loser drops to, or None if they are out
+    // How far a player knocked out here got. Later stages place higher.
+    stage: usize,
+    winner: Option<usize>,
+    running: bool,
+}
+
+impl Match {
+    fn new(section: Section, round: usize, position: usize, stage: usize) -> Self {
+        Match {
+            section,
+            round,
+            position,
+            slots: [Slot::Pending; 2],
+            winner_to: None,
+            loser_to: None,
+            stage,
+            winner: None,
+            running: false,
+        }
+    }
+}
+
+// Lay out a bracket for the players in seed order, filling it up to a power of two with byes.
+// Seeds are placed so the top seeds meet as late as possible. In double elimination, losers drop
+// into a losers bracket whose winner meets the winners bracket's winner in a grand final.
+fn build_bracket(players: &[UserId], format: Format) -> Vec<Match> {
+    let size = players.len().next_power_of_two().max(2);
+    let rounds = size.trailing_zeros() as usize;
+    let mut matches = Vec::new();
+
+    let mut winners: Vec<Vec<usize>> = Vec::new();
+    for round in 1..=rounds {
+        let ids = (0..size >> round)
+            .map(|position| {
+                matches.push(Match::new(Section::Winners, round, position, round));
+                matches.len() - 1
+            })
+            .collect();
+        winners.push(ids);
+    }
+    let mut seeds = vec![1];
+    while seeds.len() < size {
+        let count = seeds.len() * 2;
+        seeds = seeds.iter().flat_map(|&seed| [seed, count + 1 - seed]).collect();
+    }
+    for (index, &seed) in seeds.iter().enumerate() {
+        matches[winners[0][index / 2]].slots[index % 2] = players.get(seed - 1).map_or(Slot::Bye, |&player| Slot::Player(player));
+    }
+    for round in 1..rounds {
+        for (index, &id) in winners[round - 1].iter().enumerate() {
+            matches[id].winner_to = Some((winners[round][index / 2], index % 2));
+        }
+    }
+    if format == Format::Single {
+        return matches;
+    }
+
+    // Losers rounds alternate: odd rounds play off the losers among themselves, even rounds bring
+    // in the players just knocked down from the winners bracket
+    let losers_rounds = 2 * (rounds - 1);
+    let mut losers: Vec<Vec<usize>> = Vec::new();
+    for round in 1..=losers_rounds {
+        let ids = (0..size >> (round.div_ceil(2) + 1))
+            .map(|position| {
+                matches.push(Match::new(Section::Losers, round, position, round));
+                matches.len() - 1
+            })
+            .collect();
+        losers.push(ids);
+    }
+    matches.push(Match::new(Section::GrandFinal, 1, 0, losers_rounds + 1));
+    let grand_final = matches.len() - 1;
+
+    matches[winners[rounds - 1][0]].winner_to = Some((grand_final, 0));
+    for (index, &id) in winners[0].iter().enumerate() {
+        matches[id].loser_to = Some(if rounds == 1 { (grand_final, 1) } else { (losers[0][index / 2], index % 2) });
+    }
+    for round in 2..=rounds {
+        let count = winners[round - 1].len();
+        for (index, &id) in winners[round - 1].iter().enumerate() {
+            // Reversed, so players dropping down don't meet someone they just played
+            matches[id].loser_to = Some((losers[2 * (round - 1) - 1][count - 1 - index], 1));
+        }
+    }
+    for round in 1..=losers_rounds {
+        for (index, &id) in losers[round - 1].iter().enumerate() {
+            matches[id].winner_to = Some(if round == losers_rounds {
+                (grand_final, 1)
+            } else if round % 2 == 1 {
+                (losers[round][index], 0)
+            } else {
+                (losers[round][index / 2], index % 2)
+            });
+        }
+    }
+    matches
+}
+
+// One tournament per channel: a sign-up period, then a bracket of head-to-head races
+pub struct Tournament {
+    id: u64,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    creator: UserId,
+    format: Format,
+    filter: Option<String>,
+    nsfw_channel: bool,
+    entrants: Vec<UserId>, // In seed order once the bracket is drawn
+    names: HashMap<UserId, String>, // Shown on the bracket image
+    matches: Vec<Match>,
+    signup: Option<MessageId>,
+    bracket: Option<MessageId>,
+    started: bool,
+    knocked_out: Vec<(UserId, usize)>, // Player and the stage they went out at
+}
+
+impl Tournament {
+    fn signup_text(&self, seconds: u64) -> String {
+        let entrants: Vec<_> = self.entrants.iter().map(|user_id| user_id.mention().to_string()).collect();
+        format!(
+            "**Tournament!** {} is running a {} tournament. Press Register within {} seconds to enter. \
+             Every match is a head-to-head race on the same scramble, with boards in your direct messages.\n\
+             Registered ({}/{}): {}",
+            self.creator.mention(),
+            self.format.name(),
+            seconds,
+            self.entrants.len(),
+            MAX_ENTRANTS,
+            if entrants.is_empty() { "nobody yet".to_string() } else { entrants.join(", ") }
+        )
+    }
+
+    fn signup_buttons(&self) -> CreateActionRow {
+        let mut row = CreateActionRow::default();
+        row.create_button(|button| {
+            button
+                .label("Register")
+                .custom_id(format!("{}{}", REGISTER_PREFIX, self.id))
+                .style(ButtonStyle::Success)
+        });
+        row.create_button(|button| {
+            button
+                .label("Withdraw")
+                .custom_id(format!("{}{}", WITHDRAW_PREFIX, self.id))
+                .style(ButtonStyle::Secondary)
+        });
+        row
+    }
+
+    fn rounds_in(&self, section: Section) -> usize {
+        self.matches.iter().filter(|m| m.section == section).map(|m| m.round).max().unwrap_or(0)
+    }
+
+    fn match_title(&self, index: usize) -> String {
+        let m = &self.matches[index];
+        let last = self.rounds_in(m.section);
+        match (self.format, m.section) {
+            (_, Section::GrandFinal) => "Grand final".to_string(),
+            (Format::Single, _) => match last - m.round {
+                0 => "Final".to_string(),
+                1 => "Semifinal".to_string(),
+                2 => "Quarterfinal".to_string(),
+                _ => format!("Round {}", m.round),
+            },
+            (Format::Double, Section::Winners) if m.round == last => "Winners final".to_string(),
+            (Format::Double, Section::Winners) => format!("Winners round {}", m.round),
+            (Format::Double, Section::Losers) if m.round == last => "Losers final".to_string(),
+            (Format::Double, Section::Losers) => format!("Losers round {}", m.round),
+        }
+    }
+
+    // Put a result in place: the winner moves on, the loser drops down or is out
+    fn record(&mut self, index: usize, winner: usize) {
+        let m = &mut self.matches[index];
+        m.winner = Some(winner);
+        m.running = false;
+        let (won, lost, winner_to, loser_to, stage) = (m.slots[winner], m.slots[1 - winner], m.winner_to, m.loser_to, m.stage);
+        if let Some((next, slot)) = winner_to {
+            self.matches[next].slots[slot] = won;
+        }
+        match (loser_to, lost) {
+            (Some((next, slot)), _) => self.matches[next].slots[slot] = lost,
+            (None, Slot::Player(user_id)) => self.knocked_out.push((user_id, stage)),
+            (None, _) => {}
+        }
+    }
+
+    // Settle every match with a bye in it, since nobody will ever come to play it
+    fn walkovers(&mut self) {
+        while let Some(index) = self.matches.iter().position(|m| {
+            m.winner.is_none() && !m.slots.contains(&Slot::Pending) && m.slots.contains(&Slot::Bye)
+        }) {
+            let winner = if self.matches[index].slots[0] == Slot::Bye { 1 } else { 0 };
+            self.record(index, winner);
+        }
+    }
+
+    // Matches whose players are both known, marked as running
+    fn ready_matches(&mut self) -> Vec<(usize, [UserId; 2])> {
+        let mut ready = Vec::new();
+        for (index, m) in self.matches.iter_mut().enumerate() {
+            if let (None, false, [Slot::Player(first), Slot::Player(second)]) = (m.winner, m.running, m.slots) {
+                m.running = true;
+                ready.push((index, [first, second]));
+            }
+        }
+        ready
+    }
+
+    // The winner of the last match, once it has been played
+    fn champion(&self) -> Option<UserId> {
+        let last = self.matches.iter().find(|m| m.winner_to.is_none())?;
+        match last.slots[last.winner?] {
+            Slot::Player(user_id) => Some(user_id),
+            _ => None,
+        }
+    }
+
+    // Final places, best first. Players knocked out at the same stage share a place.
+    fn placements(&self) -> Vec<(UserId, usize)> {
+        let mut stages = self.knocked_out.clone();
+        if let Some(champion) = self.champion() {
+            stages.push((champion, usize::MAX));
+        }
+        stages.sort_by_key(|&(_, stage)| std::cmp::Reverse(stage));
+        stages
+            .iter()
+            .map(|&(user_id, stage)| (user_id, 1 + stages.iter().filter(|(_, other)| *other > stage).count()))
+            .collect()
+    }
+
+    fn slot_name(&self, slot: Slot) -> String {
+        match slot {
+            Slot::Pending => "TBD".to_string(),
+            Slot::Bye => "bye".to_string(),
+            Slot::Player(user_id) => self.names.get(&user_id).cloned().unwrap_or_else(|| "Player".to_string()),
+        }
+    }
+
+    fn sections(&self) -> Vec<BracketSection> {
+        let titles = match self.format {
+            Format::Single => [(Section::Winners, "Bracket")].as_slice(),
+            Format::Double => [
+                (Section::Winners, "Winners bracket"),
+                (Section::Losers, "Losers bracket"),
+                (Section::GrandFinal, "Grand final"),
+            ]
+            .as_slice(),
+        };
+        titles
+            .iter()
+            .filter(|(section, _)| self.rounds_in(*section) > 0)
+            .map(|&(section, title)| BracketSection {
+                title: title.to_string(),
+                rounds: (1..=self.rounds_in(section))
+                    .map(|round| {
+                        let mut matches: Vec<&Match> =
+                            self.matches.iter().filter(|m| m.section == section && m.round == round).collect();
+                        matches.sort_by_key(|m| m.position);
+                        matches
+                            .into_iter()
+                            .map(|m| BracketMatch {
+                                players: [self.slot_name(m.slots[0]), self.slot_name(m.slots[1])],
+                                winner: m.winner,
+                                live: m.running,
+                                next: m
+                                    .winner_to
+                                    .map(|(next, _)| &self.matches[next])
+                                    .filter(|next| next.section == section)
+                                    .map(|next| next.position),
+                            })
+                            .collect()
+                    })
+                    .collect(),
+            })
+            .collect()
+    }
+
+    fn live_text(&self) -> String {
+        let live: Vec<_> = (0..self.matches.len())
+            .filter(|&index| self.matches[index].running)
+            .filter_map(|index| match self.matches[index].slots {
+                [Slot::Player(first), Slot::Player(second)] => Some(format!(
+                    "\n{}: {} vs {}",
+                    self.match_title(index),
+                    first.mention(),
+                    second.mention()
+                )),
+                _ => None,
+            })
+            .collect();
+        if live.is_empty() {
+            String::new()
+        } else {
+            format!("\nNow playing (boards are in your direct messages):{}", live.concat())
+        }
+    }
+}
+
+// A player's tournament results in one server
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct TournamentRecord {
+    pub played: usize,
+    pub wins: usize,
+    pub podiums: usize, // Finished in the top three
+    pub best: Option<usize>,
+}
+
+// Every server's tournament results, saved to a JSON file by `keep_saved` after each tournament
+pub struct TournamentStats {
+    path: PathBuf,
+    guilds: HashMap<GuildId, HashMap<UserId, TournamentRecord>>,
+    changed: Arc<Notify>,
+}
+
+impl TournamentStats {
+    // Load the results, starting empty if the file does not exist yet
+    pub fn load(path: PathBuf) -> Result<Self, String> {
+        let guilds = match std::fs::read_to_string(&path) {
+            Ok(json) => serde_json::from_str(&json).map_err(|e| format!("{}: {}", path.display(), e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(format!("{}: {}", path.display(), e)),
+        };
+        Ok(TournamentStats {
+            path,
+            guilds,
+            changed: Arc::new(Notify::new()),
+        })
+    }
+
+    fn get(&self, guild_id: GuildId, user_id: UserId) -> Option<&TournamentRecord> {
+        self.guilds.get(&guild_id)?.get(&user_id)
+    }
+
+    fn record(&mut self, guild_id: GuildId, placements: &[(UserId, usize)]) {
+        let records = self.guilds.entry(guild_id).or_default();
+        for &(user_id, place) in placements {
+            let record = records.entry(user_id).or_default();
+            record.played += 1;
+            record.wins += usize::from(place == 1);
+            record.podiums += usize::from(place <= 3);
+            record.best = Some(record.best.map_or(place, |best| best.min(place)));
+        }
+        self.changed.notify_one();
+    }
+}
+
+impl SavedStore for TournamentStats {
+    const NAME: &'static str = "tournament results";
+
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn changes(&self) -> &Arc<Notify> {
+        &self.changed
+    }
+
+    fn to_json(&self) -> Result<String, String> {
+        serde_json::to_string_pretty(&self.guilds).map_err(|e| e.to_string())
+    }
+}
+
+// Open sign-ups for a tournament in the channel, then draw the bracket when they close.
+// Arguments are an optional format, single or double, then an optional category or tag.
+pub async fn create(ctx: &Context, invoker: &Invoker, args: &str) -> ActionResult {
+    let guild_id = match invoker.guild_id {
+        Some(guild_id) => guild_id,
+        None => return Ok(Some("Tournaments can only be run in a server.".to_string())),
+    };
+    let mut words = args.split_whitespace().peekable();
+    let format = match words.peek().map(|word| word.to_lowercase()).as_deref() {
+        Some("single") => {
+            words.next();
+            Format::Single
+        }
+        Some("double") => {
+            words.next();
+            Format::Double
+        }
+        _ => Format::Single,
+    };
+    let filter = words.collect::<Vec<_>>().join(" ");
+
+    let nsfw_channel = match invoker.channel_id.to_channel(ctx).await? {
+        Channel::Guild(channel) => channel.nsfw,
+        _ => false,
+    };
+    let (config, signup_window) = {
+        let data = ctx.data.read().await;
+        (data.get::<ConfigKey>().unwrap().get(invoker.guild_id), *data.get::<TournamentSignupKey>().unwrap())
+    };
+    if !config.allows_channel(invoker.channel_id) {
+        return Ok(Some("Puzzles can't be played in this channel.".to_string()));
+    }
+
+    let tournament = Tournament {
+        id: rand::random(),
+        guild_id,
+        channel_id: invoker.channel_id,
+        creator: invoker.user_id,
+        format,
+        filter: (!filter.is_empty()).then_some(filter),
+        nsfw_channel,
+        entrants: Vec::new(),
+        names: HashMap::new(),
+        matches: Vec::new(),
+        signup: None,
+        bracket: None,
+        started: false,
+        knocked_out: Vec::new(),
+    };
+    let (tournament_id, text, buttons) = (tournament.id, tournament.signup_text(signup_window.as_secs()), tournament.signup_buttons());
+    let tournament = Arc::new(Mutex::new(tournament));
+    {
+        let mut data = ctx.data.write().await;
+        let tournaments = data.get_mut::<TournamentKey>().unwrap();
+        if tournaments.contains_key(&invoker.channel_id) {
+            return Ok(Some("A tournament is already running in this channel.".to_string()));
+        }
+        tournaments.insert(invoker.channel_id, tournament.clone());
+    }
+
+    let message = invoker
+        .channel_id
+        .send_message(&ctx.http, |m| {
+            m.content(text)
+                .allowed_mentions(|mentions| mentions.empty_parse())
+                .components(|c| c.add_action_row(buttons))
+        })
+        .await;
+    match message {
+        Ok(message) => tournament.lock().await.signup = Some(message.id),
+        Err(e) => {
+            ctx.data.write().await.get_mut::<TournamentKey>().unwrap().remove(&invoker.channel_id);
+            return Err(e.into());
+        }
+    }
+
+    tokio::time::sleep(signup_window).await;
+    begin(ctx, invoker.channel_id, tournament_id).await?;
+    Ok(None)
+}
+
+// Close sign-ups early. Only the tournament's creator or a moderator may.
+pub async fn start_now(ctx: &Context, invoker: &Invoker) -> ActionResult {
+    let tournament = match ctx.data.read().await.get::<TournamentKey>().unwrap().get(&invoker.channel_id).cloned() {
+        Some(tournament) => tournament,
+        None => return Ok(Some("There is no tournament in this channel. Create one with !tournament create.".to_string())),
+    };
+    let (tournament_id, creator, started) = {
+        let tournament = tournament.lock().await;
+        (tournament.id, tournament.creator, tournament.started)
+    };
+    if started {
+        return Ok(Some("The tournament has already started.".to_string()));
+    }
+    if creator != invoker.user_id && !is_moderator(ctx, invoker).await {
+        return Ok(Some("Only the tournament's creator or a moderator can start it early.".to_string()));
+    }
+    begin(ctx, invoker.channel_id, tournament_id).await?;
+    Ok(None)
+}
+
+// Close sign-ups and draw the bracket, or call the tournament off if too few registered
+async fn begin(ctx: &Context, channel_id: ChannelId, tournament_id: u64) -> Result<(), BoxError> {
+    let tournament = match ctx.data.read().await.get::<TournamentKey>().unwrap().get(&channel_id).cloned() {
+        Some(tournament) => tournament,
+        None => return Ok(()),
+    };
+    let mut tournament = tournament.lock().await;
+    if tournament.id != tournament_id || tournament.started {
+        return Ok(());
+    }
+    tournament.started = true;
+
+    if tournament.entrants.len() < MIN_ENTRANTS {
+        ctx.data.write().await.get_mut::<TournamentKey>().unwrap().remove(&channel_id);
+        if let Some(message_id) = tournament.signup {
+            channel_id
+                .edit_message(&ctx.http, message_id, |m| {
+                    m.content(format!(
+                        "Not enough players registered, so the tournament was called off. It takes at least {}.",
+                        MIN_ENTRANTS
+                    ))
+                    .components(|c| c)
+                })
+                .await?;
+        }
+        return Ok(());
+    }
+
+    // Seed by rating, best first; players with the same rating are seeded randomly
+    tournament.entrants.shuffle(&mut rand::thread_rng());
+    {
+        let data = ctx.data.read().await;
+        let ratings = data.get::<RatingsKey>().unwrap();
+        tournament
+            .entrants
+            .sort_by(|a, b| ratings.player(*b).value.total_cmp(&ratings.player(*a).value));
+    }
+    tournament.matches = build_bracket(&tournament.entrants, tournament.format);
+    tournament.walkovers();
+    if let Some(message_id) = tournament.signup {
+        let entrants: Vec<_> = tournament.entrants.iter().map(|user_id| user_id.mention().to_string()).collect();
+        let text = format!(
+            "**Tournament!** Registration is closed. {} players: {}",
+            entrants.len(),
+            entrants.join(", ")
+        );
+        if let Err(e) = channel_id
+            .edit_message(&ctx.http, message_id, |m| {
+                m.content(text).allowed_mentions(|mentions| mentions.empty_parse()).components(|c| c)
+            })
+            .await
+        {
+            eprintln!("Failed to close tournament sign-ups: {:?}", e);
+        }
+    }
+
+    let ready = tournament.ready_matches();
+    publish(ctx, &mut tournament, "**Tournament!** The bracket is drawn.").await;
+    launch(ctx, &tournament, ready);
+    Ok(())
+}
+
+// Start a race for every match that is ready. Each runs on its own, and reports back when it ends.
+fn launch(ctx: &Context, tournament: &Tournament, ready: Vec<(usize, [UserId; 2])>) {
+    for (index, players) in ready {
+        let ctx = ctx.clone();
+        let (channel_id, tournament_id) = (tournament.channel_id, tournament.id);
+        let setup = race::MatchSetup {
+            channel_id,
+            guild_id: Some(tournament.guild_id),
+            nsfw_channel: tournament.nsfw_channel,
+            filter: tournament.filter.clone(),
+            racers: players,
+            title: tournament.match_title(index),
+            grid_size: None,
+            match_of: race::MatchOf::Tournament(tournament_id, index),
+        };
+        tokio::spawn(async move {
+            let started = race::start_match(&ctx, setup).await;
+            // Don't hold the bracket up: the higher seed goes through
+            if let Err(e) = started {
+                eprintln!("Failed to start tournament match: {:?}", e);
+                report(&ctx, channel_id, tournament_id, index, vec![players[0]]).await;
+            }
+        });
+    }
+}
+
+// A match race ended with the players ranked best first. Move the bracket on, and finish the
+// tournament once the last match is played.
+pub async fn report(ctx: &Context, channel_id: ChannelId, tournament_id: u64, index: usize, ranking: Vec<UserId>) {
+    let tournament_handle = match ctx.data.read().await.get::<TournamentKey>().unwrap().get(&channel_id).cloned() {
+        Some(tournament) => tournament,
+        None => return,
+    };
+    let mut tournament = tournament_handle.lock().await;
+    // The tournament may have been cancelled, and a new one started in the channel
+    if tournament.id != tournament_id || !tournament.matches.get(index).is_some_and(|m| m.running) {
+        return;
+    }
+    let slots = tournament.matches[index].slots;
+    let winner = ranking
+        .iter()
+        .find_map(|user_id| slots.iter().position(|&slot| slot == Slot::Player(*user_id)))
+        .unwrap_or(0);
+    let status = match (slots[winner], slots[1 - winner]) {
+        (Slot::Player(won), Slot::Player(lost)) => format!(
+            "**Tournament!** {} beat {} in the {}.",
+            won.mention(),
+            lost.mention(),
+            tournament.match_title(index).to_lowercase()
+        ),
+        _ => "**Tournament!**".to_string(),
+    };
+    tournament.record(index, winner);
+    tournament.walkovers();
+
+    if tournament.champion().is_none() {
+        let ready = tournament.ready_matches();
+        publish(ctx, &mut tournament, &status).await;
+        launch(ctx, &tournament, ready);
+        return;
+    }
+
+    {
+        let mut data = ctx.data.write().await;
+        let tournaments = data.get_mut::<TournamentKey>().unwrap();
+        if tournaments.get(&channel_id).is_some_and(|current| Arc::ptr_eq(current, &tournament_handle)) {
+            tournaments.remove(&channel_id);
+        }
+    }
+    let placements = tournament.placements();
+    ctx.data
+        .write()
+        .await
+        .get_mut::<TournamentStatsKey>()
+        .unwrap()
+        .record(tournament.guild_id, &placements);
+
+    let mut text = format!("{}\n**Tournament over!** Final placements:", status);
+    for (user_id, place) in &placements {
+        text.push_str(&format!("\n{} {}", race::ordinal(*place), user_id.mention()));
+    }
+    publish(ctx, &mut tournament, &text).await;
+}
+
+// Draw the bracket and show it under `status`, editing the bracket message if there is one
+async fn publish(ctx: &Context, tournament: &mut Tournament, status: &str) {
+    let (renderer, encoder) = {
+        let data = ctx.data.read().await;
+        (data.get::<RendererKey>().unwrap().clone(), *data.get::<EncoderKey>().unwrap())
+    };
+    let sections = tournament.sections();
+    let image = match tokio::task::spawn_blocking(move || renderer.render_bracket(&sections)).await {
+        Ok(image) => image,
+        Err(e) => {
+            eprintln!("Failed to draw tournament bracket: {:?}", e);
+            return;
+        }
+    };
+    let attachment = match encode_blocking(Arc::new(image), encoder, "bracket").await {
+        Ok(attachment) => attachment,
+        Err(e) => {
+            eprintln!("Failed to encode tournament bracket: {:?}", e);
+            return;
+        }
+    };
+
+    let text = format!("{}{}", status, tournament.live_text());
+    let channel_id = tournament.channel_id;
+    let result = match tournament.bracket {
+        Some(message_id) => channel_id
+            .edit_message(&ctx.http, message_id, |m| {
+                m.content(text)
+                    .allowed_mentions(|mentions| mentions.empty_parse())
+                    .remove_all_attachments()
+                    .attachment(attachment)
+            })
+            .await,
+        None => {
+            channel_id
+                .send_message(&ctx.http, |m| {
+                    m.content(text)
+                        .allowed_mentions(|mentions| mentions.empty_parse())
+                        .add_file(attachment)
+                })
+                .await
+        }
+    };
+    match result {
+        Ok(message) => tournament.bracket = Some(message.id),
+        Err(e) => eprintln!("Failed to post tournament bracket: {:?}", e),
+    }
+}
+
+// Post the bracket again at the bottom of the channel; later updates edit the new copy
+pub async fn show_bracket(ctx: &Context, channel_id: ChannelId) -> ActionResult {
+    let tournament = match ctx.data.read().await.get::<TournamentKey>().unwrap().get(&channel_id).cloned() {
+        Some(tournament) => tournament,
+        None => return Ok(Some("There is no tournament in this channel. Create one with !tournament create.".to_string())),
+    };
+    let mut tournament = tournament.lock().await;
+    if tournament.matches.is_empty() {
+        return Ok(Some("The bracket is drawn when registration closes.".to_string()));
+    }
+    tournament.bracket = None;
+    publish(ctx, &mut tournament, "**Tournament bracket**").await;
+    Ok(None)
+}
+
+// Call the tournament off. Matches already under way finish, but no longer count.
+pub async fn cancel(ctx: &Context, invoker: &Invoker) -> ActionResult {
+    let tournament = match ctx.data.read().await.get::<TournamentKey>().unwrap().get(&invoker.channel_id).cloned() {
+        Some(tournament) => tournament,
+        None => return Ok(Some("There is no tournament in this channel.".to_string())),
+    };
+    let mut tournament_guard = tournament.lock().await;
+    if tournament_guard.creator != invoker.user_id && !is_moderator(ctx, invoker).await {
+        return Ok(Some("Only the tournament's creator or a moderator can cancel it.".to_string()));
+    }
+    {
+        let mut data = ctx.data.write().await;
+        let tournaments = data.get_mut::<TournamentKey>().unwrap();
+        if tournaments.get(&invoker.channel_id).is_some_and(|current| Arc::ptr_eq(current, &tournament)) {
+            tournaments.remove(&invoker.channel_id);
+        }
+    }
+    // Stops the sign-up timer from drawing a bracket
+    tournament_guard.started = true;
+    if let (Some(message_id), true) = (tournament_guard.signup, tournament_guard.matches.is_empty()) {
+        if let Err(e) = invoker
+            .channel_id
+            .edit_message(&ctx.http, message_id, |m| m.content("**Tournament!** Cancelled.").components(|c| c))
+            .await
+        {
+            eprintln!("Failed to close tournament sign-ups: {:?}", e);
+        }
+    }
+    Ok(Some("The tournament was cancelled.".to_string()))
+}
+
+// A player's tournament results in this server
+pub async fn stats(ctx: &Context, guild_id: Option<GuildId>, user_id: UserId) -> ActionResult {
+    let guild_id = match guild_id {
+        Some(guild_id) => guild_id,
+        None => return Ok(Some("Tournaments can only be run in a server.".to_string())),
+    };
+    let data = ctx.data.read().await;
+    let record = match data.get::<TournamentStatsKey>().unwrap().get(guild_id, user_id) {
+        Some(record) => record,
+        None => return Ok(Some(format!("{} hasn't played in a tournament here yet.", user_id.mention()))),
+    };
+    Ok(Some(format!(
+        "**Tournament record for {}**\nPlayed: {}\nWon: {}\nTop three finishes: {}\nBest finish: {}",
+        user_id.mention(),
+        record.played,
+        record.wins,
+        record.podiums,
+        record.best.map_or("none".to_string(), race::ordinal)
+    )))
+}
+
+pub fn is_tournament_button(custom_id: &str) -> bool {
+    custom_id.starts_with(TOURNAMENT_PREFIX)
+}
+
+// Register or Withdraw on the sign-up message
+pub async fn handle_component(ctx: &Context, component: &MessageComponentInteraction) {
+    let custom_id = component.data.custom_id.as_str();
+    let (tournament_id, registering) = match (
+        custom_id.strip_prefix(REGISTER_PREFIX).and_then(|id| id.parse::<u64>().ok()),
+        custom_id.strip_prefix(WITHDRAW_PREFIX).and_then(|id| id.parse::<u64>().ok()),
+    ) {
+        (Some(id), _) => (id, true),
+        (_, Some(id)) => (id, false),
+        _ => return,
+    };
+    let (tournament, signup_window) = {
+        let data = ctx.data.read().await;
+        (
+            data.get::<TournamentKey>().unwrap().get(&component.channel_id).cloned(),
+            *data.get::<TournamentSignupKey>().unwrap(),
+        )
+    };
+    let tournament = match tournament {
+        Some(tournament) => tournament,
+        None => return respond_ephemeral(ctx, component, "This tournament is over.").await,
+    };
+    let user_id = component.user.id;
+
+    if registering {
+        {
+            let tournament = tournament.lock().await;
+            if tournament.id != tournament_id || tournament.started {
+                return respond_ephemeral(ctx, component, "Registration for this tournament is closed.").await;
+            }
+            if tournament.entrants.contains(&user_id) {
+                return respond_ephemeral(ctx, component, "You're already registered.").await;
+            }
+        }
+        // Sending the DM can be slow, so it happens without holding the tournament
+        let welcome = user_id.create_dm_channel(ctx).await;
+        let welcome = match welcome {
+            Ok(dm) => dm
+                .say(
+                    &ctx.http,
+                    format!(
+                        "You're registered for the tournament in {}! Your match boards will appear here.",
+                        component.channel_id.mention()
+                    ),
+                )
+                .await
+                .map(|_| ()),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = welcome {
+            eprintln!("Failed to DM tournament entrant: {:?}", e);
+            return respond_ephemeral(
+                ctx,
+                component,
+                "I couldn't send you a direct message, which is where match boards go. \
+                 Allow direct messages from this server's members, then press Register again.",
+            )
+            .await;
+        }
+    }
+
+    let text = {
+        let mut tournament = tournament.lock().await;
+        if tournament.id != tournament_id || tournament.started {
+            drop(tournament);
+            return respond_ephemeral(ctx, component, "Registration for this tournament is closed.").await;
+        }
+        if registering {
+            if tournament.entrants.len() >= MAX_ENTRANTS {
+                drop(tournament);
+                return respond_ephemeral(ctx, component, "This tournament is full.").await;
+            }
+            if !tournament.entrants.contains(&user_id) {
+                tournament.entrants.push(user_id);
+                let name = component.member.as_ref().map_or_else(|| component.user.name.clone(), |member| member.display_name().into_owned());
+                tournament.names.insert(user_id, name);
+            }
+        } else if tournament.entrants.contains(&user_id) {
+            tournament.entrants.retain(|&entrant| entrant != user_id);
+        } else {
+            drop(tournament);
+            return respond_ephemeral(ctx, component, "You aren't registered.").await;
+        }
+        tournament.signup_text(signup_window.as_secs())
+    };
+    if let Err(e) = component
+        .create_interaction_response(&ctx.http, |response| {
+            response.kind(InteractionResponseType::UpdateMessage).interaction_response_data(|message| {
+                message.content(text).allowed_mentions(|mentions| mentions.empty_parse())
+            })
+        })
+        .await
+    {
+        eprintln!("Failed to update tournament sign-ups: {:?}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZES: [usize; 4] = [2, 3, 5, 8];
+    const FORMATS: [Format; 2] = [Format::Single, Format::Double];
+
+    fn players(count: usize) -> Vec<UserId> {
+        (1..=count as u64).map(UserId).collect()
+    }
+
+    fn tournament(count: usize, format: Format) -> Tournament {
+        let entrants = players(count);
+        Tournament {
+            id: 0,
+            guild_id: GuildId(1),
+            channel_id: ChannelId(1),
+            creator: UserId(1),
+            format,
+            filter: None,
+            nsfw_channel: false,
+            matches: build_bracket(&entrants, format),
+            entrants,
+            names: HashMap::new(),
+            signup: None,
+            bracket: None,
+            started: true,
+            knocked_out: Vec::new(),
+        }
+    }
+
+    // Play every match until the bracket is done, with `pick` choosing which slot wins
+    fn play_out(tournament: &mut Tournament, pick: impl Fn(usize) -> usize) {
+        loop {
+            tournament.walkovers();
+            let ready = tournament.ready_matches();
+            if ready.is_empty() {
+                break;
+            }
+            for (index, _) in ready {
+                tournament.record(index, pick(index));
+            }
+        }
+    }
+
+    #[test]
+    fn first_round_never_pairs_two_byes() {
+        for count in SIZES {
+            for format in FORMATS {
+                let matches = build_bracket(&players(count), format);
+                for m in matches.iter().filter(|m| m.section == Section::Winners && m.round == 1) {
+                    assert_ne!(m.slots, [Slot::Bye, Slot::Bye], "{} entrants, {:?}", count, format);
+                    assert!(!m.slots.contains(&Slot::Pending));
+                }
+                let seeded: Vec<_> = matches
+                    .iter()
+                    .flat_map(|m| m.slots)
+                    .filter_map(|slot| match slot {
+                        Slot::Player(user_id) => Some(user_id),
+                        _ => None,
+                    })
+                    .collect();
+                assert_eq!(seeded.len(), count);
+            }
+        }
+    }
+
+    #[test]
+    fn every_match_is_reachable() {
+        for count in SIZES {
+            for format in FORMATS {
+                let matches = build_bracket(&players(count), format);
+                let mut feeds = vec![0; matches.len()];
+                for (next, _) in matches.iter().flat_map(|m| m.winner_to.into_iter().chain(m.loser_to)) {
+                    feeds[next] += 1;
+                }
+                for (index, m) in matches.iter().enumerate() {
+                    let expected = if m.section == Section::Winners && m.round == 1 { 0 } else { 2 };
+                    assert_eq!(feeds[index], expected, "{} entrants, {:?}, match {}", count, format, index);
+                }
+                assert_eq!(matches.iter().filter(|m| m.winner_to.is_none()).count(), 1);
+            }
+        }
+    }
+
+    #[test]
+    fn brackets_play_out_to_a_champion() {
+        for count in SIZES {
+            for format in FORMATS {
+                for pick in [|_| 0, |_| 1, |index: usize| index % 2] {
+                    let mut tournament = tournament(count, format);
+                    play_out(&mut tournament, pick);
+                    assert!(tournament.matches.iter().all(|m| m.winner.is_some()), "{} entrants, {:?}", count, format);
+                    assert!(tournament.champion().is_some());
+
+                    let placements = tournament.placements();
+                    let mut placed: Vec<_> = placements.iter().map(|&(user_id, _)| user_id).collect();
+                    placed.sort();
+                    assert_eq!(placed, players(count));
+                    assert_eq!(placements.iter().filter(|&&(_, place)| place == 1).count(), 1);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn single_elimination_losers_share_places_by_round() {
+        let mut tournament = tournament(8, Format::Single);
+        play_out(&mut tournament, |_| 0);
+        let mut placements = tournament.placements();
+        placements.sort();
+        let places: Vec<_> = placements.iter().map(|&(_, place)| place).collect();
+        assert_eq!(places, [1, 2, 3, 3, 5, 5, 5, 5]);
+    }
+
+    #[test]
+    fn byes_do_not_take_places() {
+        let mut tournament = tournament(5, Format::Single);
+        play_out(&mut tournament, |_| 0);
+        let mut placements = tournament.placements();
+        placements.sort();
+        let places: Vec<_> = placements.iter().map(|&(_, place)| place).collect();
+        assert_eq!(places, [1, 2, 3, 3, 5]);
+    }
+
+    #[test]
+    fn double_elimination_losers_share_places_by_round() {
+        let mut tournament = tournament(8, Format::Double);
+        play_out(&mut tournament, |_| 0);
+        let mut places: Vec<_> = tournament.placements().iter().map(|&(_, place)| place).collect();
+        places.sort();
+        assert_eq!(places, [1, 2, 3, 4, 5, 5, 7, 7]);
+    }
+}
@@ -0,0 +1,294 @@
+use serenity::builder::{CreateActionRow, CreateButton};
+use serenity::model::application::component::ButtonStyle;
+use serenity::model::application::interaction::message_component::MessageComponentInteraction;
+use serenity::model::application::interaction::InteractionResponseType;
+use serenity::prelude::*;
+
+use crate::board;
+use crate::guild_config;
+
+// Tutorials run entirely in a message only the learner can see. The board state lives in each
+// button's id ("tutorial:<mode>:<tiles>:<selected>:<moves>:<slot>"), so nothing is stored.
+const TUTORIAL_PREFIX: &str = "tutorial:";
+const GRID_SIZE: u32 = 2;
+// Needs three swaps, so the learner sees tiles turn green one by one
+const SCRAMBLE: [usize; 4] = [2, 0, 3, 1];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TutorialMode {
+    Classic,
+    Race,
+    Coop,
+    Battle,
+    Duel,
+    ChannelPlays,
+}
+
+impl TutorialMode {
+    pub const NAMES: [&'static str; 6] = ["classic", "race", "coop", "battle", "duel", "channel"];
+
+    fn name(self) -> &'static str {
+        match self {
+            TutorialMode::Classic => "classic",
+            TutorialMode::Race => "race",
+            TutorialMode::Coop => "coop",
+            TutorialMode::Battle => "battle",
+            TutorialMode::Duel => "duel",
+            TutorialMode::ChannelPlays => "channel",
+        }
+    }
+
+    fn title(self) -> &'static str {
+        match self {
+            TutorialMode::Classic => "**Tutorial: classic puzzle**",
+            TutorialMode::Race => "**Tutorial: racing**",
+            TutorialMode::Coop => "**Tutorial: co-op**",
+            TutorialMode::Battle => "**Tutorial: team battles**",
+            TutorialMode::Duel => "**Tutorial: duels**",
+            TutorialMode::ChannelPlays => "**Tutorial: channel plays**",
+        }
+    }
+
+    // Shown once the practice board is solved, with the commands for playing for real
+    fn wrap_up(self, prefix: &str) -> String {
+        match self {
+            TutorialMode::Classic => format!(
+                "In a real game the tiles are pieces of a picture and everyone in the channel plays the same board. \
+                 You can also list several swaps at once with the Swap Tiles button, ask for a `{p}hint`, or press Guess \
+                 to name the picture for points. Start one with `{p}start_picture_puzzle`.",
+                p = prefix
+            ),
+            TutorialMode::Race => format!(
+                "In a race every player gets this same scramble as their own board in their direct messages, \
+                 and a standings message in the channel shows how far along everyone is. The first to finish wins \
+                 the most points. Start one with `{p}race` and have others press Join.",
+                p = prefix
+            ),
+            TutorialMode::Coop => format!(
+                "In a co-op game everyone who presses Join shares one board and takes turns, one swap each. \
+                 A player who doesn't move in time is skipped, and the points are shared by how many tiles each \
+                 of you put in place. Start one with `{p}coop`.",
+                p = prefix
+            ),
+            TutorialMode::Battle => format!(
+                "In a team battle every team gets this same scramble on its own board in the channel, and anyone \
+                 on a team can swap its tiles. The first team to finish wins, and the server keeps a team \
+                 leaderboard. Start one with `{p}battle` and pick a team, or `{p}battle @Red @Blue` to make teams from roles.",
+                p = prefix
+            ),
+            TutorialMode::Duel => format!(
+                "In a duel you challenge one player, who has a short while to press Accept. Then you both get this \
+                 same scramble in your direct messages and the first to finish wins. You can stake coins that the \
+                 winner takes, and `{p}duel_record @player` shows how your duels have gone. Start one with `{p}duel @player`.",
+                p = prefix
+            ),
+            TutorialMode::ChannelPlays => format!(
+                "In channel plays the whole channel shares one board. Clicking two tiles, or `{p}vote 1 2`, votes for \
+                 a swap, and when the voting window closes the most popular swap is made. Points are shared by how \
+                 often each player voted for the swaps that were made. Start one with `{p}channel_plays`.",
+                p = prefix
+            ),
+        }
+    }
+}
+
+impl std::str::FromStr for TutorialMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "" | "classic" => Ok(TutorialMode::Classic),
+            "race" | "racing" => Ok(TutorialMode::Race),
+            "coop" | "co-op" => Ok(TutorialMode::Coop),
+            "battle" | "team" | "teams" => Ok(TutorialMode::Battle),
+            "duel" | "duels" => Ok(TutorialMode::Duel),
+            "channel" | "channel_plays" | "vote" => Ok(TutorialMode::ChannelPlays),
+            _ => Err(format!("unknown tutorial \"{}\" (use {})", s, TutorialMode::NAMES.join(", "))),
+        }
+    }
+}
+
+// Practice board state carried between clicks
+struct Step {
+    mode: TutorialMode,
+    tiles: Vec<usize>,
+    selected: Option<usize>,
+    moves: usize,
+}
+
+impl Step {
+    fn first(mode: TutorialMode) -> Self {
+        Step {
+            mode,
+            tiles: SCRAMBLE.to_vec(),
+            selected: None,
+            moves: 0,
+        }
+    }
+
+    fn solved(&self) -> bool {
+        self.tiles.iter().enumerate().all(|(slot, &tile)| slot == tile)
+    }
+
+    // Parse a button id back into the state it was drawn from, and the clicked slot
+    fn parse(custom_id: &str) -> Option<(Self, Option<usize>)> {
+        let mut parts = custom_id.strip_prefix(TUTORIAL_PREFIX)?.split(':');
+        let mode = parts.next()?.parse().ok()?;
+        let state = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some("start"), None, None, None) => return Some((Step::first(mode), None)),
+            (Some(tiles), Some(selected), Some(moves), Some(slot)) => (tiles, selected, moves, slot),
+            _ => return None,
+        };
+        let (tiles, selected, moves, slot) = state;
+        let tiles: Vec<usize> = tiles.chars().map(|c| c.to_digit(10).map(|d| d as usize)).collect::<Option<_>>()?;
+        let mut sorted = tiles.clone();
+        sorted.sort_unstable();
+        if sorted != [0, 1, 2, 3] {
+            return None;
+        }
+        let step = Step {
+            mode,
+            tiles,
+            selected: selected.parse().ok(),
+            moves: moves.parse().ok()?,
+        };
+        Some((step, slot.parse().ok().filter(|&slot| slot < SCRAMBLE.len())))
+    }
+
+    // Select the clicked tile, put it back, or swap it with the selected one
+    fn click(&mut self, slot: usize) {
+        match self.selected.take() {
+            Some(first) if first != slot => {
+                self.tiles.swap(first, slot);
+                self.moves += 1;
+            }
+            Some(_) => {}
+            None => self.selected = Some(slot),
+        }
+    }
+
+    fn text(&self, prefix: &str, clicked_same: bool) -> String {
+        let placed = board::correct_slots(&self.tiles).len();
+        let body = if self.solved() {
+            format!(
+                "Solved in {} moves! {}",
+                self.moves,
+                self.mode.wrap_up(prefix)
+            )
+        } else if let Some(slot) = self.selected {
+            format!(
+                "You picked up tile {}. Now click the tile it should swap places with, or click it again to put it down.",
+                self.tiles[slot] + 1
+            )
+        } else if clicked_same {
+            "Tile put back. Click a tile to pick it up.".to_string()
+        } else if self.moves == 0 {
+            "Each button is a place on a 2x2 board, and its number is the tile sitting there. \
+             Put the tiles in order, 1 2 on the top row and 3 4 below. Click a tile to pick it up."
+                .to_string()
+        } else {
+            format!(
+                "Swapped! Green tiles are in their correct place: {} of 4 so far. Click another tile to keep going.",
+                placed
+            )
+        };
+        format!("{}\n{}", self.mode.title(), body)
+    }
+
+    fn buttons(&self) -> Vec<CreateActionRow> {
+        let solved = self.solved();
+        let selected = self.selected.map_or("-".to_string(), |slot| slot.to_string());
+        let tiles: String = self.tiles.iter().map(|tile| tile.to_string()).collect();
+        self.tiles
+            .chunks(GRID_SIZE as usize)
+            .enumerate()
+            .map(|(row_index, row_tiles)| {
+                let mut row = CreateActionRow::default();
+                for (column, &tile) in row_tiles.iter().enumerate() {
+                    let slot = row_index * GRID_SIZE as usize + column;
+                    let style = if self.selected == Some(slot) {
+                        ButtonStyle::Primary
+                    } else if tile == slot {
+                        ButtonStyle::Success
+                    } else {
+                        ButtonStyle::Secondary
+                    };
+                    let mut button = CreateButton::default();
+                    button
+                        .label(tile + 1)
+                        .custom_id(format!(
+                            "{}{}:{}:{}:{}:{}",
+                            TUTORIAL_PREFIX,
+                            self.mode.name(),
+                            tiles,
+                            selected,
+                            self.moves,
+                            slot
+                        ))
+                        .style(style)
+                        .disabled(solved);
+                    row.add_button(button);
+                }
+                row
+            })
+            .collect()
+    }
+}
+
+// A button that opens the tutorial for whoever clicks it
+pub fn start_button(mode: TutorialMode) -> CreateActionRow {
+    let mut row = CreateActionRow::default();
+    row.add_button({
+        let mut button = CreateButton::default();
+        button
+            .label("Start the tutorial")
+            .custom_id(format!("{}{}:start", TUTORIAL_PREFIX, mode.name()))
+            .style(ButtonStyle::Primary);
+        button
+    });
+    row
+}
+
+// The first step of a tutorial, for slash commands to show straight away
+pub fn opening(mode: TutorialMode, prefix: &str) -> (String, Vec<CreateActionRow>) {
+    let step = Step::first(mode);
+    (step.text(prefix, false), step.buttons())
+}
+
+pub fn is_tutorial_button(custom_id: &str) -> bool {
+    custom_id.starts_with(TUTORIAL_PREFIX)
+}
+
+// Start buttons open a new private tutorial; tile buttons advance the one they belong to
+pub async fn handle_click(ctx: &Context, component: &MessageComponentInteraction) {
+    let (mut step, slot) = match Step::parse(&component.data.custom_id) {
+        Some(parsed) => parsed,
+        None => return,
+    };
+    let prefix = guild_config(ctx, component.guild_id).await.prefix;
+
+    let clicked_same = slot.is_some() && step.selected == slot;
+    if let Some(slot) = slot {
+        step.click(slot);
+    }
+    let text = step.text(&prefix, clicked_same);
+    let rows = step.buttons();
+
+    let result = component
+        .create_interaction_response(&ctx.http, |response| {
+            match slot {
+                Some(_) => response.kind(InteractionResponseType::UpdateMessage),
+                None => response.kind(InteractionResponseType::ChannelMessageWithSource),
+            }
+            .interaction_response_data(|message| {
+                message
+                    .content(text)
+                    .ephemeral(true)
+                    .components(|c| c.set_action_rows(rows))
+            })
+        })
+        .await;
+    if let Err(e) = result {
+        eprintln!("Failed to update tutorial: {:?}", e);
+    }
+}